@@ -0,0 +1,31 @@
+#version 150
+
+uniform sampler2D source;
+uniform vec2 texel_size;
+uniform float threshold;
+// Set for the pass reading the scene, which is still gamma encoded and not
+// yet thresholded.
+uniform bool first;
+
+varying vec2 frag_texcoord;
+
+const float GAMMA = 2.2;
+
+vec3 fetch(vec2 offset) {
+    vec3 color = texture2D(source, frag_texcoord + offset * texel_size).rgb;
+    if (first) {
+        color = pow(max(color, vec3(0.0)), vec3(GAMMA));
+        // Keeps only what's above the threshold, without a hard edge.
+        float brightness = max(color.r, max(color.g, color.b));
+        color *= max(brightness - threshold, 0.0) / max(brightness, 1.0e-4);
+    }
+    return color;
+}
+
+// Four bilinear taps between texels average sixteen of them.
+void main() {
+    vec3 color = fetch(vec2(-1.0, -1.0)) + fetch(vec2(1.0, -1.0))
+        + fetch(vec2(-1.0, 1.0)) + fetch(vec2(1.0, 1.0));
+
+    gl_FragColor = vec4(0.25 * color, 1.0);
+}
@@ -0,0 +1,19 @@
+#version 150
+
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+varying vec2 frag_texcoord;
+
+vec3 fetch(float x, float y) {
+    return texture2D(source, frag_texcoord + vec2(x, y) * texel_size).rgb;
+}
+
+// A 3x3 tent over the smaller level, added to what's already in the larger.
+void main() {
+    vec3 color = 4.0 * fetch(0.0, 0.0)
+        + 2.0 * (fetch(-1.0, 0.0) + fetch(1.0, 0.0) + fetch(0.0, -1.0) + fetch(0.0, 1.0))
+        + fetch(-1.0, -1.0) + fetch(1.0, -1.0) + fetch(-1.0, 1.0) + fetch(1.0, 1.0);
+
+    gl_FragColor = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,41 @@
+#version 150
+
+uniform sampler2D source, bloom;
+uniform sampler3D grading_lut;
+uniform float bloom_strength, exposure, vignette_strength;
+// 0 to clamp, 1 for Reinhard and 2 for ACES, as post::ToneMapping.
+uniform int tone_mapping;
+uniform bool grading;
+
+varying vec2 frag_texcoord;
+
+const float GAMMA = 2.2;
+
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec3 color = pow(max(texture2D(source, frag_texcoord).rgb, vec3(0.0)), vec3(GAMMA));
+    color += bloom_strength * texture2D(bloom, frag_texcoord).rgb;
+    color *= exposure;
+
+    if (tone_mapping == 1) {
+        color = color / (1.0 + color);
+    } else if (tone_mapping == 2) {
+        color = aces(color);
+    }
+    color = pow(clamp(color, 0.0, 1.0), vec3(1.0 / GAMMA));
+
+    if (grading) {
+        // Lines texel centres up with the ends of the range.
+        float size = float(textureSize(grading_lut, 0).x);
+        color = texture3D(grading_lut, color * (size - 1.0) / size + 0.5 / size).rgb;
+    }
+
+    float falloff = smoothstep(0.2, 1.0, length(frag_texcoord - 0.5) * 1.41421);
+    color *= 1.0 - vignette_strength * falloff;
+
+    // FXAA wants luma alongside.
+    gl_FragColor = vec4(color, dot(color, vec3(0.299, 0.587, 0.114)));
+}
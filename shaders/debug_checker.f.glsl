@@ -0,0 +1,12 @@
+#version 150
+
+varying vec2 frag_texcoord;
+
+const float CHECKS = 16.0;
+
+// A stand-in for flag.f.glsl showing how each mesh is mapped, unlit, as a
+// checkerboard that shows stretching and seams.
+void main() {
+    vec2 check = floor(CHECKS * frag_texcoord);
+    gl_FragColor = vec4(mod(check.x + check.y, 2.0) == 0.0 ? vec3(0.9) : vec3(0.2), 1.0);
+}
@@ -0,0 +1,12 @@
+#version 150
+
+varying vec3 frag_position;
+
+const float NEAR_DEPTH = 0.5, FAR_DEPTH = 4.0;
+
+// A stand-in for flag.f.glsl showing how far away each mesh is, unlit, from
+// white up close to black in the distance.
+void main() {
+    float depth = clamp((length(frag_position) - NEAR_DEPTH) / (FAR_DEPTH - NEAR_DEPTH), 0.0, 1.0);
+    gl_FragColor = vec4(vec3(1.0 - depth), 1.0);
+}
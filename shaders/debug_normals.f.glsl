@@ -0,0 +1,7 @@
+#version 150
+
+varying vec3 frag_color;
+
+void main() {
+    gl_FragColor = vec4(frag_color, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 150
+
+uniform mat4 p_matrix, mv_matrix;
+uniform float normal_length;
+
+attribute vec3 position, normal;
+// 0 at the vertex and 1 at the tip of its normal.
+attribute float end;
+
+varying vec3 frag_color;
+
+// Each line is drawn from two copies of its vertex, the second moved out
+// along the normal. The normal's x, y and z, from -1 to 1, also colour the
+// line red, green and blue.
+void main() {
+    gl_Position = p_matrix * mv_matrix * vec4(position + end * normal_length * normal, 1.0);
+    frag_color = 0.5 * normal + 0.5;
+}
@@ -0,0 +1,17 @@
+#version 150
+
+// Where the heatmap runs out at either end: squashed, and about to tear.
+uniform float min_strain, tear_strain;
+
+varying float frag_strain;
+
+const vec3 COMPRESSED_COLOR = vec3(0.0, 0.3, 1.0);
+const vec3 RESTING_COLOR = vec3(0.0, 1.0, 0.0);
+const vec3 TEARING_COLOR = vec3(1.0, 0.0, 0.0);
+
+void main() {
+    vec3 color = frag_strain < 1.0
+        ? mix(RESTING_COLOR, COMPRESSED_COLOR, clamp((1.0 - frag_strain) / (1.0 - min_strain), 0.0, 1.0))
+        : mix(RESTING_COLOR, TEARING_COLOR, clamp((frag_strain - 1.0) / (tear_strain - 1.0), 0.0, 1.0));
+    gl_FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 150
+
+uniform mat4 p_matrix, mv_matrix;
+
+attribute vec3 position;
+// Length over rest length.
+attribute float strain;
+
+varying float frag_strain;
+
+void main() {
+    gl_Position = p_matrix * mv_matrix * vec4(position, 1.0);
+    frag_strain = strain;
+}
@@ -0,0 +1,9 @@
+#version 150
+
+varying vec2 frag_texcoord;
+
+// A stand-in for flag.f.glsl showing each mesh's texture coordinates, unlit,
+// as red and green, wrapping where they repeat.
+void main() {
+    gl_FragColor = vec4(fract(frag_texcoord), 0.0, 1.0);
+}
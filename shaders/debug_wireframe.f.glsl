@@ -0,0 +1,7 @@
+#version 150
+
+uniform vec3 color;
+
+void main() {
+    gl_FragColor = vec4(color, 1.0);
+}
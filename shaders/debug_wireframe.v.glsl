@@ -0,0 +1,9 @@
+#version 150
+
+uniform mat4 p_matrix, mv_matrix;
+
+attribute vec3 position;
+
+void main() {
+    gl_Position = p_matrix * mv_matrix * vec4(position, 1.0);
+}
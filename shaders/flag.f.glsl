@@ -1,32 +1,74 @@
 #version 150
 
-uniform mat4 p_matrix, mv_matrix;
-uniform sampler2D texture;
+// mv_matrix, prefiltered_map, environment_max_lod, light_direction,
+// environment_direction and apply_fog come from fog.glsl.
+uniform mat4 p_matrix;
+uniform sampler2D texture, detail_normal_map, roughness_map;
+uniform vec2 detail_scale;
+uniform samplerCube irradiance_map;
 
 varying vec3 frag_position, frag_normal;
+varying vec4 frag_tangent;
 varying vec2 frag_texcoord;
 varying float frag_shininess;
 varying vec4 frag_specular;
 
-const vec3 light_direction = vec3(0.408248, -0.816497, 0.408248);
 const vec4 light_diffuse = vec4(0.8, 0.8, 0.8, 0.0);
-const vec4 light_ambient = vec4(0.2, 0.2, 0.2, 1.0);
+// The sky's irradiance stands in for a flat ambient, scaled to be about as
+// bright overhead as the 0.2 it replaces.
+const float ambient_scale = 0.5;
 const vec4 light_specular = vec4(1.0, 1.0, 1.0, 1.0);
 
+// Lighting here is in display terms, so fog is applied after decoding.
+const float GAMMA = 2.2;
+
+// Tilts the normal by the detail normal map, whose x and y run along the
+// tangent and bitangent.
+vec3 detail_normal(vec3 normal, vec2 detail_texcoord) {
+    vec3 tangent = normalize(frag_tangent.xyz - normal * dot(frag_tangent.xyz, normal)),
+         bitangent = frag_tangent.w * cross(normal, tangent),
+         n = texture2D(detail_normal_map, detail_texcoord).xyz * 2.0 - 1.0;
+
+    return normalize(n.x * tangent + n.y * bitangent + n.z * normal);
+}
+
 void main() {
+    vec3 normal = normalize(frag_normal);
+    float shininess = frag_shininess;
+    vec4 specular = frag_specular;
+
+    // Meshes without tangents, like the rope, go without.
+    if (detail_scale.x > 0.0 && dot(frag_tangent.xyz, frag_tangent.xyz) > 0.0) {
+        vec2 detail_texcoord = frag_texcoord * detail_scale;
+        float gloss = 1.0 - texture2D(roughness_map, detail_texcoord).r;
+        normal = detail_normal(normal, detail_texcoord);
+        shininess *= gloss;
+        specular *= gloss;
+    }
+
     vec3 mv_light_direction = (mv_matrix * vec4(light_direction, 0.0)).xyz,
-         normal = normalize(frag_normal),
          eye = normalize(frag_position),
          reflection = reflect(mv_light_direction, normal);
 
+    // Roughness from shininess, as meshes::Material::from_shininess.
+    float roughness = sqrt(2.0 / (shininess + 2.0));
+    vec4 light_ambient
+        = vec4(ambient_scale * textureCube(irradiance_map, environment_direction(normal)).rgb, 1.0);
+    vec4 environment_reflection = textureLod(
+        prefiltered_map, environment_direction(reflect(eye, normal)), roughness * environment_max_lod
+    );
+
     vec4 frag_diffuse = texture2D(texture, frag_texcoord);
     vec4 diffuse_factor
         = max(-dot(normal, mv_light_direction), 0.0) * light_diffuse;
     vec4 ambient_diffuse_factor
         = diffuse_factor + light_ambient;
     vec4 specular_factor
-        = max(pow(-dot(reflection, eye), frag_shininess), 0.0) * light_specular;
+        = max(pow(-dot(reflection, eye), shininess), 0.0) * light_specular;
     
-    gl_FragColor = specular_factor * frag_specular
+    vec4 color = (specular_factor + environment_reflection) * specular
         + ambient_diffuse_factor * frag_diffuse;
+    vec3 fogged = apply_fog(pow(max(color.rgb, vec3(0.0)), vec3(GAMMA)), frag_position);
+
+    gl_FragColor = vec4(pow(fogged, vec3(1.0 / GAMMA)), color.a);
 }
@@ -4,11 +4,13 @@ uniform mat4 p_matrix, mv_matrix;
 uniform sampler2D texture;
 
 attribute vec3 position, normal;
+attribute vec4 tangent;
 attribute vec2 texcoord;
 attribute float shininess;
 attribute vec4 specular;
 
 varying vec3 frag_position, frag_normal;
+varying vec4 frag_tangent;
 varying vec2 frag_texcoord;
 varying float frag_shininess;
 varying vec4 frag_specular;
@@ -18,6 +20,7 @@ void main() {
     gl_Position = p_matrix * eye_position;
     frag_position = eye_position.xyz;
     frag_normal   = (mv_matrix * vec4(normal, 0.0)).xyz;
+    frag_tangent  = vec4((mv_matrix * vec4(tangent.xyz, 0.0)).xyz, tangent.w);
     frag_texcoord = texcoord;
     frag_shininess = shininess;
     frag_specular = specular;
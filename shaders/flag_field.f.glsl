@@ -0,0 +1,29 @@
+#version 150
+
+// mv_matrix, light_direction and apply_fog come from fog.glsl.
+uniform sampler2DArray textures;
+
+varying vec3 frag_position, frag_normal;
+varying vec2 frag_texcoord;
+varying float frag_layer;
+
+const vec4 light_diffuse = vec4(0.8, 0.8, 0.8, 0.0);
+const vec4 light_ambient = vec4(0.2, 0.2, 0.2, 1.0);
+// Lighting here is in display terms, as in flag.f.glsl.
+const float GAMMA = 2.2;
+
+// As flag.f.glsl without the specular term, which flags don't have, and lit
+// from whichever side faces the eye.
+void main() {
+    vec3 mv_light_direction = (mv_matrix * vec4(light_direction, 0.0)).xyz,
+         normal = normalize(gl_FrontFacing ? frag_normal : -frag_normal);
+
+    vec4 frag_diffuse = texture(textures, vec3(frag_texcoord, frag_layer));
+    vec4 diffuse_factor
+        = max(-dot(normal, mv_light_direction), 0.0) * light_diffuse;
+
+    vec4 color = (diffuse_factor + light_ambient) * frag_diffuse;
+    vec3 fogged = apply_fog(pow(max(color.rgb, vec3(0.0)), vec3(GAMMA)), frag_position);
+
+    gl_FragColor = vec4(pow(fogged, vec3(1.0 / GAMMA)), color.a);
+}
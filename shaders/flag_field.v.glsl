@@ -0,0 +1,43 @@
+#version 150
+
+uniform mat4 p_matrix, mv_matrix;
+uniform float time;
+
+attribute vec2 texcoord;
+attribute mat4 instance_transform;
+// The texture array layer, then the time offset into the wave.
+attribute vec2 instance_params;
+
+varying vec3 frag_position, frag_normal;
+varying vec2 frag_texcoord;
+varying float frag_layer;
+
+const float PI = 3.141592653589793;
+
+// The wave from calculate_flag_vertex, with the flag's texcoords standing in
+// for its s/t grid coordinates.
+void main() {
+    float s = texcoord.x, t = texcoord.y;
+    float wave_time = time + instance_params.y;
+    float amplitude = 0.0625 + 0.03125 * sin(PI * wave_time);
+    float phase = 1.5 * PI * (wave_time + s);
+
+    vec3 sgrad = vec3(
+        1.0 + 0.5 * amplitude * t * (t - 1.0),
+        0.0,
+        0.125 * (sin(phase) + s * cos(phase) * (1.5 * PI))
+    );
+    vec3 tgrad = vec3(-amplitude * (1.0 - s) * (2.0 * t - 1.0), 0.75, 0.0);
+    vec3 position = vec3(
+        s - amplitude * (1.0 - 0.5 * s) * t * (t - 1.0),
+        0.75 * t - 0.375,
+        0.125 * s * sin(phase)
+    );
+
+    vec4 eye_position = mv_matrix * instance_transform * vec4(position, 1.0);
+    gl_Position = p_matrix * eye_position;
+    frag_position = eye_position.xyz;
+    frag_normal = (mv_matrix * instance_transform * vec4(normalize(cross(tgrad, sgrad)), 0.0)).xyz;
+    frag_texcoord = texcoord;
+    frag_layer = instance_params.x;
+}
@@ -0,0 +1,64 @@
+// Fog and scattering for the scene's fragment shaders, which are compiled
+// with this inserted after their #version line. It declares the uniforms and
+// constants it needs, so those shaders use them without declaring them again.
+
+uniform mat4 mv_matrix;
+uniform samplerCube prefiltered_map;
+uniform float environment_max_lod;
+uniform bool fog, scattering;
+uniform float fog_density, fog_height_density, fog_height_falloff, fog_base_height, fog_sky;
+uniform float scattering_anisotropy;
+uniform vec3 fog_color, sun_color;
+
+const float PI = 3.141592653589793;
+// Toward the scene from the sun.
+const vec3 light_direction = vec3(0.408248, -0.816497, 0.408248);
+// Extinction per channel relative to the fog's, as air scatters blue
+// sooner than red.
+const vec3 SCATTERING_SPREAD = vec3(0.7, 1.0, 1.4);
+
+// Eye space back to the scene, then into the cube maps' convention, which
+// has +z toward the viewer.
+vec3 environment_direction(vec3 eye_direction) {
+    vec3 d = transpose(mat3(mv_matrix)) * eye_direction;
+    return vec3(d.xy, -d.z);
+}
+
+// Optical depth from the eye to a point, both in the scene's frame: the
+// even fog plus the height fog, whose density falls off exponentially with
+// height and so integrates in closed form along the ray.
+float optical_depth(vec3 eye, vec3 point) {
+    float distance = length(point - eye),
+          rise = fog_height_falloff * (point.y - eye.y),
+          eye_density = fog_height_density * exp(-fog_height_falloff * (eye.y - fog_base_height)),
+          average = abs(rise) > 1.0e-4 ? (1.0 - exp(-rise)) / rise : 1.0;
+
+    return (fog_density + eye_density * average) * distance;
+}
+
+// Fogs a linear colour seen at an eye space position.
+vec3 apply_fog(vec3 color, vec3 position) {
+    if (!fog) {
+        return color;
+    }
+
+    mat3 to_scene = transpose(mat3(mv_matrix));
+    vec3 eye = -(to_scene * mv_matrix[3].xyz),
+         point = to_scene * (position - mv_matrix[3].xyz),
+         view = normalize(point - eye),
+         // The blurriest sky, so the fog doesn't pick out the sun's disc.
+         sky = textureLod(prefiltered_map, vec3(view.xy, -view.z), environment_max_lod).rgb,
+         in_color = mix(fog_color, sky, fog_sky);
+    float depth = optical_depth(eye, point);
+
+    if (scattering) {
+        // Henyey-Greenstein, toward the sun.
+        float g = scattering_anisotropy,
+              cos_theta = dot(view, -light_direction),
+              phase = (1.0 - g * g) / (4.0 * PI * pow(1.0 + g * g - 2.0 * g * cos_theta, 1.5));
+        vec3 transmittance = exp(-depth * SCATTERING_SPREAD);
+        return color * transmittance + (in_color + sun_color * phase) * (1.0 - transmittance);
+    }
+
+    return mix(color, in_color, 1.0 - exp(-depth));
+}
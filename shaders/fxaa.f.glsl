@@ -0,0 +1,42 @@
+#version 150
+
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+varying vec2 frag_texcoord;
+
+const float REDUCE_MIN = 1.0 / 128.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float SPAN_MAX = 8.0;
+
+float luma(vec2 offset) {
+    return texture2D(source, frag_texcoord + offset * texel_size).a;
+}
+
+vec3 fetch(vec2 offset) {
+    return texture2D(source, frag_texcoord + offset).rgb;
+}
+
+// Blurs along edges found from the luma of the corners around each pixel,
+// keeping the wider blur only where it doesn't overshoot the neighbourhood.
+void main() {
+    float nw = luma(vec2(-1.0, -1.0)),
+          ne = luma(vec2(1.0, -1.0)),
+          sw = luma(vec2(-1.0, 1.0)),
+          se = luma(vec2(1.0, 1.0)),
+          m = luma(vec2(0.0));
+    float luma_min = min(m, min(min(nw, ne), min(sw, se))),
+          luma_max = max(m, max(max(nw, ne), max(sw, se)));
+
+    vec2 direction = vec2(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    float reduce = max(0.25 * (nw + ne + sw + se) * REDUCE_MUL, REDUCE_MIN),
+          scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, -SPAN_MAX, SPAN_MAX) * texel_size;
+
+    vec3 near = 0.5 * (fetch(direction * (1.0 / 3.0 - 0.5)) + fetch(direction * (2.0 / 3.0 - 0.5))),
+         far = 0.5 * near + 0.25 * (fetch(direction * -0.5) + fetch(direction * 0.5));
+    float far_luma = dot(far, vec3(0.299, 0.587, 0.114));
+
+    vec3 color = far_luma < luma_min || far_luma > luma_max ? near : far;
+    gl_FragColor = vec4(color, 1.0);
+}
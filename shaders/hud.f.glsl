@@ -0,0 +1,10 @@
+#version 150
+
+uniform sampler2D font;
+
+varying vec2 frag_texcoord;
+varying vec4 frag_color;
+
+void main() {
+    gl_FragColor = vec4(frag_color.rgb, frag_color.a * texture2D(font, frag_texcoord).r);
+}
@@ -0,0 +1,17 @@
+#version 150
+
+uniform vec2 screen_size;
+
+attribute vec2 position, texcoord;
+attribute vec4 color;
+
+varying vec2 frag_texcoord;
+varying vec4 frag_color;
+
+// Positions are in pixels from the top left.
+void main() {
+    vec2 ndc = 2.0 * position / screen_size - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    frag_texcoord = texcoord;
+    frag_color = color;
+}
@@ -0,0 +1,163 @@
+#version 150
+
+// mv_matrix, prefiltered_map, environment_max_lod, PI, light_direction,
+// environment_direction and apply_fog come from fog.glsl.
+uniform mat4 p_matrix;
+uniform sampler2D texture, detail_normal_map, roughness_map;
+uniform vec2 detail_scale;
+uniform samplerCube irradiance_map;
+uniform sampler2D brdf_lut;
+
+// 0 for standard, 1 for cloth and 2 for anisotropic metal, as meshes::Lobe.
+uniform int lobe;
+uniform float metallic, roughness, anisotropy;
+uniform vec3 sheen;
+
+varying vec3 frag_position, frag_normal;
+varying vec4 frag_tangent;
+varying vec2 frag_texcoord;
+// frag_shininess and frag_specular are Phong's; the material uniforms stand
+// in for them here.
+
+const float GAMMA = 2.2;
+const float MIN_ALPHA = 0.002;
+
+// The same light as flag.f.glsl, in linear units.
+const vec3 light_color = vec3(2.5);
+
+float d_ggx(float n_dot_h, float alpha) {
+    float alpha2 = alpha * alpha,
+          f = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+
+    return alpha2 / (PI * f * f);
+}
+
+// Height-correlated Smith masking, folded together with the 4 n.l n.v of the
+// microfacet denominator.
+float v_smith_ggx(float n_dot_v, float n_dot_l, float alpha) {
+    float alpha2 = alpha * alpha,
+          view = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2),
+          light = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2);
+
+    return 0.5 / (view + light);
+}
+
+float d_ggx_anisotropic(float n_dot_h, float t_dot_h, float b_dot_h, float alpha_t, float alpha_b) {
+    float alpha2 = alpha_t * alpha_b;
+    vec3 d = vec3(alpha_b * t_dot_h, alpha_t * b_dot_h, alpha2 * n_dot_h);
+    float w2 = alpha2 / dot(d, d);
+
+    return alpha2 * w2 * w2 / PI;
+}
+
+float v_smith_ggx_anisotropic(
+    float alpha_t, float alpha_b,
+    float t_dot_v, float b_dot_v, float n_dot_v,
+    float t_dot_l, float b_dot_l, float n_dot_l
+) {
+    float view = n_dot_l * length(vec3(alpha_t * t_dot_v, alpha_b * b_dot_v, n_dot_v)),
+          light = n_dot_v * length(vec3(alpha_t * t_dot_l, alpha_b * b_dot_l, n_dot_l));
+
+    return 0.5 / (view + light);
+}
+
+vec3 f_schlick(vec3 f0, float v_dot_h) {
+    return f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+// The "Charlie" sheen distribution, which puts its weight at grazing
+// angles the way fibres sticking up from fabric catch the light.
+float d_charlie(float n_dot_h, float alpha) {
+    float inverse_alpha = 1.0 / alpha,
+          sin2 = 1.0 - n_dot_h * n_dot_h;
+
+    return (2.0 + inverse_alpha) * pow(sin2, 0.5 * inverse_alpha) / (2.0 * PI);
+}
+
+float v_neubelt(float n_dot_v, float n_dot_l) {
+    return 1.0 / (4.0 * (n_dot_l + n_dot_v - n_dot_l * n_dot_v));
+}
+
+vec3 prefiltered(vec3 eye_direction, float perceptual_roughness) {
+    return textureLod(
+        prefiltered_map, environment_direction(eye_direction), perceptual_roughness * environment_max_lod
+    ).rgb;
+}
+
+void main() {
+    vec3 normal = normalize(frag_normal),
+         tangent = frag_tangent.xyz;
+    bool has_tangent = dot(tangent, tangent) > 0.0;
+    float perceptual_roughness = roughness;
+
+    if (detail_scale.x > 0.0 && has_tangent) {
+        vec2 detail_texcoord = frag_texcoord * detail_scale;
+        vec3 t = normalize(tangent - normal * dot(tangent, normal)),
+             b = frag_tangent.w * cross(normal, t),
+             n = texture2D(detail_normal_map, detail_texcoord).xyz * 2.0 - 1.0;
+        normal = normalize(n.x * t + n.y * b + n.z * normal);
+        perceptual_roughness = texture2D(roughness_map, detail_texcoord).r;
+    }
+
+    vec3 base_color = pow(texture2D(texture, frag_texcoord).rgb, vec3(GAMMA)),
+         l = -normalize((mv_matrix * vec4(light_direction, 0.0)).xyz),
+         v = -normalize(frag_position),
+         h = normalize(l + v);
+    float n_dot_l = max(dot(normal, l), 0.0),
+          n_dot_v = max(dot(normal, v), 1.0e-4),
+          n_dot_h = max(dot(normal, h), 0.0),
+          v_dot_h = max(dot(v, h), 0.0),
+          alpha = max(perceptual_roughness * perceptual_roughness, MIN_ALPHA);
+
+    vec3 diffuse_color = base_color * (1.0 - metallic),
+         f0 = mix(vec3(0.04), base_color, metallic),
+         diffuse = diffuse_color / PI,
+         specular,
+         environment_specular;
+    // GGX scale and bias on f0, then the sheen's albedo.
+    vec3 split_sum = texture2D(brdf_lut, vec2(n_dot_v, perceptual_roughness)).rgb;
+
+    if (lobe == 1) {
+        specular = sheen * d_charlie(n_dot_h, alpha) * v_neubelt(n_dot_v, n_dot_l);
+        environment_specular = sheen * split_sum.b * prefiltered(reflect(-v, normal), perceptual_roughness);
+    } else {
+        vec3 f = f_schlick(f0, v_dot_h);
+        float dv;
+        vec3 reflection_normal = normal;
+        if (lobe == 2 && has_tangent) {
+            vec3 t = normalize(tangent - normal * dot(tangent, normal)),
+                 b = frag_tangent.w * cross(normal, t);
+            float alpha_t = max(alpha * (1.0 + anisotropy), MIN_ALPHA),
+                  alpha_b = max(alpha * (1.0 - anisotropy), MIN_ALPHA);
+            dv = d_ggx_anisotropic(n_dot_h, dot(t, h), dot(b, h), alpha_t, alpha_b)
+                * v_smith_ggx_anisotropic(
+                    alpha_t, alpha_b,
+                    dot(t, v), dot(b, v), n_dot_v,
+                    dot(t, l), dot(b, l), n_dot_l
+                );
+
+            // Bends the normal the environment is reflected about toward
+            // the grain, which stretches reflections along it the way the
+            // anisotropic lobe stretches highlights.
+            vec3 grain = anisotropy >= 0.0 ? b : t,
+                 across = cross(grain, v),
+                 grain_normal = cross(across, grain);
+            reflection_normal = normalize(mix(normal, grain_normal, abs(anisotropy)));
+        } else {
+            dv = d_ggx(n_dot_h, alpha) * v_smith_ggx(n_dot_v, n_dot_l, alpha);
+        }
+        specular = f * dv;
+        diffuse *= 1.0 - f;
+        environment_specular = (f0 * split_sum.r + split_sum.g)
+            * prefiltered(reflect(-v, reflection_normal), perceptual_roughness);
+    }
+
+    vec3 environment_diffuse = diffuse_color
+        * textureCube(irradiance_map, environment_direction(normal)).rgb;
+    vec3 color = (diffuse + specular) * light_color * n_dot_l
+        + environment_diffuse + environment_specular;
+
+    color = apply_fog(color, frag_position);
+
+    gl_FragColor = vec4(pow(color, vec3(1.0 / GAMMA)), 1.0);
+}
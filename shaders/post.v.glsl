@@ -0,0 +1,10 @@
+#version 150
+
+attribute vec2 corner;
+
+varying vec2 frag_texcoord;
+
+void main() {
+    gl_Position = vec4(corner, 0.0, 1.0);
+    frag_texcoord = 0.5 * corner + 0.5;
+}
@@ -0,0 +1,15 @@
+#version 150
+
+uniform samplerCube sky;
+
+varying vec3 frag_direction;
+
+const float GAMMA = 2.2;
+
+void main() {
+    // Cube maps have +z toward the viewer; the scene has it away.
+    vec3 d = normalize(frag_direction);
+    vec3 color = textureCube(sky, vec3(d.xy, -d.z)).rgb;
+
+    gl_FragColor = vec4(pow(color, vec3(1.0 / GAMMA)), 1.0);
+}
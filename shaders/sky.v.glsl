@@ -0,0 +1,15 @@
+#version 150
+
+uniform mat4 p_matrix, mv_matrix;
+
+attribute vec2 corner;
+
+varying vec3 frag_direction;
+
+// Runs the ray through each corner back out of the projection and the view,
+// leaving the eye position behind.
+void main() {
+    vec3 eye_direction = vec3(corner.x / p_matrix[0][0], corner.y / p_matrix[1][1], 1.0);
+    gl_Position = vec4(corner, 1.0, 1.0);
+    frag_direction = transpose(mat3(mv_matrix)) * eye_direction;
+}
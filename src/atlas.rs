@@ -0,0 +1,290 @@
+use file_util;
+use gl;
+use gl::types::*;
+use gl_util;
+use meshes::FlagVertex;
+use std::cmp;
+use std::io;
+use std::os::raw;
+
+
+// Pixels repeated around each image so filtering at its edges doesn't pick
+// up its neighbours.
+const GUTTER: i32 = 2;
+
+// Pixels as read_tga gives them: BGR, bottom row first.
+pub struct Image {
+    pub pixels: Vec<[u8; 3]>,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Image {
+    pub fn load(filename: &str) -> io::Result<Image> {
+        let (pixels, height, width) = try!(file_util::read_tga(filename));
+        Ok(Image { pixels: pixels, width: width, height: height })
+    }
+
+    fn pixel(&self, x: i32, y: i32) -> [u8; 3] {
+        let x = cmp::min(cmp::max(x, 0), self.width - 1);
+        let y = cmp::min(cmp::max(y, 0), self.height - 1);
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// A run of the skyline: everything below `y` from `x` to `x + width` is
+// taken.
+#[derive(Copy, Clone, Debug)]
+struct Segment {
+    x: i32,
+    y: i32,
+    width: i32,
+}
+
+// Bottom-left skyline packing: each rectangle goes where its top edge ends
+// up lowest, breaking ties toward the narrowest run so wide gaps are kept for
+// wide rectangles.
+pub struct Packer {
+    width: i32,
+    height: i32,
+    skyline: Vec<Segment>,
+}
+
+impl Packer {
+    pub fn new(width: i32, height: i32) -> Packer {
+        Packer {
+            width: width,
+            height: height,
+            skyline: vec![Segment { x: 0, y: 0, width: width }],
+        }
+    }
+
+    // Where a rectangle with its left edge at segment `k` would rest.
+    fn fit(&self, k: usize, width: i32, height: i32) -> Option<i32> {
+        let x = self.skyline[k].x;
+        if x + width > self.width {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut remaining = width;
+        for segment in self.skyline[k..].iter() {
+            if remaining <= 0 {
+                break;
+            }
+            y = cmp::max(y, segment.y);
+            remaining -= segment.width;
+        }
+
+        if y + height > self.height { None } else { Some(y) }
+    }
+
+    pub fn pack(&mut self, width: i32, height: i32) -> Option<Rect> {
+        let mut best: Option<(usize, i32)> = None;
+        for k in 0..self.skyline.len() {
+            if let Some(y) = self.fit(k, width, height) {
+                let better = match best {
+                    None => true,
+                    Some((b, best_y)) => {
+                        y < best_y || (y == best_y && self.skyline[k].width < self.skyline[b].width)
+                    }
+                };
+                if better {
+                    best = Some((k, y));
+                }
+            }
+        }
+
+        let (k, y) = match best {
+            Some(best) => best,
+            None => return None,
+        };
+        let rect = Rect { x: self.skyline[k].x, y: y, width: width, height: height };
+        self.skyline.insert(k, Segment { x: rect.x, y: y + height, width: width });
+
+        // Trim the runs the new one now covers.
+        let right = rect.x + width;
+        while k + 1 < self.skyline.len() && self.skyline[k + 1].x < right {
+            let overlap = right - self.skyline[k + 1].x;
+            if self.skyline[k + 1].width <= overlap {
+                self.skyline.remove(k + 1);
+            } else {
+                self.skyline[k + 1].x += overlap;
+                self.skyline[k + 1].width -= overlap;
+                break;
+            }
+        }
+
+        // Merge neighbouring runs at the same height.
+        let mut i = 0;
+        while i + 1 < self.skyline.len() {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+
+        Some(rect)
+    }
+}
+
+// Where an image ended up in an atlas, in texture coordinates.
+#[derive(Copy, Clone, Debug)]
+pub struct Region {
+    pub lo: [GLfloat; 2],
+    pub hi: [GLfloat; 2],
+}
+
+impl Region {
+    // The region covering a whole texture, for meshes that don't share one.
+    pub fn whole() -> Region {
+        Region { lo: [0.0, 0.0], hi: [1.0, 1.0] }
+    }
+
+    pub fn remap(&self, texcoord: [GLfloat; 2]) -> [GLfloat; 2] {
+        [
+            self.lo[0] + (self.hi[0] - self.lo[0]) * texcoord[0],
+            self.lo[1] + (self.hi[1] - self.lo[1]) * texcoord[1],
+        ]
+    }
+
+    // Moves texture coordinates meant for the image alone into the atlas.
+    pub fn remap_vertices(&self, vertices: &mut [FlagVertex]) {
+        for v in vertices.iter_mut() {
+            v.texcoord = self.remap(v.texcoord);
+        }
+    }
+}
+
+// Atlas regions don't repeat, so meshes that tile their texture have to keep
+// it to themselves.
+pub fn fits_region(vertices: &[FlagVertex]) -> bool {
+    vertices.iter().all(|v| {
+        v.texcoord.iter().all(|&c| c >= 0.0 && c <= 1.0)
+    })
+}
+
+// Packs images into one texture so meshes using any of them can share a
+// binding. The GL texture is named up front so meshes can be pointed at it
+// before the last image is in, and filled by upload.
+pub struct Atlas {
+    pub texture: GLuint,
+    width: i32,
+    height: i32,
+    packer: Packer,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Atlas {
+    pub fn new(width: i32, height: i32) -> Atlas {
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+        }
+
+        Atlas {
+            texture: texture,
+            width: width,
+            height: height,
+            packer: Packer::new(width, height),
+            pixels: vec![[0; 3]; (width * height) as usize],
+        }
+    }
+
+    // Returns None if there's no room left for the image.
+    pub fn add(&mut self, image: &Image) -> Option<Region> {
+        let rect = match self.packer.pack(image.width + 2 * GUTTER, image.height + 2 * GUTTER) {
+            Some(rect) => rect,
+            None => return None,
+        };
+
+        for y in 0..rect.height {
+            let row = ((rect.y + y) * self.width + rect.x) as usize;
+            for x in 0..rect.width {
+                self.pixels[row + x as usize] = image.pixel(x - GUTTER, y - GUTTER);
+            }
+        }
+
+        let (x, y) = ((rect.x + GUTTER) as GLfloat, (rect.y + GUTTER) as GLfloat);
+        let (width, height) = (self.width as GLfloat, self.height as GLfloat);
+        Some(Region {
+            lo: [x / width, y / height],
+            hi: [(x + image.width as GLfloat) / width, (y + image.height as GLfloat) / height],
+        })
+    }
+
+    pub fn upload(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0,
+                gl::RGB8 as GLint,
+                self.width, self.height, 0,
+                gl::BGR, gl::UNSIGNED_BYTE,
+                self.pixels.as_ptr() as *const raw::c_void
+            );
+        }
+    }
+}
+
+// Collects same-size images for the layers of a 2D array texture, such as
+// flag designs to pick between per instance.
+pub struct TextureArrayBuilder {
+    width: i32,
+    height: i32,
+    layers: Vec<Vec<[u8; 3]>>,
+}
+
+impl TextureArrayBuilder {
+    pub fn new() -> TextureArrayBuilder {
+        TextureArrayBuilder { width: 0, height: 0, layers: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    // The first image sets the size the rest have to match. Returns the
+    // image's layer.
+    pub fn add(&mut self, image: Image) -> Result<usize, String> {
+        if self.layers.is_empty() {
+            self.width = image.width;
+            self.height = image.height;
+        } else if image.width != self.width || image.height != self.height {
+            return Err(format!(
+                "{} x {} doesn't match the other layers' {} x {}",
+                image.width, image.height, self.width, self.height
+            ));
+        }
+
+        self.layers.push(image.pixels);
+        Ok(self.layers.len() - 1)
+    }
+
+    pub fn load(&mut self, filename: &str) -> Result<usize, String> {
+        let image = try!(Image::load(filename).map_err(|e| e.to_string()));
+        self.add(image)
+    }
+
+    pub fn layer(&self, k: usize) -> Image {
+        Image { pixels: self.layers[k].clone(), width: self.width, height: self.height }
+    }
+
+    pub fn build(&self) -> GLuint {
+        gl_util::make_texture_array(&self.layers, self.width, self.height)
+    }
+}
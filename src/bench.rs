@@ -0,0 +1,129 @@
+use flag_update::FlagUpdater;
+use gl::types::GLfloat;
+use meshes;
+use meshes::FlagVertex;
+use simulation;
+use std::f32;
+use std::time::Instant;
+
+
+// The flag as drawn, then finer grids such as a scene of many flags or a
+// close-up might need.
+const DEFAULT_GRIDS: [(usize, usize); 3] = [(100, 75), (400, 300), (1000, 750)];
+const DEFAULT_UPDATES: u32 = 200;
+
+pub const USAGE: &str = "usage: flag bench [--columns COUNT] [--rows COUNT] [--updates COUNT] [--threads COUNT]";
+
+pub struct BenchOptions {
+    pub grids: Vec<(usize, usize)>,
+    pub updates: u32,
+    pub threads: usize,
+}
+
+impl BenchOptions {
+    pub fn parse(args: &[String]) -> Result<BenchOptions, String> {
+        let mut columns = None;
+        let mut rows = None;
+        let mut options = BenchOptions {
+            grids: DEFAULT_GRIDS.to_vec(),
+            updates: DEFAULT_UPDATES,
+            threads: simulation::default_threads(),
+        };
+
+        let mut rest = args.iter();
+        while let Some(flag) = rest.next() {
+            let value = match rest.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {}", flag)),
+            };
+            let invalid = || format!("invalid value '{}' for {}", value, flag);
+            match flag.as_str() {
+                "--columns" => columns = Some(try!(value.parse::<usize>().map_err(|_| invalid()))),
+                "--rows" => rows = Some(try!(value.parse::<usize>().map_err(|_| invalid()))),
+                "--updates" => options.updates = try!(value.parse().map_err(|_| invalid())),
+                "--threads" => options.threads = try!(value.parse().map_err(|_| invalid())),
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+
+        // Either dimension on its own keeps the flag's proportions.
+        match (columns, rows) {
+            (Some(columns), Some(rows)) => options.grids = vec![(columns, rows)],
+            (Some(columns), None) => options.grids = vec![(columns, usize::max(columns * 3 / 4, 2))],
+            (None, Some(rows)) => options.grids = vec![(usize::max(rows * 4 / 3, 2), rows)],
+            (None, None) => {}
+        }
+
+        if options.grids.iter().any(|&(columns, rows)| columns < 2 || rows < 2) {
+            return Err(String::from("the grid needs at least two columns and two rows"));
+        }
+        if options.updates == 0 || options.threads == 0 {
+            return Err(String::from("the update and thread counts must be positive"));
+        }
+
+        Ok(options)
+    }
+}
+
+// Milliseconds per update, over `updates` updates a step apart.
+fn time_updates<F: FnMut(&mut [FlagVertex], GLfloat)>(vertices: &mut [FlagVertex], updates: u32, mut update: F) -> f64 {
+    let start = Instant::now();
+    for k in 0..updates {
+        update(vertices, k as GLfloat / 60.0);
+    }
+    let elapsed = start.elapsed();
+
+    (elapsed.as_secs() as f64 * 1.0e3 + elapsed.subsec_nanos() as f64 * 1.0e-6) / updates as f64
+}
+
+fn max_difference(reference: &[FlagVertex], vertices: &[FlagVertex]) -> GLfloat {
+    let mut difference: GLfloat = 0.0;
+    for (a, b) in reference.iter().zip(vertices.iter()) {
+        for c in 0..3 {
+            difference = f32::max(difference, (a.position[c] - b.position[c]).abs());
+            difference = f32::max(difference, (a.normal[c] - b.normal[c]).abs());
+            difference = f32::max(difference, (a.tangent[c] - b.tangent[c]).abs());
+        }
+    }
+
+    difference
+}
+
+// 1, 2, 4 and so on up to `threads`, which is always included.
+fn thread_counts(threads: usize) -> Vec<usize> {
+    let mut counts = vec![];
+    let mut count = 1;
+    while count < threads {
+        counts.push(count);
+        count *= 2;
+    }
+    counts.push(threads);
+
+    counts
+}
+
+// Times the one-vertex-at-a-time update the flag used to run on against the
+// vectorized one at each thread count, checking the results agree.
+pub fn run(options: &BenchOptions) {
+    for &(columns, rows) in options.grids.iter() {
+        println!("{} x {} grid, {} updates", columns, rows, options.updates);
+        let mut reference = vec![FlagVertex::zero(); columns * rows];
+        let scalar = time_updates(&mut reference, options.updates, |vertices, time| {
+            meshes::calculate_flag_grid(vertices, columns, rows, time, 0.0);
+        });
+        println!("  {:<20} {:>9.3} ms", "scalar", scalar);
+
+        for threads in thread_counts(options.threads) {
+            let mut updater = FlagUpdater::new(threads);
+            let mut vertices = vec![FlagVertex::zero(); columns * rows];
+            let simd = time_updates(&mut vertices, options.updates, |vertices, time| {
+                updater.update(vertices, columns, rows, time, 0.0);
+            });
+            let label = format!("simd, {} thread{}", threads, if threads == 1 { "" } else { "s" });
+            println!(
+                "  {:<20} {:>9.3} ms {:>7.1}x   max error {:.1e}",
+                label, simd, scalar / simd, max_difference(&reference, &vertices)
+            );
+        }
+    }
+}
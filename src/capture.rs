@@ -0,0 +1,343 @@
+use gl;
+use gl::types::GLint;
+use glfw::Glfw;
+use png;
+use video;
+use std::fs;
+use std::io;
+use std::os::raw;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+pub const CAPTURE_DIRECTORY: &str = "captures";
+const RECORD_FPS: f64 = 30.0;
+const DEFAULT_RECORD_SECONDS: f64 = 4.0;
+
+pub const USAGE: &str
+    = "usage: flag record <png|y4m|gif> [--seconds SECONDS] [--width PIXELS] [--height PIXELS] [--output PATH]";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RecordFormat {
+    PngSequence,
+    Y4m,
+    Gif,
+}
+
+impl RecordFormat {
+    fn next(self) -> RecordFormat {
+        match self {
+            RecordFormat::PngSequence => RecordFormat::Y4m,
+            RecordFormat::Y4m => RecordFormat::Gif,
+            RecordFormat::Gif => RecordFormat::PngSequence,
+        }
+    }
+
+    fn default_output(self) -> PathBuf {
+        let name = format!("recording_{}", timestamp());
+        let path = PathBuf::from(CAPTURE_DIRECTORY).join(name);
+        match self {
+            RecordFormat::PngSequence => path,
+            RecordFormat::Y4m => path.with_extension("y4m"),
+            RecordFormat::Gif => path.with_extension("gif"),
+        }
+    }
+}
+
+// Options for recording a clip without showing a window.
+pub struct RecordOptions {
+    pub format: RecordFormat,
+    pub seconds: f64,
+    pub width: GLint,
+    pub height: GLint,
+    pub output: Option<PathBuf>,
+}
+
+impl RecordOptions {
+    pub fn parse(args: &[String]) -> Result<RecordOptions, String> {
+        let format = match args.first().map(|s| s.as_str()) {
+            Some("png") => RecordFormat::PngSequence,
+            Some("y4m") => RecordFormat::Y4m,
+            Some("gif") => RecordFormat::Gif,
+            Some(other) => return Err(format!("unknown record format '{}'", other)),
+            None => return Err(String::from("missing record format")),
+        };
+
+        let mut options = RecordOptions {
+            format: format,
+            seconds: DEFAULT_RECORD_SECONDS,
+            width: 640,
+            height: 480,
+            output: None,
+        };
+
+        let mut rest = args[1..].iter();
+        while let Some(flag) = rest.next() {
+            let value = match rest.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {}", flag)),
+            };
+            let invalid = || format!("invalid value '{}' for {}", value, flag);
+            match flag.as_str() {
+                "--seconds" => options.seconds = try!(value.parse().map_err(|_| invalid())),
+                "--width" => options.width = try!(value.parse().map_err(|_| invalid())),
+                "--height" => options.height = try!(value.parse().map_err(|_| invalid())),
+                "--output" => options.output = Some(PathBuf::from(value)),
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+
+        if !(options.seconds > 0.0) || options.width <= 0 || options.height <= 0 {
+            return Err(String::from("the duration and frame size must be positive"));
+        }
+
+        Ok(options)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        (self.seconds * RECORD_FPS).round() as usize
+    }
+}
+
+// Where recorded frames go. Video sinks are opened on the first frame, once
+// its size is known.
+enum FrameSink {
+    PngSequence(PathBuf),
+    Y4m(video::Y4mWriter),
+    Gif(video::GifWriter),
+}
+
+impl FrameSink {
+    fn open(format: RecordFormat, output: &Path, width: usize, height: usize) -> io::Result<FrameSink> {
+        if format == RecordFormat::PngSequence {
+            try!(fs::create_dir_all(output));
+            return Ok(FrameSink::PngSequence(output.to_path_buf()));
+        }
+
+        if let Some(parent) = output.parent() {
+            try!(fs::create_dir_all(parent));
+        }
+        let fps = RECORD_FPS as u32;
+        Ok(match format {
+            RecordFormat::Y4m => FrameSink::Y4m(try!(video::Y4mWriter::create(output, width, height, fps))),
+            _ => FrameSink::Gif(try!(video::GifWriter::create(output, width, height, fps))),
+        })
+    }
+
+    fn write(&mut self, frame: usize, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+        match *self {
+            FrameSink::PngSequence(ref directory) => {
+                let filename = directory.join(format!("frame_{:05}.png", frame));
+                png::write_png(&filename.to_string_lossy(), width, height, rgb)
+            }
+            FrameSink::Y4m(ref mut writer) => writer.write_frame(width, height, rgb),
+            FrameSink::Gif(ref mut writer) => writer.write_frame(width, height, rgb),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match *self {
+            FrameSink::PngSequence(_) => Ok(()),
+            FrameSink::Y4m(ref mut writer) => writer.finish(),
+            FrameSink::Gif(ref mut writer) => writer.finish(),
+        }
+    }
+}
+
+pub struct Capture {
+    screenshot_requested: bool,
+    format: RecordFormat,
+    recording: bool,
+    output: PathBuf,
+    sink: Option<FrameSink>,
+    frame: usize,
+    start_time: Option<f64>,
+    resume_time: Option<f64>,
+    // Added to GLFW's clock to give the frame clock, which falls behind it
+    // while frames are recorded. GLFW's own clock is left alone for the
+    // frame rate and readouts.
+    clock_offset: f64,
+}
+
+// The milliseconds since the epoch of the last timestamp handed out.
+static LAST_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+
+// Formats the current UTC time as YYYYMMDD-HHMMSS-mmm, converting days since
+// the epoch to a civil date. Each call moves on at least a millisecond from
+// the last, so names made from it in quick succession never collide.
+pub fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    let millis = now.max(LAST_TIMESTAMP.load(Ordering::Relaxed) + 1);
+    LAST_TIMESTAMP.store(millis, Ordering::Relaxed);
+
+    let seconds = (millis / 1000) as i64;
+    let (days, time_of_day) = (seconds / 86400, seconds % 86400);
+
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year, month, day, time_of_day / 3600, (time_of_day / 60) % 60, time_of_day % 60, millis % 1000
+    )
+}
+
+// Reads the back buffer, or the bound offscreen framebuffer, as RGB, flipping
+// it so the top row comes first.
+pub fn read_framebuffer(width: i32, height: i32) -> Vec<u8> {
+    let stride = 3 * width as usize;
+    let mut pixels = vec![0 as u8; stride * height as usize];
+    unsafe {
+        let mut bound = 0;
+        gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut bound);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadBuffer(if bound == 0 { gl::BACK } else { gl::COLOR_ATTACHMENT0 });
+        gl::ReadPixels(
+            0, 0, width, height,
+            gl::RGB, gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut raw::c_void
+        );
+    }
+
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks(stride).rev() {
+        flipped.extend_from_slice(row);
+    }
+
+    flipped
+}
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture {
+            screenshot_requested: false,
+            format: RecordFormat::PngSequence,
+            recording: false,
+            output: PathBuf::from(CAPTURE_DIRECTORY),
+            sink: None,
+            frame: 0,
+            start_time: None,
+            resume_time: None,
+            clock_offset: 0.0,
+        }
+    }
+
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    // Takes effect from the next recording.
+    pub fn cycle_format(&mut self) {
+        self.format = self.format.next();
+        println!("Recording format: {:?}", self.format);
+    }
+
+    pub fn set_format(&mut self, format: RecordFormat) {
+        self.format = format;
+    }
+
+    pub fn start_recording(&mut self, output: Option<PathBuf>) {
+        self.output = output.unwrap_or_else(|| self.format.default_output());
+        self.sink = None;
+        self.recording = true;
+        self.frame = 0;
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recording = false;
+        self.resume_time = self.start_time.take().map(|t| t + (self.frame as f64) / RECORD_FPS);
+
+        let finished = match self.sink.take() {
+            Some(mut sink) => sink.finish(),
+            None => return,
+        };
+        if let Err(e) = finished {
+            eprintln!("Failed to finish {}: {}", self.output.display(), e);
+            return;
+        }
+
+        let output = self.output.display();
+        match self.format {
+            RecordFormat::PngSequence => println!(
+                "Recorded {} frames to {}; encode with: ffmpeg -framerate {} -i {}/frame_%05d.png",
+                self.frame, output, RECORD_FPS, output
+            ),
+            RecordFormat::Y4m => println!(
+                "Recorded {} frames to {}; encode with: ffmpeg -i {} flag.mp4", self.frame, output, output
+            ),
+            RecordFormat::Gif => println!("Recorded {} frames to {}", self.frame, output),
+        }
+    }
+
+    pub fn toggle_recording(&mut self) {
+        if self.recording {
+            self.stop_recording();
+        } else {
+            self.start_recording(None);
+        }
+    }
+
+    // The clock that drives each frame. While recording, it advances by a
+    // fixed step per captured frame no matter how long each frame takes to
+    // render and write out. Afterwards it picks up from where the recording
+    // left off.
+    pub fn frame_time(&mut self, glfw: &Glfw) -> f64 {
+        let now = glfw.get_time();
+        if self.recording {
+            let start = *self.start_time.get_or_insert(now + self.clock_offset);
+            return start + (self.frame as f64) / RECORD_FPS;
+        }
+
+        if let Some(time) = self.resume_time.take() {
+            self.clock_offset = time - now;
+        }
+        now + self.clock_offset
+    }
+
+    fn write_screenshot(&self, width: i32, height: i32) -> io::Result<String> {
+        try!(fs::create_dir_all(CAPTURE_DIRECTORY));
+        let filename = format!("{}/screenshot_{}.png", CAPTURE_DIRECTORY, timestamp());
+        let pixels = read_framebuffer(width, height);
+        try!(png::write_png(&filename, width as usize, height as usize, &pixels));
+
+        Ok(filename)
+    }
+
+    fn write_frame(&mut self, width: i32, height: i32) -> io::Result<()> {
+        let (width, height) = (width as usize, height as usize);
+        if self.sink.is_none() {
+            self.sink = Some(try!(FrameSink::open(self.format, &self.output, width, height)));
+        }
+
+        let pixels = read_framebuffer(width as i32, height as i32);
+        self.sink.as_mut().unwrap().write(self.frame, width, height, &pixels)
+    }
+
+    // Call after rendering and before swapping buffers.
+    pub fn capture(&mut self, width: i32, height: i32) {
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            match self.write_screenshot(width, height) {
+                Ok(filename) => println!("Saved {}", filename),
+                Err(e) => eprintln!("Failed to save screenshot: {}", e),
+            }
+        }
+
+        if self.recording {
+            if let Err(e) = self.write_frame(width, height) {
+                eprintln!("Failed to write frame {}: {}", self.frame, e);
+                self.stop_recording();
+                return;
+            }
+            self.frame += 1;
+        }
+    }
+}
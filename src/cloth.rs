@@ -0,0 +1,474 @@
+use collision::{Collider, SpatialHash};
+use gl::types::{GLfloat, GLushort};
+use meshes::FlagVertex;
+use mesh_builder;
+use std::cell::RefCell;
+use std::f32;
+use std::rc::Rc;
+use vec_util;
+
+
+const GRAVITY: [GLfloat; 3] = [0.0, -9.8, 0.0];
+const DAMPING: GLfloat = 0.01;
+const ITERATIONS: usize = 8;
+
+// Particles keep this far from solid surfaces, and twice this from each other
+// unless they are close neighbours on the grid.
+const THICKNESS: GLfloat = 0.004;
+const SELF_DISTANCE: GLfloat = 2.0 * THICKNESS;
+const SELF_NEIGHBOURHOOD: usize = 2;
+
+const WIND_SPEED: GLfloat = 6.0;
+const STORM_WIND_SPEED: GLfloat = 20.0;
+// How much storm gusts vary across the flag, as a fraction of the wind speed.
+const STORM_TURBULENCE: GLfloat = 0.4;
+// Pressure acts across the cloth and friction along it, which is what keeps
+// a flag flying out instead of hanging.
+const WIND_PRESSURE: GLfloat = 1.5;
+const WIND_FRICTION: GLfloat = 4.0;
+
+// A constraint stretched past this multiple of its rest length tears.
+pub const TEAR_STRAIN: GLfloat = 2.0;
+const MAX_TEARS_PER_STEP: usize = 16;
+// Each particle is a vertex of the flag mesh, so tears stop at its limit.
+const MAX_PARTICLES: usize = mesh_builder::MAX_VERTICES;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Constraint {
+    pub a: u32,
+    pub b: u32,
+    pub rest: GLfloat,
+}
+
+// A Verlet particle cloth over the flag grid, held along its hoist edge. Each
+// particle drives the flag vertex with the same index. Tearing splits
+// particles, and the copies are added after the grid.
+#[derive(Clone)]
+pub struct Cloth {
+    columns: usize,
+    positions: Vec<[GLfloat; 3]>,
+    previous: Vec<[GLfloat; 3]>,
+    hoist: Vec<[GLfloat; 3]>,
+    // The topology rarely changes, so states kept for rewinding share it.
+    constraints: Rc<Vec<Constraint>>,
+    // The grid particle each particle was split from, or itself.
+    origins: Rc<Vec<u32>>,
+    // Rest distance from each particle to the hoist particle in its row,
+    // which is infinite once a tear cuts it off from the hoist.
+    tethers: Rc<Vec<GLfloat>>,
+    colliders: Rc<Vec<Collider>>,
+    // Scratch space rebuilt every step, so it is shared rather than copied.
+    hash: Rc<RefCell<SpatialHash>>,
+    grab: Option<(usize, [GLfloat; 3])>,
+    storm: bool,
+}
+
+fn sub(u: &[GLfloat; 3], v: &[GLfloat; 3]) -> [GLfloat; 3] {
+    [u[0] - v[0], u[1] - v[1], u[2] - v[2]]
+}
+
+fn distance(a: &[GLfloat; 3], b: &[GLfloat; 3]) -> GLfloat {
+    vec_util::vec_length(&sub(b, a))
+}
+
+fn find_root(parents: &mut [usize], mut k: usize) -> usize {
+    while parents[k] != k {
+        parents[k] = parents[parents[k]];
+        k = parents[k];
+    }
+    k
+}
+
+impl Cloth {
+    // `rest` gives the unstretched layout of the grid, row by row, and
+    // `vertices` the shape to start from, with the hoist edge raised by
+    // `hoist_offset`. The hoist is pinned where it is in `vertices`, so it
+    // doesn't jump when the cloth takes over from the wave.
+    pub fn new(
+        columns: usize, rows: usize, rest: &[[GLfloat; 3]], vertices: &[FlagVertex], hoist_offset: GLfloat,
+        colliders: Vec<Collider>
+    ) -> Cloth {
+        let mut constraints = vec![];
+        {
+            let mut link = |a: usize, b: usize| {
+                constraints.push(Constraint { a: a as u32, b: b as u32, rest: distance(&rest[a], &rest[b]) });
+            };
+            for j in 0..rows {
+                for i in 0..columns {
+                    let k = j * columns + i;
+                    // Structural links along each row and column.
+                    if i + 1 < columns { link(k, k + 1); }
+                    if j + 1 < rows { link(k, k + columns); }
+                    // Shear links across each cell.
+                    if i + 1 < columns && j + 1 < rows {
+                        link(k, k + columns + 1);
+                        link(k + 1, k + columns);
+                    }
+                    // Bend links skipping a particle.
+                    if i + 2 < columns { link(k, k + 2); }
+                    if j + 2 < rows { link(k, k + 2 * columns); }
+                }
+            }
+        }
+
+        let positions: Vec<[GLfloat; 3]> = vertices.iter()
+            .map(|v| [v.position[0], v.position[1], v.position[2]])
+            .collect();
+        let hoist = (0..rows).map(|j| {
+            let p = positions[j * columns];
+            [p[0], p[1] - hoist_offset, p[2]]
+        }).collect();
+
+        Cloth {
+            columns: columns,
+            previous: positions.clone(),
+            positions: positions,
+            hoist: hoist,
+            constraints: Rc::new(constraints),
+            origins: Rc::new((0..rest.len() as u32).collect()),
+            tethers: Rc::new((0..rest.len()).map(|k| distance(&rest[k], &rest[k - k % columns])).collect()),
+            colliders: Rc::new(colliders),
+            hash: Rc::new(RefCell::new(SpatialHash::new(2.0 * SELF_DISTANCE, rest.len()))),
+            grab: None,
+            storm: false,
+        }
+    }
+
+    fn pinned(&self, k: usize) -> bool {
+        let hoist = k < self.hoist.len() * self.columns && k % self.columns == 0;
+        hoist || self.grab.map_or(false, |(grabbed, _)| grabbed == k)
+    }
+
+    // The hoist particle a particle is tethered to.
+    fn anchor(&self, k: usize) -> usize {
+        let origin = self.origins[k] as usize;
+        origin - origin % self.columns
+    }
+
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    pub fn grab(&mut self, particle: usize, target: [GLfloat; 3]) {
+        self.grab = Some((particle, target));
+    }
+
+    pub fn move_grab(&mut self, target: [GLfloat; 3]) {
+        if let Some((particle, _)) = self.grab {
+            self.grab = Some((particle, target));
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.grab = None;
+    }
+
+    pub fn storm(&self) -> bool {
+        self.storm
+    }
+
+    // Returns whether the storm is now blowing.
+    pub fn toggle_storm(&mut self) -> bool {
+        self.storm = !self.storm;
+        self.storm
+    }
+
+    // Gusty wind blowing out from the pole. Storm gusts also swirl across
+    // the flag, pulling neighbouring parts of it apart.
+    pub fn wind(&self, time: GLfloat, p: &[GLfloat; 3]) -> [GLfloat; 3] {
+        let gust = 1.0 + 0.25 * f32::sin(0.9 * time) + 0.1 * f32::sin(2.3 * time);
+        if !self.storm {
+            return [WIND_SPEED * gust, 0.0, 0.15 * WIND_SPEED * f32::sin(0.7 * time)];
+        }
+
+        let swirl = STORM_TURBULENCE * STORM_WIND_SPEED;
+        [
+            STORM_WIND_SPEED * gust + swirl * f32::sin(3.1 * time + 7.0 * p[0] - 4.0 * p[1]),
+            swirl * f32::sin(2.3 * time + 5.0 * p[1] + 3.0 * p[0]),
+            swirl * f32::cos(2.9 * time + 6.0 * p[1] - 5.0 * p[0]),
+        ]
+    }
+
+    // Advances by `dt`, with the hoist edge raised by `hoist_offset`. The
+    // normals in `vertices` from the last step shape the wind force, and
+    // positions and normals are written back. Returns whether the cloth tore,
+    // in which case vertices have been added and `elements` rebuilt.
+    pub fn step(
+        &mut self, dt: GLfloat, time: GLfloat, hoist_offset: GLfloat,
+        vertices: &mut Vec<FlagVertex>, elements: &mut Rc<Vec<GLushort>>
+    ) -> bool {
+        for k in 0..self.positions.len() {
+            if self.pinned(k) {
+                continue;
+            }
+            let (p, q) = (self.positions[k], self.previous[k]);
+            let wind = self.wind(time, &p);
+            let n = &vertices[k].normal;
+            let velocity = [(p[0] - q[0]) / dt, (p[1] - q[1]) / dt, (p[2] - q[2]) / dt];
+            let relative = [wind[0] - velocity[0], wind[1] - velocity[1], wind[2] - velocity[2]];
+            let across = vec_util::vec_dot(n, &relative);
+
+            let mut next = [0.0; 3];
+            for c in 0..3 {
+                let along = relative[c] - across * n[c];
+                let acceleration = GRAVITY[c] + WIND_PRESSURE * across * n[c] + WIND_FRICTION * along;
+                next[c] = p[c] + (p[c] - q[c]) * (1.0 - DAMPING) + acceleration * dt * dt;
+            }
+            self.previous[k] = p;
+            self.positions[k] = next;
+        }
+
+        for (j, rest) in self.hoist.iter().enumerate() {
+            let k = j * self.columns;
+            self.positions[k] = [rest[0], rest[1] + hoist_offset, rest[2]];
+            self.previous[k] = self.positions[k];
+        }
+        if let Some((k, target)) = self.grab {
+            self.positions[k] = target;
+            self.previous[k] = target;
+        }
+
+        for _ in 0..ITERATIONS {
+            self.relax();
+            self.tether();
+            self.collide();
+        }
+        self.collide_self();
+        let torn = self.tear(vertices, elements);
+
+        self.write_vertices(vertices, elements);
+        torn
+    }
+
+    fn relax(&mut self) {
+        for c in self.constraints.iter() {
+            let (a, b) = (c.a as usize, c.b as usize);
+            let weight_a = if self.pinned(a) { 0.0 } else { 1.0 };
+            let weight_b = if self.pinned(b) { 0.0 } else { 1.0 };
+            if weight_a + weight_b == 0.0 {
+                continue;
+            }
+
+            let (pa, pb) = (self.positions[a], self.positions[b]);
+            let length = distance(&pa, &pb);
+            if length == 0.0 {
+                continue;
+            }
+            let correction = (length - c.rest) / (length * (weight_a + weight_b));
+            for k in 0..3 {
+                let delta = (pb[k] - pa[k]) * correction;
+                self.positions[a][k] += delta * weight_a;
+                self.positions[b][k] -= delta * weight_b;
+            }
+        }
+    }
+
+    // Relaxation alone lets a wide cloth stretch well past its rest size, so
+    // no particle is allowed further from the hoist than it rests.
+    fn tether(&mut self) {
+        for k in 0..self.positions.len() {
+            if self.pinned(k) {
+                continue;
+            }
+            let anchor = self.positions[self.anchor(k)];
+            let p = self.positions[k];
+            let length = distance(&anchor, &p);
+            let limit = self.tethers[k];
+            if length > limit {
+                for c in 0..3 {
+                    self.positions[k][c] = anchor[c] + (p[c] - anchor[c]) * limit / length;
+                }
+            }
+        }
+    }
+
+    fn collide(&mut self) {
+        for k in 0..self.positions.len() {
+            if self.pinned(k) {
+                continue;
+            }
+            for collider in self.colliders.iter() {
+                collider.resolve(&mut self.positions[k], THICKNESS);
+            }
+        }
+    }
+
+    fn near_on_grid(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.origins[a] as usize, self.origins[b] as usize);
+        let (ia, ja) = (a % self.columns, a / self.columns);
+        let (ib, jb) = (b % self.columns, b / self.columns);
+        let (di, dj) = (if ia > ib { ia - ib } else { ib - ia }, if ja > jb { ja - jb } else { jb - ja });
+
+        di <= SELF_NEIGHBOURHOOD && dj <= SELF_NEIGHBOURHOOD
+    }
+
+    // Separates particles from distant parts of the cloth that come too
+    // close, finding candidates through the spatial hash.
+    fn collide_self(&mut self) {
+        let hash = self.hash.clone();
+        let mut hash = hash.borrow_mut();
+        hash.build(&self.positions);
+
+        let mut nearby = vec![];
+        for k in 0..self.positions.len() {
+            hash.query(&self.positions[k], &mut nearby);
+            for &other in nearby.iter() {
+                if other <= k || self.near_on_grid(k, other) {
+                    continue;
+                }
+                let weight_k = if self.pinned(k) { 0.0 } else { 1.0 };
+                let weight_other = if self.pinned(other) { 0.0 } else { 1.0 };
+                if weight_k + weight_other == 0.0 {
+                    continue;
+                }
+
+                let (p, q) = (self.positions[k], self.positions[other]);
+                let length = distance(&p, &q);
+                if length >= SELF_DISTANCE || length == 0.0 {
+                    continue;
+                }
+                let correction = (SELF_DISTANCE - length) / (length * (weight_k + weight_other));
+                for c in 0..3 {
+                    let delta = (q[c] - p[c]) * correction;
+                    self.positions[k][c] -= delta * weight_k;
+                    self.positions[other][c] += delta * weight_other;
+                }
+            }
+        }
+    }
+
+    // Breaks the constraints stretched furthest past TEAR_STRAIN, a few at a
+    // time so a tear runs across the cloth over several steps.
+    fn tear(&mut self, vertices: &mut Vec<FlagVertex>, elements: &mut Rc<Vec<GLushort>>) -> bool {
+        let mut strained = vec![];
+        for (n, c) in self.constraints.iter().enumerate() {
+            let strain = distance(&self.positions[c.a as usize], &self.positions[c.b as usize]) / c.rest;
+            if strain > TEAR_STRAIN && !(self.pinned(c.a as usize) && self.pinned(c.b as usize)) {
+                strained.push((strain, n));
+            }
+        }
+        if strained.is_empty() {
+            return false;
+        }
+        strained.sort_by(|x, y| y.0.total_cmp(&x.0));
+
+        // Particles split this step are left alone until the tear has had a
+        // step to open up.
+        let first_split = self.positions.len();
+        let mut split = vec![];
+        for &(_, n) in strained.iter().take(MAX_TEARS_PER_STEP) {
+            if self.positions.len() >= MAX_PARTICLES {
+                break;
+            }
+            let c = self.constraints[n];
+            let (a, b) = if self.pinned(c.a as usize) { (c.b as usize, c.a as usize) } else { (c.a as usize, c.b as usize) };
+            if a >= first_split || b >= first_split || split.contains(&a) || split.contains(&b) {
+                continue;
+            }
+            self.split(a, b, vertices, Rc::make_mut(elements));
+            split.push(a);
+        }
+
+        self.untether_loose_pieces();
+        true
+    }
+
+    // Splits particle `a` across the plane through it facing `b`. Whatever
+    // lies on `b`'s side moves to a new copy of `a`, and triangles left
+    // straddling the plane fray away.
+    fn split(&mut self, a: usize, b: usize, vertices: &mut Vec<FlagVertex>, elements: &mut Vec<GLushort>) {
+        let copy = self.positions.len();
+        let (p, q) = (self.positions[a], self.previous[a]);
+        self.positions.push(p);
+        self.previous.push(q);
+        let vertex = vertices[a];
+        vertices.push(vertex);
+        let origin = self.origins[a];
+        Rc::make_mut(&mut self.origins).push(origin);
+        let tether = self.tethers[a];
+        Rc::make_mut(&mut self.tethers).push(tether);
+
+        let positions = &self.positions;
+        let facing = sub(&positions[b], &p);
+        let beyond = |k: usize| vec_util::vec_dot(&sub(&positions[k], &p), &facing) > 0.0;
+
+        for c in Rc::make_mut(&mut self.constraints).iter_mut() {
+            if c.a as usize == a && beyond(c.b as usize) {
+                c.a = copy as u32;
+            } else if c.b as usize == a && beyond(c.a as usize) {
+                c.b = copy as u32;
+            }
+        }
+
+        let mut kept = 0;
+        for t in 0..elements.len() / 3 {
+            let mut triangle = [elements[3 * t], elements[3 * t + 1], elements[3 * t + 2]];
+            if let Some(corner) = triangle.iter().position(|&k| k as usize == a) {
+                let first = beyond(triangle[(corner + 1) % 3] as usize);
+                let second = beyond(triangle[(corner + 2) % 3] as usize);
+                if first != second {
+                    continue;
+                }
+                if first {
+                    triangle[corner] = copy as GLushort;
+                }
+            }
+            elements[3 * kept..3 * kept + 3].copy_from_slice(&triangle);
+            kept += 1;
+        }
+        elements.truncate(3 * kept);
+    }
+
+    // Pieces torn right off are left to blow away, rather than being held to
+    // the hoist by their tethers.
+    fn untether_loose_pieces(&mut self) {
+        let mut parents: Vec<usize> = (0..self.positions.len()).collect();
+        for c in self.constraints.iter() {
+            let (root_a, root_b) = (find_root(&mut parents, c.a as usize), find_root(&mut parents, c.b as usize));
+            parents[root_a] = root_b;
+        }
+
+        let mut held = vec![false; parents.len()];
+        for j in 0..self.hoist.len() {
+            let root = find_root(&mut parents, j * self.columns);
+            held[root] = true;
+        }
+        for k in 0..parents.len() {
+            if !held[find_root(&mut parents, k)] {
+                Rc::make_mut(&mut self.tethers)[k] = f32::INFINITY;
+            }
+        }
+    }
+
+    // Normals are summed over the triangles around each vertex, so they stay
+    // sharp along torn edges. Each triangle's normal is wound to match
+    // calculate_flag_vertex.
+    fn write_vertices(&self, vertices: &mut [FlagVertex], elements: &[GLushort]) {
+        for (v, p) in vertices.iter_mut().zip(self.positions.iter()) {
+            v.position[0] = p[0];
+            v.position[1] = p[1];
+            v.position[2] = p[2];
+            v.normal = [0.0; 4];
+        }
+
+        for triangle in elements.chunks(3) {
+            let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+            let edge1 = sub(&self.positions[b], &self.positions[a]);
+            let edge2 = sub(&self.positions[c], &self.positions[a]);
+            let mut normal = [0.0; 3];
+            vec_util::vec_cross(&mut normal, &edge2, &edge1);
+            for &k in &[a, b, c] {
+                for i in 0..3 {
+                    vertices[k].normal[i] += normal[i];
+                }
+            }
+        }
+
+        for v in vertices.iter_mut() {
+            if vec_util::vec_length(&v.normal) > 0.0 {
+                vec_util::vec_normalize(&mut v.normal);
+            }
+        }
+        mesh_builder::compute_tangents(vertices, elements);
+    }
+}
@@ -0,0 +1,173 @@
+use gl::types::GLfloat;
+use vec_util;
+
+
+#[derive(Copy, Clone, Debug)]
+pub enum Collider {
+    Sphere { center: [GLfloat; 3], radius: GLfloat },
+    // A cylinder with hemispherical ends.
+    Capsule { a: [GLfloat; 3], b: [GLfloat; 3], radius: GLfloat },
+    // A cylinder with flat ends.
+    Cylinder { a: [GLfloat; 3], b: [GLfloat; 3], radius: GLfloat },
+    // Everything behind the plane is solid.
+    Plane { point: [GLfloat; 3], normal: [GLfloat; 3] },
+}
+
+fn sub(u: &[GLfloat; 3], v: &[GLfloat; 3]) -> [GLfloat; 3] {
+    [u[0] - v[0], u[1] - v[1], u[2] - v[2]]
+}
+
+fn add_scaled(u: &[GLfloat; 3], v: &[GLfloat; 3], s: GLfloat) -> [GLfloat; 3] {
+    [u[0] + v[0] * s, u[1] + v[1] * s, u[2] + v[2] * s]
+}
+
+// Pushes `p` out along `offset` from `origin` until it lies `radius` away.
+fn push_out(p: &mut [GLfloat; 3], origin: &[GLfloat; 3], offset: &[GLfloat; 3], radius: GLfloat) -> bool {
+    let length = vec_util::vec_length(offset);
+    if length >= radius || length == 0.0 {
+        return false;
+    }
+
+    *p = add_scaled(origin, offset, radius / length);
+    true
+}
+
+impl Collider {
+    // Moves a particle of the given thickness out of the collider. Returns
+    // whether it was inside.
+    pub fn resolve(&self, p: &mut [GLfloat; 3], thickness: GLfloat) -> bool {
+        match *self {
+            Collider::Sphere { center, radius } => {
+                let offset = sub(p, &center);
+                push_out(p, &center, &offset, radius + thickness)
+            }
+            Collider::Capsule { a, b, radius } => {
+                let axis = sub(&b, &a);
+                let u = vec_util::vec_dot(&sub(p, &a), &axis) / vec_util::vec_dot(&axis, &axis);
+                let nearest = add_scaled(&a, &axis, f32::max(0.0, f32::min(u, 1.0)));
+                let offset = sub(p, &nearest);
+                push_out(p, &nearest, &offset, radius + thickness)
+            }
+            Collider::Cylinder { a, b, radius } => {
+                let axis = sub(&b, &a);
+                let length = vec_util::vec_length(&axis);
+                let direction = [axis[0] / length, axis[1] / length, axis[2] / length];
+                let along = vec_util::vec_dot(&sub(p, &a), &direction);
+                if along < -thickness || along > length + thickness {
+                    return false;
+                }
+
+                let nearest = add_scaled(&a, &direction, along);
+                let offset = sub(p, &nearest);
+                let distance = vec_util::vec_length(&offset);
+                let radius = radius + thickness;
+                if distance >= radius {
+                    return false;
+                }
+
+                // Leave by whichever of the side and the end caps is closest.
+                let side = radius - distance;
+                let bottom = along + thickness;
+                let top = length + thickness - along;
+                if side <= bottom && side <= top && distance > 0.0 {
+                    *p = add_scaled(&nearest, &offset, radius / distance);
+                } else if bottom < top {
+                    *p = add_scaled(p, &direction, -bottom);
+                } else {
+                    *p = add_scaled(p, &direction, top);
+                }
+                true
+            }
+            Collider::Plane { point, normal } => {
+                let depth = vec_util::vec_dot(&sub(p, &point), &normal) - thickness;
+                if depth >= 0.0 {
+                    return false;
+                }
+
+                *p = add_scaled(p, &normal, -depth);
+                true
+            }
+        }
+    }
+}
+
+// Buckets points into a fixed-size table by hashed grid cell, so neighbours
+// within half a cell size can be found without a pass over every pair.
+pub struct SpatialHash {
+    spacing: GLfloat,
+    cell_start: Vec<usize>,
+    entries: Vec<usize>,
+}
+
+impl SpatialHash {
+    pub fn new(spacing: GLfloat, count: usize) -> SpatialHash {
+        SpatialHash {
+            spacing: spacing,
+            cell_start: vec![0; 2 * count + 1],
+            entries: vec![0; count],
+        }
+    }
+
+    fn cell(&self, p: &[GLfloat; 3]) -> [i32; 3] {
+        [
+            (p[0] / self.spacing).floor() as i32,
+            (p[1] / self.spacing).floor() as i32,
+            (p[2] / self.spacing).floor() as i32,
+        ]
+    }
+
+    fn hash(&self, cell: [i32; 3]) -> usize {
+        let h = cell[0].wrapping_mul(92837111) ^ cell[1].wrapping_mul(689287499) ^ cell[2].wrapping_mul(283923481);
+        (h as u32 as usize) % (self.cell_start.len() - 1)
+    }
+
+    pub fn build(&mut self, positions: &[[GLfloat; 3]]) {
+        if self.entries.len() != positions.len() {
+            *self = SpatialHash::new(self.spacing, positions.len());
+        }
+
+        for start in self.cell_start.iter_mut() {
+            *start = 0;
+        }
+        for p in positions {
+            let h = self.hash(self.cell(p));
+            self.cell_start[h] += 1;
+        }
+        let mut total = 0;
+        for start in self.cell_start.iter_mut() {
+            total += *start;
+            *start = total;
+        }
+        for (k, p) in positions.iter().enumerate() {
+            let h = self.hash(self.cell(p));
+            self.cell_start[h] -= 1;
+            self.entries[self.cell_start[h]] = k;
+        }
+    }
+
+    // Collects the points in the eight cells nearest `p`, which include every
+    // point within half a cell size of it.
+    pub fn query(&self, p: &[GLfloat; 3], out: &mut Vec<usize>) {
+        out.clear();
+        let mut lo = [0; 3];
+        for c in 0..3 {
+            lo[c] = (p[c] / self.spacing - 0.5).floor() as i32;
+        }
+        let mut visited = [0; 8];
+        let mut count = 0;
+        for dx in 0..2 {
+            for dy in 0..2 {
+                for dz in 0..2 {
+                    let h = self.hash([lo[0] + dx, lo[1] + dy, lo[2] + dz]);
+                    // Neighbouring cells can share a bucket.
+                    if visited[..count].contains(&h) {
+                        continue;
+                    }
+                    visited[count] = h;
+                    count += 1;
+                    out.extend_from_slice(&self.entries[self.cell_start[h]..self.cell_start[h + 1]]);
+                }
+            }
+        }
+    }
+}
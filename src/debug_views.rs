@@ -0,0 +1,396 @@
+use cloth;
+use cloth::Constraint;
+use gl;
+use gl_debug;
+use gl::types::*;
+use gl_util;
+use meshes::{FlagMesh, FlagVertex};
+use std::ffi::CString;
+use std::mem;
+use std::os::raw;
+use std::ptr;
+use vertex_stream::VertexStream;
+
+
+const WIREFRAME_COLOR: [GLfloat; 3] = [1.0, 1.0, 1.0];
+const NORMAL_LENGTH: GLfloat = 0.05;
+// Strain at which the heatmap is fully blue, as squashed to half length. It
+// is fully red at cloth::TEAR_STRAIN.
+const MIN_STRAIN: GLfloat = 0.5;
+
+// What meshes are filled in with. Anything other than Shaded draws them with
+// the fragment shader the view names in place of the scene's.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SurfaceView {
+    Shaded,
+    Checker,
+    Texcoords,
+    Depth,
+}
+
+// The views with shaders of their own, in the order their programs are kept.
+pub const SURFACE_VIEWS: [SurfaceView; 3] = [SurfaceView::Checker, SurfaceView::Texcoords, SurfaceView::Depth];
+
+impl SurfaceView {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SurfaceView::Shaded => "shaded",
+            SurfaceView::Checker => "UV checkerboard",
+            SurfaceView::Texcoords => "texture coordinates",
+            SurfaceView::Depth => "depth",
+        }
+    }
+
+    pub fn next(&self) -> SurfaceView {
+        match *self {
+            SurfaceView::Shaded => SurfaceView::Checker,
+            SurfaceView::Checker => SurfaceView::Texcoords,
+            SurfaceView::Texcoords => SurfaceView::Depth,
+            SurfaceView::Depth => SurfaceView::Shaded,
+        }
+    }
+
+    // Where the view is in SURFACE_VIEWS.
+    pub fn index(&self) -> Option<usize> {
+        SURFACE_VIEWS.iter().position(|view| view == self)
+    }
+
+    pub fn fragment_shader(&self) -> Option<&'static str> {
+        match *self {
+            SurfaceView::Shaded => None,
+            SurfaceView::Checker => Some("shaders/debug_checker.f.glsl"),
+            SurfaceView::Texcoords => Some("shaders/debug_texcoords.f.glsl"),
+            SurfaceView::Depth => Some("shaders/debug_depth.f.glsl"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct NormalVertex {
+    position: [GLfloat; 3],
+    normal: [GLfloat; 3],
+    // 0 at the vertex and 1 at the far end of its line.
+    end: GLfloat,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct StrainVertex {
+    position: [GLfloat; 3],
+    strain: GLfloat,
+}
+
+// A program for one of the overlays, with the uniforms and attribute they
+// all have.
+struct LineProgram {
+    program: GLuint,
+    p_matrix: GLint,
+    mv_matrix: GLint,
+    position: GLint,
+}
+
+impl LineProgram {
+    fn new() -> LineProgram {
+        LineProgram { program: 0, p_matrix: 0, mv_matrix: 0, position: 0 }
+    }
+
+    // Loads shaders/debug_<name>.v.glsl and .f.glsl, leaving the program 0
+    // if they fail.
+    fn load(name: &str) -> LineProgram {
+        let mut line_program = LineProgram::new();
+        let vertex_shader = gl_util::make_shader(gl::VERTEX_SHADER, &format!("shaders/debug_{}.v.glsl", name));
+        let fragment_shader = gl_util::make_shader(gl::FRAGMENT_SHADER, &format!("shaders/debug_{}.f.glsl", name));
+        if vertex_shader == 0 || fragment_shader == 0 {
+            return line_program;
+        }
+        line_program.program = gl_util::make_program(vertex_shader, fragment_shader);
+        if line_program.program == 0 {
+            return line_program;
+        }
+        gl_debug::label(gl::PROGRAM, line_program.program, &format!("debug {}", name));
+
+        line_program.p_matrix = line_program.uniform("p_matrix");
+        line_program.mv_matrix = line_program.uniform("mv_matrix");
+        line_program.position = line_program.attribute("position");
+        line_program
+    }
+
+    // Lookups on a program that failed to load find nothing.
+    fn uniform(&self, name: &str) -> GLint {
+        if self.program == 0 {
+            return -1;
+        }
+        let name = CString::new(name).unwrap();
+        unsafe { gl::GetUniformLocation(self.program, name.as_ptr()) }
+    }
+
+    fn attribute(&self, name: &str) -> GLint {
+        if self.program == 0 {
+            return -1;
+        }
+        let name = CString::new(name).unwrap();
+        unsafe { gl::GetAttribLocation(self.program, name.as_ptr()) }
+    }
+
+    fn begin(&self, p_matrix: &[GLfloat], mv_matrix: &[GLfloat]) {
+        unsafe {
+            gl::UseProgram(self.program);
+            gl::UniformMatrix4fv(self.p_matrix, 1, gl::FALSE, p_matrix.as_ptr());
+            gl::UniformMatrix4fv(self.mv_matrix, 1, gl::FALSE, mv_matrix.as_ptr());
+            gl::EnableVertexAttribArray(self.position as GLuint);
+        }
+    }
+
+    fn end(&self) {
+        unsafe {
+            gl::DisableVertexAttribArray(self.position as GLuint);
+        }
+    }
+}
+
+// Uploads `lines`, in pairs of ends, to `buffer`.
+fn upload_lines<T>(buffer: GLuint, lines: &[T]) {
+    unsafe {
+        gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            (lines.len() * mem::size_of::<T>()) as GLsizeiptr,
+            lines.as_ptr() as *const raw::c_void,
+            gl::STREAM_DRAW
+        );
+    }
+}
+
+fn position(v: &FlagVertex) -> [GLfloat; 3] {
+    [v.position[0], v.position[1], v.position[2]]
+}
+
+// Overlays for seeing what the meshes and the cloth are up to: wireframes,
+// vertex normals and the cloth's constraints coloured by how far each is
+// stretched. Each is drawn by a small shader of its own.
+pub struct DebugViews {
+    pub surface: SurfaceView,
+    pub wireframe: bool,
+    pub normals: bool,
+    pub strain: bool,
+    wireframe_program: LineProgram,
+    wireframe_color: GLint,
+    normals_program: LineProgram,
+    normal_length: GLint,
+    normal_attribute: GLint,
+    normal_end: GLint,
+    strain_program: LineProgram,
+    tear_strain: GLint,
+    min_strain: GLint,
+    strain_attribute: GLint,
+    line_buffer: GLuint,
+    normal_lines: Vec<NormalVertex>,
+    strain_lines: Vec<StrainVertex>,
+}
+
+impl DebugViews {
+    pub fn new() -> DebugViews {
+        DebugViews {
+            surface: SurfaceView::Shaded,
+            wireframe: false,
+            normals: false,
+            strain: false,
+            wireframe_program: LineProgram::new(),
+            wireframe_color: 0,
+            normals_program: LineProgram::new(),
+            normal_length: 0,
+            normal_attribute: 0,
+            normal_end: 0,
+            strain_program: LineProgram::new(),
+            tear_strain: 0,
+            min_strain: 0,
+            strain_attribute: 0,
+            line_buffer: 0,
+            normal_lines: vec![],
+            strain_lines: vec![],
+        }
+    }
+
+    // Returns false if any of the overlays' shaders can't be loaded. Those
+    // overlays are left unavailable and the rest still work.
+    pub fn init(&mut self) -> bool {
+        self.wireframe_program = LineProgram::load("wireframe");
+        self.wireframe_color = self.wireframe_program.uniform("color");
+
+        self.normals_program = LineProgram::load("normals");
+        self.normal_length = self.normals_program.uniform("normal_length");
+        self.normal_attribute = self.normals_program.attribute("normal");
+        self.normal_end = self.normals_program.attribute("end");
+
+        self.strain_program = LineProgram::load("strain");
+        self.tear_strain = self.strain_program.uniform("tear_strain");
+        self.min_strain = self.strain_program.uniform("min_strain");
+        self.strain_attribute = self.strain_program.attribute("strain");
+
+        unsafe {
+            gl::GenBuffers(1, &mut self.line_buffer);
+        }
+        gl_debug::label(gl::BUFFER, self.line_buffer, "debug lines");
+
+        self.wireframe_program.program != 0 && self.normals_program.program != 0 && self.strain_program.program != 0
+    }
+
+    // Moves on to the next surface view that `available` says has a program,
+    // or back to Shaded.
+    pub fn cycle_surface<F>(&mut self, available: F) where F: Fn(SurfaceView) -> bool {
+        self.surface = self.surface.next();
+        while self.surface != SurfaceView::Shaded && !available(self.surface) {
+            self.surface = self.surface.next();
+        }
+        println!("Surfaces {}", self.surface.name());
+    }
+
+    pub fn toggle_wireframe(&mut self) {
+        self.wireframe = !self.wireframe && self.wireframe_program.program != 0;
+        println!("Wireframe {}", if self.wireframe { "on" } else { "off" });
+    }
+
+    pub fn toggle_normals(&mut self) {
+        self.normals = !self.normals && self.normals_program.program != 0;
+        println!("Normals {}", if self.normals { "on" } else { "off" });
+    }
+
+    pub fn toggle_strain(&mut self) {
+        self.strain = !self.strain && self.strain_program.program != 0;
+        println!("Constraint strain {}", if self.strain { "on" } else { "off" });
+    }
+
+    // Outlines every triangle of the meshes, and of the flag with its
+    // positions from `stream`, pulled a little toward the eye so the lines
+    // win the depth test against the faces they lie on.
+    pub fn render_wireframe(
+        &self, p_matrix: &[GLfloat], mv_matrix: &[GLfloat],
+        flag: &FlagMesh, stream: &VertexStream, meshes: &[&FlagMesh]
+    ) {
+        if !self.wireframe {
+            return;
+        }
+
+        let program = &self.wireframe_program;
+        program.begin(p_matrix, mv_matrix);
+        unsafe {
+            gl::Uniform3f(self.wireframe_color, WIREFRAME_COLOR[0], WIREFRAME_COLOR[1], WIREFRAME_COLOR[2]);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+            gl::Enable(gl::POLYGON_OFFSET_LINE);
+            gl::PolygonOffset(-1.0, -1.0);
+            gl::Disable(gl::CULL_FACE);
+
+            for (k, mesh) in Some(flag).into_iter().chain(meshes.iter().cloned()).enumerate() {
+                if k == 0 {
+                    stream.bind_position(program.position);
+                } else {
+                    gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vertex_buffer);
+                    gl::VertexAttribPointer(
+                        program.position as GLuint,
+                        3, gl::FLOAT, gl::FALSE, mem::size_of::<FlagVertex>() as GLint,
+                        offset_of!(FlagVertex, position) as *const raw::c_void
+                    );
+                }
+                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, mesh.element_buffer);
+                gl::DrawElements(gl::TRIANGLES, mesh.element_count, gl::UNSIGNED_SHORT, ptr::null());
+            }
+
+            gl::Enable(gl::CULL_FACE);
+            gl::Disable(gl::POLYGON_OFFSET_LINE);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+        }
+        program.end();
+    }
+
+    // A line out of each vertex along its normal, coloured by its direction.
+    // Only meshes kept on the CPU, like the flag and the rope, can be shown.
+    pub fn render_normals(&mut self, p_matrix: &[GLfloat], mv_matrix: &[GLfloat], meshes: &[&[FlagVertex]]) {
+        if !self.normals {
+            return;
+        }
+
+        self.normal_lines.clear();
+        for vertices in meshes.iter() {
+            for v in vertices.iter() {
+                let normal = [v.normal[0], v.normal[1], v.normal[2]];
+                for &end in [0.0, 1.0].iter() {
+                    self.normal_lines.push(NormalVertex { position: position(v), normal: normal, end: end });
+                }
+            }
+        }
+
+        let program = &self.normals_program;
+        let (normal, end) = (self.normal_attribute as GLuint, self.normal_end as GLuint);
+        let stride = mem::size_of::<NormalVertex>() as GLint;
+        program.begin(p_matrix, mv_matrix);
+        upload_lines(self.line_buffer, &self.normal_lines);
+        unsafe {
+            gl::Uniform1f(self.normal_length, NORMAL_LENGTH);
+            gl::EnableVertexAttribArray(normal);
+            gl::EnableVertexAttribArray(end);
+            gl::VertexAttribPointer(
+                program.position as GLuint, 3, gl::FLOAT, gl::FALSE, stride,
+                offset_of!(NormalVertex, position) as *const raw::c_void
+            );
+            gl::VertexAttribPointer(
+                normal, 3, gl::FLOAT, gl::FALSE, stride, offset_of!(NormalVertex, normal) as *const raw::c_void
+            );
+            gl::VertexAttribPointer(
+                end, 1, gl::FLOAT, gl::FALSE, stride, offset_of!(NormalVertex, end) as *const raw::c_void
+            );
+            gl::DrawArrays(gl::LINES, 0, self.normal_lines.len() as GLsizei);
+            gl::DisableVertexAttribArray(end);
+            gl::DisableVertexAttribArray(normal);
+        }
+        program.end();
+    }
+
+    // Each of the cloth's constraints between the flag vertices its particles
+    // drive, coloured by length over rest length. Drawn over everything, as
+    // the lines lie in the flag's surface.
+    pub fn render_strain(
+        &mut self, p_matrix: &[GLfloat], mv_matrix: &[GLfloat],
+        vertices: &[FlagVertex], constraints: &[Constraint]
+    ) {
+        if !self.strain {
+            return;
+        }
+
+        self.strain_lines.clear();
+        for c in constraints.iter() {
+            let (a, b) = (c.a as usize, c.b as usize);
+            if a >= vertices.len() || b >= vertices.len() {
+                continue;
+            }
+            let (pa, pb) = (position(&vertices[a]), position(&vertices[b]));
+            let d = [pb[0] - pa[0], pb[1] - pa[1], pb[2] - pa[2]];
+            let strain = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt() / c.rest;
+            self.strain_lines.push(StrainVertex { position: pa, strain: strain });
+            self.strain_lines.push(StrainVertex { position: pb, strain: strain });
+        }
+
+        let program = &self.strain_program;
+        let strain = self.strain_attribute as GLuint;
+        let stride = mem::size_of::<StrainVertex>() as GLint;
+        program.begin(p_matrix, mv_matrix);
+        upload_lines(self.line_buffer, &self.strain_lines);
+        unsafe {
+            gl::Uniform1f(self.tear_strain, cloth::TEAR_STRAIN);
+            gl::Uniform1f(self.min_strain, MIN_STRAIN);
+            gl::EnableVertexAttribArray(strain);
+            gl::VertexAttribPointer(
+                program.position as GLuint, 3, gl::FLOAT, gl::FALSE, stride,
+                offset_of!(StrainVertex, position) as *const raw::c_void
+            );
+            gl::VertexAttribPointer(
+                strain, 1, gl::FLOAT, gl::FALSE, stride, offset_of!(StrainVertex, strain) as *const raw::c_void
+            );
+            gl::Disable(gl::DEPTH_TEST);
+            gl::DrawArrays(gl::LINES, 0, self.strain_lines.len() as GLsizei);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::DisableVertexAttribArray(strain);
+        }
+        program.end();
+    }
+}
@@ -0,0 +1,108 @@
+use atlas::Image;
+use gl::types::GLfloat;
+use std::f32;
+use vec_util;
+
+
+// Threads across a tile of the weave. It has to be even for the over-under
+// pattern to meet itself at the edges.
+const WEAVE_THREADS: usize = 8;
+const WEAVE_SIZE: usize = 256;
+const WEAVE_BUMPINESS: GLfloat = 6.0;
+
+const GRAIN_SIZE: usize = 256;
+const GRAIN_BUMPINESS: GLfloat = 2.0;
+
+// A repeatable value in [0, 1) for each texel.
+fn noise(x: usize, y: usize, salt: u32) -> GLfloat {
+    let mut h = (x as u32).wrapping_mul(374761393) ^ (y as u32).wrapping_mul(668265263) ^ salt;
+    h = (h ^ (h >> 13)).wrapping_mul(1274126177);
+    h ^= h >> 16;
+
+    (h >> 8) as GLfloat / (1 << 24) as GLfloat
+}
+
+// A plain weave, with the warp running along t and the weft along s. Each
+// thread rounds over in cross-section, and rises and falls as it passes over
+// and under the threads crossing it.
+fn weave_heights() -> Vec<GLfloat> {
+    let pi = vec_util::M_PI;
+    let scale = WEAVE_THREADS as GLfloat / WEAVE_SIZE as GLfloat;
+    let mut heights = Vec::with_capacity(WEAVE_SIZE * WEAVE_SIZE);
+    for y in 0..WEAVE_SIZE {
+        for x in 0..WEAVE_SIZE {
+            let (u, v) = ((x as GLfloat + 0.5) * scale, (y as GLfloat + 0.5) * scale);
+            let (i, j) = (u.floor(), v.floor());
+            let (across_warp, across_weft) = (f32::sin(pi * (u - i)), f32::sin(pi * (v - j)));
+            let warp = across_warp * (0.65 + 0.35 * f32::cos(pi * (v - 0.5 - i)));
+            let weft = across_weft * (0.65 - 0.35 * f32::cos(pi * (u - 0.5 - j)));
+            heights.push(f32::max(warp, weft) + 0.05 * noise(x, y, 0x5eed));
+        }
+    }
+
+    heights
+}
+
+// Fine grooves running along s, like turned or brushed metal.
+fn grain_heights() -> Vec<GLfloat> {
+    let rows: Vec<GLfloat> = (0..GRAIN_SIZE).map(|y| noise(0, y, 0x9a17)).collect();
+    let mut heights = Vec::with_capacity(GRAIN_SIZE * GRAIN_SIZE);
+    for y in 0..GRAIN_SIZE {
+        let above = rows[(y + 1) % GRAIN_SIZE];
+        let below = rows[(y + GRAIN_SIZE - 1) % GRAIN_SIZE];
+        let groove = 0.25 * below + 0.5 * rows[y] + 0.25 * above;
+        for x in 0..GRAIN_SIZE {
+            heights.push(groove + 0.1 * noise(x, y, 0x17a9));
+        }
+    }
+
+    heights
+}
+
+// Tangent-space normals from the slope of a tiling height field, packed
+// into BGR as the textures are uploaded.
+fn normal_map(heights: &[GLfloat], size: usize, bumpiness: GLfloat) -> Image {
+    let height = |x: usize, y: usize| heights[(y % size) * size + x % size];
+    let mut pixels = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let ds = 0.5 * (height(x + 1, y) - height(x + size - 1, y));
+            let dt = 0.5 * (height(x, y + 1) - height(x, y + size - 1));
+            let mut n = [-bumpiness * ds, -bumpiness * dt, 1.0];
+            vec_util::vec_normalize(&mut n);
+
+            let byte = |c: GLfloat| (127.5 + 127.5 * c) as u8;
+            pixels.push([byte(n[2]), byte(n[1]), byte(n[0])]);
+        }
+    }
+
+    Image { pixels: pixels, width: size as i32, height: size as i32 }
+}
+
+fn grey_map(values: &[GLfloat], size: usize) -> Image {
+    let pixels = values.iter().map(|&value| {
+        let byte = (255.0 * f32::min(f32::max(value, 0.0), 1.0)) as u8;
+        [byte, byte, byte]
+    }).collect();
+
+    Image { pixels: pixels, width: size as i32, height: size as i32 }
+}
+
+// Normal and roughness maps for the flag's fabric. The tops of the threads
+// catch the light, and the gaps between them don't.
+pub fn weave() -> (Image, Image) {
+    let heights = weave_heights();
+    let roughness: Vec<GLfloat> = heights.iter().enumerate().map(|(k, &h)| {
+        0.95 - 0.4 * h + 0.1 * (noise(k % WEAVE_SIZE, k / WEAVE_SIZE, 0x7007) - 0.5)
+    }).collect();
+
+    (normal_map(&heights, WEAVE_SIZE, WEAVE_BUMPINESS), grey_map(&roughness, WEAVE_SIZE))
+}
+
+// Normal and roughness maps for the flagpole's metal.
+pub fn grain() -> (Image, Image) {
+    let heights = grain_heights();
+    let roughness: Vec<GLfloat> = heights.iter().map(|&h| 0.3 + 0.4 * h).collect();
+
+    (normal_map(&heights, GRAIN_SIZE, GRAIN_BUMPINESS), grey_map(&roughness, GRAIN_SIZE))
+}
@@ -0,0 +1,673 @@
+use atlas::Image;
+use gl;
+use gl::types::*;
+use gl_util;
+use std::ffi::CString;
+use std::mem;
+use std::os::raw;
+use vec_util;
+
+
+// Where the sky comes from, in order of preference: six faces named as GL
+// orders them, or one equirectangular panorama. Without either, a clear
+// day's sky is made up to stand in.
+const SKY_DIRECTORY: &str = "assets/sky";
+const SKY_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+const SKY_PANORAMA: &str = "assets/sky.tga";
+
+const GAMMA: GLfloat = 2.2;
+
+// The made-up sky is drawn from this, and panoramas are resampled to it.
+const SKY_SIZE: usize = 256;
+// Prefiltering samples from a copy of the sky no bigger than this.
+const SOURCE_SIZE: usize = 128;
+// Diffuse light changes so slowly across the sphere that a few texels do.
+const IRRADIANCE_SIZE: usize = 16;
+const SH_SOURCE_SIZE: usize = 32;
+// Each mip level down is rougher, from a mirror at the top to fully rough
+// at PREFILTERED_LEVELS - 1.
+const PREFILTERED_SIZE: usize = 64;
+const PREFILTERED_LEVELS: usize = 6;
+const PREFILTER_SAMPLES: u32 = 128;
+const BRDF_LUT_SIZE: usize = 32;
+const BRDF_LUT_SAMPLES: u32 = 256;
+// As in the shaders, so the lookup table matches them at low roughness.
+const MIN_ALPHA: GLfloat = 0.002;
+
+// The made-up sky, in linear units. The sun sits opposite the scene's light
+// but is kept small, as the shaders light it directly already.
+const ZENITH_COLOR: [GLfloat; 3] = [0.08, 0.2, 0.5];
+const HORIZON_COLOR: [GLfloat; 3] = [0.5, 0.6, 0.7];
+const GROUND_COLOR: [GLfloat; 3] = [0.12, 0.1, 0.08];
+const SUN_DIRECTION: [GLfloat; 3] = [-0.408248, 0.816497, -0.408248];
+const SUN_COLOR: [GLfloat; 3] = [20.0, 18.0, 15.0];
+const SUN_COS_RADIUS: GLfloat = 0.9997;
+const SUN_GLOW: [GLfloat; 3] = [0.6, 0.5, 0.35];
+
+type Color = [GLfloat; 3];
+
+fn scale(c: Color, k: GLfloat) -> Color {
+    [c[0] * k, c[1] * k, c[2] * k]
+}
+
+fn add(a: Color, b: Color) -> Color {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn lerp(a: Color, b: Color, t: GLfloat) -> Color {
+    add(scale(a, 1.0 - t), scale(b, t))
+}
+
+fn normalized(mut v: [GLfloat; 3]) -> [GLfloat; 3] {
+    vec_util::vec_normalize(&mut v);
+    v
+}
+
+// Cube maps follow the RenderMan convention GL took on, which has +z toward
+// the viewer. The scene has +z away from the eye, so the shaders flip z on
+// every lookup and so does this.
+fn scene_direction(d: [GLfloat; 3]) -> [GLfloat; 3] {
+    [d[0], d[1], -d[2]]
+}
+
+// The direction through (s, t) on a face, both running -1 to 1 from the
+// first texel uploaded.
+fn face_direction(face: usize, s: GLfloat, t: GLfloat) -> [GLfloat; 3] {
+    match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    }
+}
+
+// The face a direction lands on, and where on it, from 0 to 1.
+fn face_coordinates(d: [GLfloat; 3]) -> (usize, GLfloat, GLfloat) {
+    let (x, y, z) = (d[0], d[1], d[2]);
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    let (face, s, t, major) = if ax >= ay && ax >= az {
+        if x > 0.0 { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+    } else if ay >= az {
+        if y > 0.0 { (2, x, z, ay) } else { (3, x, -z, ay) }
+    } else {
+        if z > 0.0 { (4, x, -y, az) } else { (5, -x, -y, az) }
+    };
+
+    (face, 0.5 * (s / major + 1.0), 0.5 * (t / major + 1.0))
+}
+
+// Linear RGB, six faces in GL's order.
+struct CubeMap {
+    size: usize,
+    faces: Vec<Vec<Color>>,
+}
+
+impl CubeMap {
+    fn from_fn<F: Fn([GLfloat; 3]) -> Color>(size: usize, f: F) -> CubeMap {
+        let faces = (0..6).map(|face| {
+            let mut pixels = Vec::with_capacity(size * size);
+            for y in 0..size {
+                for x in 0..size {
+                    pixels.push(f(normalized(texel_direction(face, x, y, size))));
+                }
+            }
+            pixels
+        }).collect();
+
+        CubeMap { size: size, faces: faces }
+    }
+
+    fn texel(&self, face: usize, x: isize, y: isize) -> Color {
+        let clamp = |c: isize| if c < 0 { 0 } else if c >= self.size as isize { self.size - 1 } else { c as usize };
+        self.faces[face][clamp(y) * self.size + clamp(x)]
+    }
+
+    // Bilinear within a face. Across edges it clamps, which only shows on
+    // the smallest levels.
+    fn sample(&self, d: [GLfloat; 3]) -> Color {
+        let (face, s, t) = face_coordinates(d);
+        let (u, v) = (s * self.size as GLfloat - 0.5, t * self.size as GLfloat - 0.5);
+        let (x, y) = (u.floor(), v.floor());
+        let (fx, fy) = (u - x, v - y);
+        let (x, y) = (x as isize, y as isize);
+
+        lerp(
+            lerp(self.texel(face, x, y), self.texel(face, x + 1, y), fx),
+            lerp(self.texel(face, x, y + 1), self.texel(face, x + 1, y + 1), fx),
+            fy
+        )
+    }
+
+    fn downsample(&self) -> CubeMap {
+        let size = self.size / 2;
+        let faces = (0..6).map(|face| {
+            let mut pixels = Vec::with_capacity(size * size);
+            for y in 0..size as isize {
+                for x in 0..size as isize {
+                    let sum = add(
+                        add(self.texel(face, 2 * x, 2 * y), self.texel(face, 2 * x + 1, 2 * y)),
+                        add(self.texel(face, 2 * x, 2 * y + 1), self.texel(face, 2 * x + 1, 2 * y + 1))
+                    );
+                    pixels.push(scale(sum, 0.25));
+                }
+            }
+            pixels
+        }).collect();
+
+        CubeMap { size: size, faces: faces }
+    }
+
+    // Halves down to `size` where it can, resampling first if the sizes
+    // aren't powers of two apart.
+    fn shrink(&self, size: usize) -> CubeMap {
+        let mut current = if self.size % size == 0 && (self.size / size).is_power_of_two() {
+            CubeMap { size: self.size, faces: self.faces.clone() }
+        } else {
+            let mut start = size;
+            while start * 2 <= self.size {
+                start *= 2;
+            }
+            CubeMap::from_fn(start, |d| self.sample(d))
+        };
+        while current.size > size {
+            current = current.downsample();
+        }
+
+        current
+    }
+
+    fn upload(&self, target_texture: GLuint, level: GLint) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, target_texture);
+            for (face, pixels) in self.faces.iter().enumerate() {
+                gl::TexImage2D(
+                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum, level,
+                    gl::RGB16F as GLint,
+                    self.size as GLsizei, self.size as GLsizei, 0,
+                    gl::RGB, gl::FLOAT,
+                    pixels.as_ptr() as *const raw::c_void
+                );
+            }
+        }
+    }
+}
+
+fn texel_direction(face: usize, x: usize, y: usize, size: usize) -> [GLfloat; 3] {
+    let s = 2.0 * (x as GLfloat + 0.5) / size as GLfloat - 1.0;
+    let t = 2.0 * (y as GLfloat + 0.5) / size as GLfloat - 1.0;
+    face_direction(face, s, t)
+}
+
+// The solid angle a texel covers, near enough for weighting.
+fn texel_solid_angle(face_direction: [GLfloat; 3], size: usize) -> GLfloat {
+    let texel = 2.0 / size as GLfloat;
+    let length2 = vec_util::vec_dot(&face_direction, &face_direction);
+    texel * texel / (length2 * length2.sqrt())
+}
+
+fn linear(c: u8) -> GLfloat {
+    (c as GLfloat / 255.0).powf(GAMMA)
+}
+
+// A BGR bottom-up pixel from read_tga, as linear RGB.
+fn image_color(image: &Image, x: i32, y: i32) -> Color {
+    let p = image.pixels[(y * image.width + x) as usize];
+    [linear(p[2]), linear(p[1]), linear(p[0])]
+}
+
+fn load_faces() -> Result<CubeMap, String> {
+    let mut faces = Vec::with_capacity(6);
+    let mut size = 0;
+    for name in SKY_FACES.iter() {
+        let filename = format!("{}/{}.tga", SKY_DIRECTORY, name);
+        let image = try!(Image::load(&filename).map_err(|e| format!("{}: {}", filename, e)));
+        if image.width != image.height || (size != 0 && image.width != size) {
+            return Err(format!("{} isn't square and the same size as the other faces", filename));
+        }
+        size = image.width;
+
+        // Faces are drawn top row first.
+        let mut pixels = Vec::with_capacity((size * size) as usize);
+        for y in (0..size).rev() {
+            for x in 0..size {
+                pixels.push(image_color(&image, x, y));
+            }
+        }
+        faces.push(pixels);
+    }
+
+    Ok(CubeMap { size: size as usize, faces: faces })
+}
+
+fn load_panorama() -> Result<CubeMap, String> {
+    let image = try!(Image::load(SKY_PANORAMA).map_err(|e| format!("{}: {}", SKY_PANORAMA, e)));
+    let (width, height) = (image.width, image.height);
+    let pi = vec_util::M_PI;
+
+    Ok(CubeMap::from_fn(SKY_SIZE, |d| {
+        // Longitude wraps and latitude clamps. Rows run bottom up.
+        let u = (0.5 + d[0].atan2(-d[2]) / (2.0 * pi)) * width as GLfloat - 0.5;
+        let v = (1.0 - d[1].max(-1.0).min(1.0).acos() / pi) * height as GLfloat - 0.5;
+        let (x, y) = (u.floor(), v.floor());
+        let (fx, fy) = (u - x, v - y);
+        let column = |x: i32| ((x % width) + width) % width;
+        let row = |y: i32| if y < 0 { 0 } else if y >= height { height - 1 } else { y };
+        let (x, y) = (x as i32, y as i32);
+        let pixel = |x: i32, y: i32| image_color(&image, column(x), row(y));
+
+        lerp(
+            lerp(pixel(x, y), pixel(x + 1, y), fx),
+            lerp(pixel(x, y + 1), pixel(x + 1, y + 1), fx),
+            fy
+        )
+    }))
+}
+
+fn made_up_sky() -> CubeMap {
+    CubeMap::from_fn(SKY_SIZE, |d| {
+        let d = scene_direction(d);
+        let mut color = if d[1] >= 0.0 {
+            lerp(HORIZON_COLOR, ZENITH_COLOR, d[1].sqrt())
+        } else {
+            lerp(HORIZON_COLOR, GROUND_COLOR, (-8.0 * d[1]).min(1.0))
+        };
+
+        let sun = vec_util::vec_dot(&d, &SUN_DIRECTION);
+        if sun > SUN_COS_RADIUS {
+            color = SUN_COLOR;
+        } else if sun > 0.0 {
+            color = add(color, scale(SUN_GLOW, sun.powf(64.0)));
+        }
+
+        color
+    })
+}
+
+fn load_sky() -> CubeMap {
+    match load_faces() {
+        Ok(sky) => return sky,
+        Err(e) => println!("No sky faces ({}), trying a panorama", e),
+    }
+    match load_panorama() {
+        Ok(sky) => return sky,
+        Err(e) => println!("No sky panorama ({}), making one up", e),
+    }
+
+    made_up_sky()
+}
+
+// Irradiance over pi, so a diffuse surface reflects its albedo times this,
+// from the sky's first nine spherical harmonics.
+fn irradiance(sky: &CubeMap) -> CubeMap {
+    let source = sky.shrink(SH_SOURCE_SIZE);
+    let basis = |d: [GLfloat; 3]| [
+        0.282095,
+        0.488603 * d[1], 0.488603 * d[2], 0.488603 * d[0],
+        1.092548 * d[0] * d[1], 1.092548 * d[1] * d[2],
+        0.315392 * (3.0 * d[2] * d[2] - 1.0),
+        1.092548 * d[0] * d[2], 0.546274 * (d[0] * d[0] - d[1] * d[1]),
+    ];
+
+    let mut coefficients = [[0.0 as GLfloat; 3]; 9];
+    for face in 0..6 {
+        for y in 0..source.size {
+            for x in 0..source.size {
+                let direction = texel_direction(face, x, y, source.size);
+                let weight = texel_solid_angle(direction, source.size);
+                let color = source.faces[face][y * source.size + x];
+                for (c, y_lm) in coefficients.iter_mut().zip(basis(normalized(direction)).iter()) {
+                    *c = add(*c, scale(color, y_lm * weight));
+                }
+            }
+        }
+    }
+
+    // The cosine lobe's convolution per band, over pi.
+    let bands = [1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25];
+    CubeMap::from_fn(IRRADIANCE_SIZE, |d| {
+        let mut e = [0.0; 3];
+        for ((c, y_lm), band) in coefficients.iter().zip(basis(d).iter()).zip(bands.iter()) {
+            e = add(e, scale(*c, y_lm * band));
+        }
+        // Ringing can take the harmonics below zero behind bright spots.
+        [e[0].max(0.0), e[1].max(0.0), e[2].max(0.0)]
+    })
+}
+
+fn radical_inverse(mut bits: u32) -> GLfloat {
+    bits = bits.rotate_right(16);
+    bits = ((bits & 0x55555555) << 1) | ((bits & 0xAAAAAAAA) >> 1);
+    bits = ((bits & 0x33333333) << 2) | ((bits & 0xCCCCCCCC) >> 2);
+    bits = ((bits & 0x0F0F0F0F) << 4) | ((bits & 0xF0F0F0F0) >> 4);
+    bits = ((bits & 0x00FF00FF) << 8) | ((bits & 0xFF00FF00) >> 8);
+    bits as GLfloat / 4294967296.0
+}
+
+fn hammersley(i: u32, n: u32) -> (GLfloat, GLfloat) {
+    (i as GLfloat / n as GLfloat, radical_inverse(i))
+}
+
+// Turns a direction about +z into one about `n`.
+fn about(n: [GLfloat; 3], v: [GLfloat; 3]) -> [GLfloat; 3] {
+    let up = if n[2].abs() < 0.999 { [0.0, 0.0, 1.0] } else { [1.0, 0.0, 0.0] };
+    let mut tangent = [0.0; 3];
+    vec_util::vec_cross(&mut tangent, &up, &n);
+    let tangent = normalized(tangent);
+    let mut bitangent = [0.0; 3];
+    vec_util::vec_cross(&mut bitangent, &n, &tangent);
+
+    [
+        tangent[0] * v[0] + bitangent[0] * v[1] + n[0] * v[2],
+        tangent[1] * v[0] + bitangent[1] * v[1] + n[1] * v[2],
+        tangent[2] * v[0] + bitangent[2] * v[1] + n[2] * v[2],
+    ]
+}
+
+// A half vector about +z, spread as GGX spreads them.
+fn sample_ggx((u, v): (GLfloat, GLfloat), alpha: GLfloat) -> [GLfloat; 3] {
+    let phi = 2.0 * vec_util::M_PI * u;
+    let cos_theta = ((1.0 - v) / (1.0 + (alpha * alpha - 1.0) * v)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta]
+}
+
+fn d_ggx(n_dot_h: GLfloat, alpha: GLfloat) -> GLfloat {
+    let alpha2 = alpha * alpha;
+    let f = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (vec_util::M_PI * f * f)
+}
+
+// The sky blurred by GGX lobes of increasing roughness, one per mip level,
+// taking the view to be along the normal. Samples read from a blurrier copy
+// of the sky where they're sparse, which keeps the result from speckling.
+fn prefilter(sky: &CubeMap) -> Vec<CubeMap> {
+    let mut chain = vec![sky.shrink(SOURCE_SIZE)];
+    while chain[chain.len() - 1].size > 1 {
+        let next = chain[chain.len() - 1].downsample();
+        chain.push(next);
+    }
+    let source_texel_angle = 4.0 * vec_util::M_PI / (6.0 * (SOURCE_SIZE * SOURCE_SIZE) as GLfloat);
+    let sample_lod = |d: [GLfloat; 3], lod: GLfloat| {
+        let lod = lod.max(0.0).min((chain.len() - 1) as GLfloat);
+        let (level, t) = (lod.floor() as usize, lod - lod.floor());
+        let lo = chain[level].sample(d);
+        if level + 1 < chain.len() { lerp(lo, chain[level + 1].sample(d), t) } else { lo }
+    };
+
+    (0..PREFILTERED_LEVELS).map(|level| {
+        let size = PREFILTERED_SIZE >> level;
+        if level == 0 {
+            let lod = (SOURCE_SIZE as GLfloat / size as GLfloat).log2();
+            return CubeMap::from_fn(size, |d| sample_lod(d, lod));
+        }
+
+        let roughness = level as GLfloat / (PREFILTERED_LEVELS - 1) as GLfloat;
+        let alpha = (roughness * roughness).max(MIN_ALPHA);
+        CubeMap::from_fn(size, |n| {
+            let mut sum = [0.0; 3];
+            let mut weight = 0.0;
+            for i in 0..PREFILTER_SAMPLES {
+                let h = about(n, sample_ggx(hammersley(i, PREFILTER_SAMPLES), alpha));
+                let n_dot_h = vec_util::vec_dot(&n, &h);
+                let l = [
+                    2.0 * n_dot_h * h[0] - n[0],
+                    2.0 * n_dot_h * h[1] - n[1],
+                    2.0 * n_dot_h * h[2] - n[2],
+                ];
+                let n_dot_l = vec_util::vec_dot(&n, &l);
+                if n_dot_l <= 0.0 {
+                    continue;
+                }
+
+                // With the view along the normal, the pdf of l is D / 4.
+                let pdf = d_ggx(n_dot_h, alpha) / 4.0;
+                let sample_angle = 1.0 / (PREFILTER_SAMPLES as GLfloat * pdf + 1.0e-4);
+                let lod = 0.5 * (sample_angle / source_texel_angle).log2() + 1.0;
+                sum = add(sum, scale(sample_lod(l, lod), n_dot_l));
+                weight += n_dot_l;
+            }
+            scale(sum, 1.0 / weight.max(1.0e-4))
+        })
+    }).collect()
+}
+
+fn v_smith_ggx(n_dot_v: GLfloat, n_dot_l: GLfloat, alpha: GLfloat) -> GLfloat {
+    let alpha2 = alpha * alpha;
+    let view = n_dot_l * (n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2).sqrt();
+    let light = n_dot_v * (n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2).sqrt();
+    0.5 / (view + light)
+}
+
+fn d_charlie(n_dot_h: GLfloat, alpha: GLfloat) -> GLfloat {
+    let inverse_alpha = 1.0 / alpha;
+    let sin2 = 1.0 - n_dot_h * n_dot_h;
+    (2.0 + inverse_alpha) * sin2.powf(0.5 * inverse_alpha) / (2.0 * vec_util::M_PI)
+}
+
+fn v_neubelt(n_dot_v: GLfloat, n_dot_l: GLfloat) -> GLfloat {
+    1.0 / (4.0 * (n_dot_l + n_dot_v - n_dot_l * n_dot_v))
+}
+
+// The rest of the split sum, by n.v along s and roughness along t: the scale
+// and bias on f0 for the GGX lobe, and the albedo of the cloth sheen lobe.
+fn brdf_lut() -> Vec<Color> {
+    let pi = vec_util::M_PI;
+    let mut texels = Vec::with_capacity(BRDF_LUT_SIZE * BRDF_LUT_SIZE);
+    for j in 0..BRDF_LUT_SIZE {
+        let roughness = (j as GLfloat + 0.5) / BRDF_LUT_SIZE as GLfloat;
+        let alpha = (roughness * roughness).max(MIN_ALPHA);
+        for i in 0..BRDF_LUT_SIZE {
+            let n_dot_v = (i as GLfloat + 0.5) / BRDF_LUT_SIZE as GLfloat;
+            let v = [(1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v];
+            let (mut scale_f0, mut bias, mut sheen) = (0.0, 0.0, 0.0);
+
+            for k in 0..BRDF_LUT_SAMPLES {
+                let xi = hammersley(k, BRDF_LUT_SAMPLES);
+
+                let h = sample_ggx(xi, alpha);
+                let v_dot_h = vec_util::vec_dot(&v, &h);
+                let n_dot_l = 2.0 * v_dot_h * h[2] - v[2];
+                if n_dot_l > 0.0 && v_dot_h > 0.0 {
+                    let g_vis = 4.0 * v_smith_ggx(n_dot_v, n_dot_l, alpha) * n_dot_l * v_dot_h / h[2];
+                    let fc = (1.0 - v_dot_h).powi(5);
+                    scale_f0 += (1.0 - fc) * g_vis;
+                    bias += fc * g_vis;
+                }
+
+                // Sheen goes everywhere, so sample the hemisphere evenly.
+                let (phi, cos_theta) = (2.0 * pi * xi.0, 1.0 - xi.1);
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let l = [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta];
+                let h = normalized(add(v, l));
+                sheen += d_charlie(h[2], alpha) * v_neubelt(n_dot_v, l[2]) * l[2];
+            }
+
+            let n = BRDF_LUT_SAMPLES as GLfloat;
+            texels.push([scale_f0 / n, bias / n, sheen * 2.0 * pi / n]);
+        }
+    }
+
+    texels
+}
+
+fn make_cube_texture(min_filter: GLenum) -> GLuint {
+    let mut texture = 0;
+    unsafe {
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, min_filter as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+    }
+
+    texture
+}
+
+struct Uniforms {
+    p_matrix: GLint,
+    mv_matrix: GLint,
+    sky: GLint,
+}
+
+// The sky, drawn behind everything, and the maps the flag shaders light
+// with from it: diffuse irradiance, specular prefiltered by roughness and the
+// lookup table for the split sum.
+pub struct Environment {
+    pub irradiance_map: GLuint,
+    pub prefiltered_map: GLuint,
+    pub brdf_lut: GLuint,
+    // The mip level of prefiltered_map for full roughness.
+    pub max_lod: GLfloat,
+    sky_map: GLuint,
+    vertex_buffer: GLuint,
+    program: GLuint,
+    uniforms: Uniforms,
+    corner: GLint,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment {
+            irradiance_map: 0,
+            prefiltered_map: 0,
+            brdf_lut: 0,
+            max_lod: 0.0,
+            sky_map: 0,
+            vertex_buffer: 0,
+            program: 0,
+            uniforms: Uniforms {
+                p_matrix: 0,
+                mv_matrix: 0,
+                sky: 0,
+            },
+            corner: 0,
+        }
+    }
+
+    // The maps are always made, whatever the sky. Returns false if the sky
+    // itself can't be drawn for want of its shaders.
+    pub fn init(&mut self) -> bool {
+        let sky = load_sky();
+
+        self.sky_map = make_cube_texture(gl::LINEAR_MIPMAP_LINEAR);
+        sky.upload(self.sky_map, 0);
+        unsafe {
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+        }
+
+        self.irradiance_map = make_cube_texture(gl::LINEAR);
+        irradiance(&sky).upload(self.irradiance_map, 0);
+
+        self.prefiltered_map = make_cube_texture(gl::LINEAR_MIPMAP_LINEAR);
+        for (level, map) in prefilter(&sky).iter().enumerate() {
+            map.upload(self.prefiltered_map, level as GLint);
+        }
+        self.max_lod = (PREFILTERED_LEVELS - 1) as GLfloat;
+        unsafe {
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAX_LEVEL, self.max_lod as GLint);
+        }
+
+        let lut = brdf_lut();
+        unsafe {
+            gl::GenTextures(1, &mut self.brdf_lut);
+            gl::BindTexture(gl::TEXTURE_2D, self.brdf_lut);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0,
+                gl::RGB16F as GLint,
+                BRDF_LUT_SIZE as GLsizei, BRDF_LUT_SIZE as GLsizei, 0,
+                gl::RGB, gl::FLOAT,
+                lut.as_ptr() as *const raw::c_void
+            );
+        }
+
+        let vertex_shader = gl_util::make_shader(gl::VERTEX_SHADER, "shaders/sky.v.glsl");
+        let fragment_shader = gl_util::make_shader(gl::FRAGMENT_SHADER, "shaders/sky.f.glsl");
+        if vertex_shader == 0 || fragment_shader == 0 {
+            return false;
+        }
+        self.program = gl_util::make_program(vertex_shader, fragment_shader);
+        if self.program == 0 {
+            return false;
+        }
+
+        // One triangle over the whole screen.
+        let corners: [GLfloat; 6] = [-1.0, -1.0, 3.0, -1.0, -1.0, 3.0];
+        unsafe {
+            gl::GenBuffers(1, &mut self.vertex_buffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(&corners) as GLsizeiptr,
+                corners.as_ptr() as *const raw::c_void,
+                gl::STATIC_DRAW
+            );
+
+            let uniform = |name: &str| {
+                let name = CString::new(name).unwrap();
+                gl::GetUniformLocation(self.program, name.as_ptr())
+            };
+            self.uniforms = Uniforms {
+                p_matrix: uniform("p_matrix"),
+                mv_matrix: uniform("mv_matrix"),
+                sky: uniform("sky"),
+            };
+            let corner_cstr = CString::new("corner").unwrap();
+            self.corner = gl::GetAttribLocation(self.program, corner_cstr.as_ptr());
+        }
+
+        true
+    }
+
+    // Binds the lighting maps to `first_unit` and the two units after it,
+    // in the order irradiance, prefiltered, lookup table.
+    pub fn bind_maps(&self, first_unit: GLenum) {
+        unsafe {
+            gl_check!(gl::ActiveTexture(first_unit));
+            gl_check!(gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.irradiance_map));
+            gl_check!(gl::ActiveTexture(first_unit + 1));
+            gl_check!(gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.prefiltered_map));
+            gl_check!(gl::ActiveTexture(first_unit + 2));
+            gl_check!(gl::BindTexture(gl::TEXTURE_2D, self.brdf_lut));
+            gl_check!(gl::ActiveTexture(gl::TEXTURE0));
+        }
+    }
+
+    // Fills the background, leaving depth alone so anything drawn after
+    // lands in front.
+    pub fn render_sky(&self, p_matrix: &[GLfloat], mv_matrix: &[GLfloat]) {
+        if self.program == 0 {
+            return;
+        }
+
+        unsafe {
+            gl::UseProgram(self.program);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.sky_map);
+            gl::Uniform1i(self.uniforms.sky, 0);
+            gl::UniformMatrix4fv(self.uniforms.p_matrix, 1, gl::FALSE, p_matrix.as_ptr());
+            gl::UniformMatrix4fv(self.uniforms.mv_matrix, 1, gl::FALSE, mv_matrix.as_ptr());
+            gl::Disable(gl::DEPTH_TEST);
+            gl::DepthMask(gl::FALSE);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::EnableVertexAttribArray(self.corner as GLuint);
+            gl::VertexAttribPointer(
+                self.corner as GLuint, 2, gl::FLOAT, gl::FALSE,
+                mem::size_of::<[GLfloat; 2]>() as GLint, 0 as *const raw::c_void
+            );
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::DisableVertexAttribArray(self.corner as GLuint);
+
+            gl::DepthMask(gl::TRUE);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use gl::types::*;
+use meshes;
+use meshes::FlagVertex;
+use file_util;
+use flagpole::FlagpoleProfile;
+use png;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::io;
+use std::path::Path;
+use std::f32;
+
+
+const FLAG_TEXTURE: &str = "assets/flag.tga";
+const BACKGROUND_TEXTURE: &str = "assets/background.tga";
+
+// Both of the flag's wave terms repeat every four seconds, so the default
+// range makes a seamless loop.
+const DEFAULT_END: GLfloat = 4.0;
+const DEFAULT_FPS: GLfloat = 30.0;
+
+pub const USAGE: &str
+    = "usage: flag export <obj|gltf|gltf-frames> [--start SECONDS] [--end SECONDS] [--fps RATE] [--output PATH]";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExportFormat {
+    ObjSequence,
+    GltfMorphTargets,
+    GltfFrames,
+}
+
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub start: GLfloat,
+    pub end: GLfloat,
+    pub fps: GLfloat,
+    pub output: String,
+}
+
+impl ExportOptions {
+    pub fn parse(args: &[String]) -> Result<ExportOptions, String> {
+        let format = match args.first().map(|s| s.as_str()) {
+            Some("obj") => ExportFormat::ObjSequence,
+            Some("gltf") => ExportFormat::GltfMorphTargets,
+            Some("gltf-frames") => ExportFormat::GltfFrames,
+            Some(other) => return Err(format!("unknown export format '{}'", other)),
+            None => return Err(String::from("missing export format")),
+        };
+
+        let mut options = ExportOptions {
+            format: format,
+            start: 0.0,
+            end: DEFAULT_END,
+            fps: DEFAULT_FPS,
+            output: String::from(match format {
+                ExportFormat::ObjSequence => "flag_export",
+                _ => "flag.glb",
+            }),
+        };
+
+        let mut rest = args[1..].iter();
+        while let Some(flag) = rest.next() {
+            let value = match rest.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {}", flag)),
+            };
+            let number = || value.parse::<GLfloat>().map_err(|_| format!("invalid value '{}' for {}", value, flag));
+            match flag.as_str() {
+                "--start" => options.start = try!(number()),
+                "--end" => options.end = try!(number()),
+                "--fps" => options.fps = try!(number()),
+                "--output" => options.output = value.clone(),
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+
+        if !(options.fps > 0.0) || !(options.end >= options.start) {
+            return Err(String::from("the time range must be non-empty with a positive frame rate"));
+        }
+
+        Ok(options)
+    }
+}
+
+struct Frame {
+    time: GLfloat,
+    vertices: Vec<FlagVertex>,
+}
+
+fn sample_frames(options: &ExportOptions) -> Vec<Frame> {
+    let (base, _) = meshes::build_flag_mesh();
+    let count = f32::floor((options.end - options.start) * options.fps + 1.0e-3) as usize + 1;
+
+    (0..count).map(|k| {
+        let time = options.start + (k as GLfloat) / options.fps;
+        let mut vertices = base.clone();
+        meshes::calculate_flag_mesh(&mut vertices, time, 0.0);
+        Frame { time: time, vertices: vertices }
+    }).collect()
+}
+
+fn load_png(filename: &str) -> io::Result<Vec<u8>> {
+    let (pixels, height, width) = try!(file_util::read_tga(filename));
+    let rgb = png::rgb_from_tga(&pixels, width as usize, height as usize);
+
+    Ok(png::encode_png(width as usize, height as usize, &rgb))
+}
+
+pub fn export(options: &ExportOptions, flagpole: &FlagpoleProfile) -> io::Result<()> {
+    let frames = sample_frames(options);
+    println!("exporting {} frames to {}", frames.len(), options.output);
+
+    match options.format {
+        ExportFormat::ObjSequence => export_obj_sequence(options, &frames, flagpole),
+        ExportFormat::GltfMorphTargets | ExportFormat::GltfFrames => export_glb(options, &frames, flagpole),
+    }
+}
+
+// The scene is left-handed, so z is mirrored on the way out. That also turns
+// our clockwise front faces into the counter-clockwise ones OBJ and glTF use.
+fn write_obj_object<W: Write>(
+    out: &mut W, name: &str, vertices: &[FlagVertex], elements: &[GLushort], base: usize
+) -> io::Result<()> {
+    try!(writeln!(out, "o {}", name));
+    for v in vertices {
+        try!(writeln!(out, "v {} {} {}", v.position[0], v.position[1], -v.position[2]));
+    }
+    for v in vertices {
+        try!(writeln!(out, "vt {} {}", v.texcoord[0], v.texcoord[1]));
+    }
+    for v in vertices {
+        try!(writeln!(out, "vn {} {} {}", v.normal[0], v.normal[1], -v.normal[2]));
+    }
+
+    try!(writeln!(out, "usemtl {}", name));
+    for triangle in elements.chunks(3) {
+        let (a, b, c) = (
+            base + triangle[0] as usize, base + triangle[1] as usize, base + triangle[2] as usize
+        );
+        try!(writeln!(out, "f {}/{}/{} {}/{}/{} {}/{}/{}", a, a, a, b, b, b, c, c, c));
+    }
+
+    Ok(())
+}
+
+fn export_obj_sequence(options: &ExportOptions, frames: &[Frame], flagpole: &FlagpoleProfile) -> io::Result<()> {
+    let directory = Path::new(&options.output);
+    try!(fs::create_dir_all(directory));
+
+    try!(fs::write(directory.join("flag.png"), try!(load_png(FLAG_TEXTURE))));
+    try!(fs::write(directory.join("background.png"), try!(load_png(BACKGROUND_TEXTURE))));
+    {
+        let mut mtl = BufWriter::new(try!(File::create(directory.join("flag.mtl"))));
+        try!(writeln!(mtl, "newmtl flag\nKd 1 1 1\nKs 0 0 0\nNs 0\nmap_Kd flag.png\n"));
+        try!(writeln!(mtl, "newmtl background\nKd 1 1 1\nKs 0 0 0\nNs 0\nmap_Kd background.png"));
+    }
+
+    let (_, flag_elements) = meshes::build_flag_mesh();
+    let background = meshes::build_background_mesh(flagpole);
+
+    for (k, frame) in frames.iter().enumerate() {
+        let filename = directory.join(format!("flag_{:04}.obj", k));
+        let mut out = BufWriter::new(try!(File::create(filename)));
+        try!(writeln!(out, "# frame {} at {} seconds", k, frame.time));
+        try!(writeln!(out, "mtllib flag.mtl"));
+        try!(write_obj_object(&mut out, "flag", &frame.vertices, &flag_elements, 1));
+        try!(write_obj_object(
+            &mut out, "background", &background.vertices, &background.elements,
+            1 + frame.vertices.len()
+        ));
+    }
+
+    Ok(())
+}
+
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_SHORT: u32 = 5123;
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+// Collects the binary chunk of a GLB file along with the JSON describing its
+// buffer views and accessors.
+struct GltfBuffer {
+    bin: Vec<u8>,
+    buffer_views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl GltfBuffer {
+    fn view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        while self.bin.len() % 4 != 0 {
+            self.bin.push(0);
+        }
+        let target = match target {
+            Some(target) => format!(",\"target\":{}", target),
+            None => String::new(),
+        };
+        self.buffer_views.push(format!(
+            "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{}{}}}", self.bin.len(), bytes.len(), target
+        ));
+        self.bin.extend_from_slice(bytes);
+
+        self.buffer_views.len() - 1
+    }
+
+    fn accessor(
+        &mut self, view: usize, component_type: u32, count: usize, kind: &str, bounds: String
+    ) -> usize {
+        self.accessors.push(format!(
+            "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"{}\"{}}}",
+            view, component_type, count, kind, bounds
+        ));
+
+        self.accessors.len() - 1
+    }
+
+    fn float_accessor(
+        &mut self, data: &[GLfloat], width: usize, kind: &str, with_bounds: bool, target: Option<u32>
+    ) -> usize {
+        let mut bytes = Vec::with_capacity(4 * data.len());
+        for x in data {
+            bytes.extend_from_slice(&x.to_bits().to_le_bytes());
+        }
+
+        let bounds = if with_bounds {
+            let mut lo = vec![f32::INFINITY; width];
+            let mut hi = vec![f32::NEG_INFINITY; width];
+            for element in data.chunks(width) {
+                for i in 0..width {
+                    lo[i] = f32::min(lo[i], element[i]);
+                    hi[i] = f32::max(hi[i], element[i]);
+                }
+            }
+            format!(",\"min\":{:?},\"max\":{:?}", lo, hi)
+        } else {
+            String::new()
+        };
+
+        let view = self.view(&bytes, target);
+        self.accessor(view, GLTF_FLOAT, data.len() / width, kind, bounds)
+    }
+
+    fn floats(&mut self, data: &[GLfloat], width: usize, kind: &str, with_bounds: bool) -> usize {
+        self.float_accessor(data, width, kind, with_bounds, Some(GLTF_ARRAY_BUFFER))
+    }
+
+    // Animation data is not vertex data, so its view carries no target.
+    fn animation_floats(&mut self, data: &[GLfloat], width: usize, kind: &str, with_bounds: bool) -> usize {
+        self.float_accessor(data, width, kind, with_bounds, None)
+    }
+
+    fn indices(&mut self, data: &[GLushort]) -> usize {
+        let mut bytes = Vec::with_capacity(2 * data.len());
+        for x in data {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+
+        let view = self.view(&bytes, Some(GLTF_ELEMENT_ARRAY_BUFFER));
+        self.accessor(view, GLTF_UNSIGNED_SHORT, data.len(), "SCALAR", String::new())
+    }
+}
+
+fn gltf_positions(vertices: &[FlagVertex]) -> Vec<GLfloat> {
+    vertices.iter().flat_map(|v| vec![v.position[0], v.position[1], -v.position[2]]).collect()
+}
+
+fn gltf_normals(vertices: &[FlagVertex]) -> Vec<GLfloat> {
+    vertices.iter().flat_map(|v| vec![v.normal[0], v.normal[1], -v.normal[2]]).collect()
+}
+
+// Texture rows are flipped upright when encoded, so t is flipped to match.
+fn gltf_texcoords(vertices: &[FlagVertex]) -> Vec<GLfloat> {
+    vertices.iter().flat_map(|v| vec![v.texcoord[0], 1.0 - v.texcoord[1]]).collect()
+}
+
+fn difference(a: &[GLfloat], b: &[GLfloat]) -> Vec<GLfloat> {
+    a.iter().zip(b.iter()).map(|(x, y)| x - y).collect()
+}
+
+fn join(items: &[String]) -> String {
+    items.join(",")
+}
+
+fn export_glb(options: &ExportOptions, frames: &[Frame], flagpole: &FlagpoleProfile) -> io::Result<()> {
+    let mut buffer = GltfBuffer { bin: vec![], buffer_views: vec![], accessors: vec![] };
+
+    let flag_image = buffer.view(&try!(load_png(FLAG_TEXTURE)), None);
+    let background_image = buffer.view(&try!(load_png(BACKGROUND_TEXTURE)), None);
+
+    let (_, flag_elements) = meshes::build_flag_mesh();
+    let flag_indices = buffer.indices(&flag_elements);
+    let flag_texcoords = buffer.floats(&gltf_texcoords(&frames[0].vertices), 2, "VEC2", false);
+
+    let background = meshes::build_background_mesh(flagpole);
+    let background_primitive = format!(
+        "{{\"attributes\":{{\"POSITION\":{},\"NORMAL\":{},\"TEXCOORD_0\":{}}},\"indices\":{},\"material\":1}}",
+        buffer.floats(&gltf_positions(&background.vertices), 3, "VEC3", true),
+        buffer.floats(&gltf_normals(&background.vertices), 3, "VEC3", false),
+        buffer.floats(&gltf_texcoords(&background.vertices), 2, "VEC2", false),
+        buffer.indices(&background.elements)
+    );
+
+    let times: Vec<GLfloat> = frames.iter().map(|f| f.time - options.start).collect();
+    let time_accessor = buffer.animation_floats(&times, 1, "SCALAR", true);
+
+    let mut meshes_json = vec![format!("{{\"name\":\"background\",\"primitives\":[{}]}}", background_primitive)];
+    let mut nodes = vec![String::from("{\"name\":\"background\",\"mesh\":0}")];
+    let mut channels = vec![];
+    let mut samplers = vec![];
+
+    match options.format {
+        ExportFormat::GltfMorphTargets => {
+            // Every frame becomes a morph target of the first, and the
+            // animation weights each one fully in turn.
+            let base_positions = gltf_positions(&frames[0].vertices);
+            let base_normals = gltf_normals(&frames[0].vertices);
+            let targets: Vec<String> = frames.iter().map(|frame| {
+                let positions = difference(&gltf_positions(&frame.vertices), &base_positions);
+                let normals = difference(&gltf_normals(&frame.vertices), &base_normals);
+                format!(
+                    "{{\"POSITION\":{},\"NORMAL\":{}}}",
+                    buffer.floats(&positions, 3, "VEC3", true),
+                    buffer.floats(&normals, 3, "VEC3", false)
+                )
+            }).collect();
+
+            let mut weights = vec![0.0 as GLfloat; frames.len() * frames.len()];
+            for k in 0..frames.len() {
+                weights[k * frames.len() + k] = 1.0;
+            }
+            let weight_accessor = buffer.animation_floats(&weights, 1, "SCALAR", false);
+            let initial_weights: Vec<String>
+                = (0..frames.len()).map(|k| String::from(if k == 0 { "1" } else { "0" })).collect();
+
+            meshes_json.push(format!(
+                "{{\"name\":\"flag\",\"primitives\":[{{\"attributes\":{{\"POSITION\":{},\"NORMAL\":{},\"TEXCOORD_0\":{}}},\
+                 \"indices\":{},\"material\":0,\"targets\":[{}]}}],\"weights\":[{}]}}",
+                buffer.floats(&base_positions, 3, "VEC3", true),
+                buffer.floats(&base_normals, 3, "VEC3", false),
+                flag_texcoords, flag_indices, join(&targets), join(&initial_weights)
+            ));
+            nodes.push(String::from("{\"name\":\"flag\",\"mesh\":1}"));
+            samplers.push(format!(
+                "{{\"input\":{},\"output\":{},\"interpolation\":\"LINEAR\"}}", time_accessor, weight_accessor
+            ));
+            channels.push(String::from("{\"sampler\":0,\"target\":{\"node\":1,\"path\":\"weights\"}}"));
+        }
+        _ => {
+            // One mesh per frame, each shown only for its own frame by
+            // stepping its node's scale between zero and one.
+            for (k, frame) in frames.iter().enumerate() {
+                meshes_json.push(format!(
+                    "{{\"name\":\"flag_{:04}\",\"primitives\":[{{\"attributes\":{{\"POSITION\":{},\"NORMAL\":{},\"TEXCOORD_0\":{}}},\
+                     \"indices\":{},\"material\":0}}]}}",
+                    k,
+                    buffer.floats(&gltf_positions(&frame.vertices), 3, "VEC3", true),
+                    buffer.floats(&gltf_normals(&frame.vertices), 3, "VEC3", false),
+                    flag_texcoords, flag_indices
+                ));
+
+                let scale = if k == 0 { "" } else { ",\"scale\":[0,0,0]" };
+                nodes.push(format!("{{\"name\":\"flag_{:04}\",\"mesh\":{}{}}}", k, k + 1, scale));
+
+                let mut scales = vec![0.0 as GLfloat; 3 * frames.len()];
+                for i in 0..3 {
+                    scales[3 * k + i] = 1.0;
+                }
+                let scale_accessor = buffer.animation_floats(&scales, 3, "VEC3", false);
+                samplers.push(format!(
+                    "{{\"input\":{},\"output\":{},\"interpolation\":\"STEP\"}}", time_accessor, scale_accessor
+                ));
+                channels.push(format!(
+                    "{{\"sampler\":{},\"target\":{{\"node\":{},\"path\":\"scale\"}}}}", k, k + 1
+                ));
+            }
+        }
+    }
+
+    let node_indices: Vec<String> = (0..nodes.len()).map(|i| i.to_string()).collect();
+    let json = format!(
+        "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"flag\"}},\
+         \"scene\":0,\"scenes\":[{{\"nodes\":[{}]}}],\"nodes\":[{}],\"meshes\":[{}],\
+         \"materials\":[\
+         {{\"name\":\"flag\",\"doubleSided\":true,\"pbrMetallicRoughness\":{{\"baseColorTexture\":{{\"index\":0}},\"metallicFactor\":0,\"roughnessFactor\":1}}}},\
+         {{\"name\":\"background\",\"pbrMetallicRoughness\":{{\"baseColorTexture\":{{\"index\":1}},\"metallicFactor\":0,\"roughnessFactor\":1}}}}],\
+         \"textures\":[{{\"source\":0}},{{\"source\":1}}],\
+         \"images\":[{{\"bufferView\":{},\"mimeType\":\"image/png\"}},{{\"bufferView\":{},\"mimeType\":\"image/png\"}}],\
+         \"animations\":[{{\"name\":\"wave\",\"channels\":[{}],\"samplers\":[{}]}}],\
+         \"accessors\":[{}],\"bufferViews\":[{}],\"buffers\":[{{\"byteLength\":{}}}]}}",
+        join(&node_indices), join(&nodes), join(&meshes_json),
+        flag_image, background_image,
+        join(&channels), join(&samplers),
+        join(&buffer.accessors), join(&buffer.buffer_views), buffer.bin.len()
+    );
+
+    write_glb(&options.output, json.into_bytes(), buffer.bin)
+}
+
+fn write_glb(filename: &str, mut json: Vec<u8>, mut bin: Vec<u8>) -> io::Result<()> {
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    while bin.len() % 4 != 0 {
+        bin.push(0);
+    }
+
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+    let mut out = BufWriter::new(try!(File::create(filename)));
+    try!(out.write_all(b"glTF"));
+    try!(out.write_all(&2u32.to_le_bytes()));
+    try!(out.write_all(&(length as u32).to_le_bytes()));
+    try!(out.write_all(&(json.len() as u32).to_le_bytes()));
+    try!(out.write_all(b"JSON"));
+    try!(out.write_all(&json));
+    try!(out.write_all(&(bin.len() as u32).to_le_bytes()));
+    try!(out.write_all(b"BIN\0"));
+    try!(out.write_all(&bin));
+
+    Ok(())
+}
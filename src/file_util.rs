@@ -1,8 +1,8 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{ErrorKind, Read, Write};
 use std::io;
+use std::str::FromStr;
 use tga::TgaImage;
-use std::os::raw;
 use std::ffi::CString;
 
 
@@ -15,13 +15,109 @@ pub fn file_contents(filename: &str) -> io::Result<CString> {
     Ok(c_str)
 }
 
-pub fn read_tga(filename: &str) -> io::Result<(*const raw::c_void, i32, i32)> {
+// Pixels come back in BGR order, bottom row first, ready for glTexImage2D.
+pub fn read_tga(filename: &str) -> io::Result<(Vec<[u8; 3]>, i32, i32)> {
     let mut file = try!(File::open(filename));
-    let tga_image = TgaImage::parse_from_file(&mut file).unwrap();
-    let image = tga_image.pixels().collect::<Vec<[u8; 3]>>().as_ptr() as *const raw::c_void;
+    let tga_image = match TgaImage::parse_from_file(&mut file) {
+        Ok(image) => image,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed TGA image")),
+    };
+    let image = tga_image.pixels().collect::<Vec<[u8; 3]>>();
     let height = tga_image.height() as i32;
     let width = tga_image.width() as i32;
 
     Ok((image, height, width))
 }
 
+// Writes an uncompressed 24-bit TGA from pixels laid out as read_tga returns
+// them.
+pub fn write_tga(filename: &str, pixels: &[[u8; 3]], width: i32, height: i32) -> io::Result<()> {
+    let mut out = Vec::with_capacity(18 + 3 * pixels.len());
+    out.extend_from_slice(&[0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    out.extend_from_slice(&[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8, 24, 0]);
+    for pixel in pixels {
+        out.extend_from_slice(pixel);
+    }
+
+    let mut file = try!(File::create(filename));
+    file.write_all(&out)
+}
+
+// One `key = value` line of a settings file such as post.cfg.
+pub struct Setting {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+impl Setting {
+    pub fn invalid(&self) -> String {
+        format!("line {}: invalid value '{}' for {}", self.line, self.value, self.key)
+    }
+
+    pub fn switch(&self) -> Result<bool, String> {
+        match self.value.as_str() {
+            "on" | "true" => Ok(true),
+            "off" | "false" => Ok(false),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    pub fn number<T: FromStr>(&self) -> Result<T, String> {
+        self.value.parse().map_err(|_| self.invalid())
+    }
+
+    // Three numbers separated by commas.
+    pub fn color(&self) -> Result<[f32; 3], String> {
+        let parts: Vec<&str> = self.value.split(',').map(|part| part.trim()).collect();
+        if parts.len() != 3 {
+            return Err(self.invalid());
+        }
+        let mut color = [0.0; 3];
+        for (c, part) in color.iter_mut().zip(parts.iter()) {
+            *c = try!(part.parse().map_err(|_| self.invalid()));
+        }
+
+        Ok(color)
+    }
+
+    pub fn unknown(&self) -> String {
+        format!("line {}: unknown setting {}", self.line, self.key)
+    }
+}
+
+// Lines of `key = value`, with `#` starting a comment.
+pub fn parse_settings(text: &str) -> Result<Vec<Setting>, String> {
+    let mut settings = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap().trim();
+        match parts.next() {
+            Some(value) => settings.push(Setting {
+                line: number + 1,
+                key: String::from(key),
+                value: String::from(value.trim()),
+            }),
+            None => return Err(format!("line {}: expected key = value", number + 1)),
+        }
+    }
+
+    Ok(settings)
+}
+
+// A missing file reads as no settings at all.
+pub fn read_settings(filename: &str) -> Result<Vec<Setting>, String> {
+    let mut file = match File::open(filename) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut text = String::new();
+    try!(file.read_to_string(&mut text).map_err(|e| e.to_string()));
+
+    parse_settings(&text)
+}
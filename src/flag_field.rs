@@ -0,0 +1,314 @@
+use atlas::{Image, TextureArrayBuilder};
+use environment::Environment;
+use gl;
+use gl::types::*;
+use gl_util;
+use meshes;
+use meshes::{FlagMesh, FlagVertex};
+use scene;
+use scene::SceneOptions;
+use std::ffi::CString;
+use std::fs;
+use std::mem;
+use std::os::raw;
+use std::ptr;
+
+
+// Rows of small flags across the ground between the eye and the wall, as for
+// a parade or a stadium crowd.
+const FIELD_COLUMNS: usize = 16;
+const FIELD_ROWS: usize = 12;
+const FIELD_LO: [GLfloat; 2] = [-0.8, -0.9];
+const FIELD_HI: [GLfloat; 2] = [1.7, 0.1];
+const FIELD_FLAG_HEIGHT: GLfloat = -0.75;
+const FIELD_FLAG_SCALE: GLfloat = 0.1;
+// How far flags turn from facing the eye, in radians either way.
+const FIELD_MAX_YAW: GLfloat = 0.3;
+// The wave repeats every four seconds.
+const WAVE_PERIOD: GLfloat = 4.0;
+
+const FLAG_TEXTURE: &str = "assets/flag.tga";
+const DESIGNS_DIRECTORY: &str = "assets/flags";
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct FlagInstance {
+    transform: [GLfloat; 16],
+    // Texture array layer and time offset into the wave.
+    params: [GLfloat; 2],
+}
+
+struct Uniforms {
+    p_matrix: GLint,
+    mv_matrix: GLint,
+    time: GLint,
+    textures: GLint,
+    prefiltered_map: GLint,
+    environment_max_lod: GLint,
+    fog: scene::FogUniforms,
+}
+
+struct Attributes {
+    texcoord: GLint,
+    // The first of four consecutive locations, one per column.
+    transform: GLint,
+    params: GLint,
+}
+
+// Draws a field of flags from one shared grid in a single instanced call,
+// with the wave worked out on the GPU. Each flag has its own transform,
+// design and place in the wave.
+pub struct FlagField {
+    pub enabled: bool,
+    mesh: FlagMesh,
+    instance_buffer: GLuint,
+    instance_count: GLsizei,
+    program: GLuint,
+    uniforms: Uniforms,
+    attributes: Attributes,
+}
+
+// A repeatable scatter of values in [0, 1) for laying out the field.
+fn jitter(k: usize, salt: u32) -> GLfloat {
+    let h = (k as u32 ^ salt).wrapping_mul(2654435761);
+    (h >> 8) as GLfloat / (1 << 24) as GLfloat
+}
+
+fn field_instances(layers: usize) -> Vec<FlagInstance> {
+    let mut instances = Vec::with_capacity(FIELD_ROWS * FIELD_COLUMNS);
+    for j in 0..FIELD_ROWS {
+        for i in 0..FIELD_COLUMNS {
+            let k = j * FIELD_COLUMNS + i;
+            // Alternate rows are staggered so flags don't hide one another.
+            let stagger = if j % 2 == 0 { 0.0 } else { 0.5 };
+            let x = FIELD_LO[0] + (FIELD_HI[0] - FIELD_LO[0]) * (i as GLfloat + stagger) / FIELD_COLUMNS as GLfloat;
+            let z = FIELD_LO[1] + (FIELD_HI[1] - FIELD_LO[1]) * j as GLfloat / (FIELD_ROWS - 1) as GLfloat;
+            let yaw = FIELD_MAX_YAW * (2.0 * jitter(k, 0x9e37) - 1.0);
+            let (sin, cos) = (yaw.sin() * FIELD_FLAG_SCALE, yaw.cos() * FIELD_FLAG_SCALE);
+
+            instances.push(FlagInstance {
+                transform: [
+                    cos, 0.0, -sin, 0.0,
+                    0.0, FIELD_FLAG_SCALE, 0.0, 0.0,
+                    sin, 0.0, cos, 0.0,
+                    x, FIELD_FLAG_HEIGHT, z, 1.0,
+                ],
+                params: [(k % layers) as GLfloat, WAVE_PERIOD * jitter(k, 0x85eb)],
+            });
+        }
+    }
+
+    instances
+}
+
+// The flag plus any other designs of the same size. Without others to choose
+// from, the flag with its colours rotated stands in.
+fn flag_designs() -> Option<TextureArrayBuilder> {
+    let mut designs = TextureArrayBuilder::new();
+    if let Err(e) = designs.load(FLAG_TEXTURE) {
+        eprintln!("Failed to load {}: {}", FLAG_TEXTURE, e);
+        return None;
+    }
+
+    if let Ok(entries) = fs::read_dir(DESIGNS_DIRECTORY) {
+        let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+        paths.sort();
+        for path in paths {
+            if path.extension().map_or(true, |ext| ext != "tga") {
+                continue;
+            }
+            let filename = path.to_string_lossy().into_owned();
+            if let Err(e) = designs.load(&filename) {
+                eprintln!("Skipping flag design {}: {}", filename, e);
+            }
+        }
+    }
+
+    if designs.len() == 1 {
+        let flag = designs.layer(0);
+        for shift in 1..3 {
+            let pixels = flag.pixels.iter().map(|p| [p[shift], p[(shift + 1) % 3], p[(shift + 2) % 3]]).collect();
+            designs.add(Image { pixels: pixels, width: flag.width, height: flag.height }).unwrap();
+        }
+    }
+
+    Some(designs)
+}
+
+impl FlagField {
+    pub fn new() -> FlagField {
+        FlagField {
+            enabled: false,
+            mesh: FlagMesh {
+                vertex_buffer: 0,
+                element_buffer: 0,
+                element_count: 0,
+                texture: 0,
+                detail: meshes::DetailMaps::none(),
+                material: meshes::Material::standard(1.0, 0.0),
+            },
+            instance_buffer: 0,
+            instance_count: 0,
+            program: 0,
+            uniforms: Uniforms {
+                p_matrix: 0,
+                mv_matrix: 0,
+                time: 0,
+                textures: 0,
+                prefiltered_map: 0,
+                environment_max_lod: 0,
+                fog: scene::FogUniforms::new(),
+            },
+            attributes: Attributes {
+                texcoord: 0,
+                transform: 0,
+                params: 0,
+            },
+        }
+    }
+
+    // Returns false, leaving the field unavailable, if the shaders or the
+    // flag texture can't be loaded.
+    pub fn init(&mut self) -> bool {
+        let vertex_shader = gl_util::make_shader(gl::VERTEX_SHADER, "shaders/flag_field.v.glsl");
+        let fragment_shader = gl_util::make_shader_with_library(
+            gl::FRAGMENT_SHADER, scene::FOG_SHADER, "shaders/flag_field.f.glsl"
+        );
+        if vertex_shader == 0 || fragment_shader == 0 {
+            return false;
+        }
+        self.program = gl_util::make_program(vertex_shader, fragment_shader);
+        if self.program == 0 {
+            return false;
+        }
+
+        let designs = match flag_designs() {
+            Some(designs) => designs,
+            None => return false,
+        };
+        self.mesh.texture = designs.build();
+
+        // The grid never tears or moves here, so it is uploaded once.
+        let (vertices, elements) = meshes::build_flag_mesh();
+        meshes::init_mesh(
+            &mut self.mesh,
+            &vertices, vertices.len() as GLsizei,
+            &elements, elements.len() as GLsizei,
+            gl::STATIC_DRAW
+        );
+
+        let instances = field_instances(designs.len());
+        self.instance_count = instances.len() as GLsizei;
+        unsafe {
+            gl::GenBuffers(1, &mut self.instance_buffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (instances.len() * mem::size_of::<FlagInstance>()) as GLsizeiptr,
+                instances.as_ptr() as *const raw::c_void,
+                gl::STATIC_DRAW
+            );
+
+            let uniform = |name: &str| {
+                let name = CString::new(name).unwrap();
+                gl::GetUniformLocation(self.program, name.as_ptr())
+            };
+            self.uniforms = Uniforms {
+                p_matrix: uniform("p_matrix"),
+                mv_matrix: uniform("mv_matrix"),
+                time: uniform("time"),
+                textures: uniform("textures"),
+                prefiltered_map: uniform("prefiltered_map"),
+                environment_max_lod: uniform("environment_max_lod"),
+                fog: scene::FogUniforms::locate(self.program),
+            };
+            let attribute = |name: &str| {
+                let name = CString::new(name).unwrap();
+                gl::GetAttribLocation(self.program, name.as_ptr())
+            };
+            self.attributes = Attributes {
+                texcoord: attribute("texcoord"),
+                transform: attribute("instance_transform"),
+                params: attribute("instance_params"),
+            };
+        }
+
+        true
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled && self.program != 0;
+        println!("Flag field {}", if self.enabled { "on" } else { "off" });
+    }
+
+    // Fogged like the rest of the scene, with `environment` giving the sky
+    // the fog takes its colour from.
+    pub fn render(
+        &self, p_matrix: &[GLfloat], mv_matrix: &[GLfloat], time: GLfloat,
+        scene: &SceneOptions, environment: &Environment
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        let instance_stride = mem::size_of::<FlagInstance>() as GLint;
+        let instance_locations: Vec<GLuint> = (0..4)
+            .map(|column| (self.attributes.transform + column) as GLuint)
+            .chain(Some(self.attributes.params as GLuint))
+            .collect();
+        unsafe {
+            gl::UseProgram(self.program);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.mesh.texture);
+            gl::Uniform1i(self.uniforms.textures, 0);
+            environment.bind_maps(gl::TEXTURE1);
+            gl::Uniform1i(self.uniforms.prefiltered_map, 2);
+            gl::Uniform1f(self.uniforms.environment_max_lod, environment.max_lod);
+            self.uniforms.fog.set(scene);
+            gl::UniformMatrix4fv(self.uniforms.p_matrix, 1, gl::FALSE, p_matrix.as_ptr());
+            gl::UniformMatrix4fv(self.uniforms.mv_matrix, 1, gl::FALSE, mv_matrix.as_ptr());
+            gl::Uniform1f(self.uniforms.time, time);
+            // Turned flags show their backs.
+            gl::Disable(gl::CULL_FACE);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.mesh.vertex_buffer);
+            gl::EnableVertexAttribArray(self.attributes.texcoord as GLuint);
+            gl::VertexAttribPointer(
+                self.attributes.texcoord as GLuint,
+                2, gl::FLOAT, gl::FALSE, mem::size_of::<FlagVertex>() as GLint,
+                offset_of!(FlagVertex, texcoord) as *const raw::c_void
+            );
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_buffer);
+            for (column, &location) in instance_locations[..4].iter().enumerate() {
+                gl::EnableVertexAttribArray(location);
+                gl::VertexAttribPointer(
+                    location, 4, gl::FLOAT, gl::FALSE, instance_stride,
+                    (offset_of!(FlagInstance, transform) + column * mem::size_of::<[GLfloat; 4]>()) as *const raw::c_void
+                );
+            }
+            gl::EnableVertexAttribArray(instance_locations[4]);
+            gl::VertexAttribPointer(
+                instance_locations[4], 2, gl::FLOAT, gl::FALSE, instance_stride,
+                offset_of!(FlagInstance, params) as *const raw::c_void
+            );
+            for &location in instance_locations.iter() {
+                gl::VertexAttribDivisor(location, 1);
+            }
+
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.mesh.element_buffer);
+            gl::DrawElementsInstanced(
+                gl::TRIANGLES, self.mesh.element_count, gl::UNSIGNED_SHORT, ptr::null(), self.instance_count
+            );
+
+            // Attribute state is shared with the other program, so leave it
+            // as we found it.
+            for &location in instance_locations.iter() {
+                gl::VertexAttribDivisor(location, 0);
+                gl::DisableVertexAttribArray(location);
+            }
+            gl::DisableVertexAttribArray(self.attributes.texcoord as GLuint);
+            gl::Enable(gl::CULL_FACE);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use gl::types::GLfloat;
+use meshes::FlagVertex;
+use simd;
+use simd::F32x4;
+use std::f32;
+use std::panic;
+use std::slice;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+// Handing a band to a worker costs about as much as filling a few thousand
+// vertices, so smaller grids use fewer threads.
+const MIN_BAND_VERTICES: usize = 16384;
+
+// The terms of calculate_flag_vertex that depend only on s, for four columns.
+#[derive(Copy, Clone)]
+struct ColumnBlock {
+    s: F32x4,
+    z: F32x4,
+    // The z component of the gradient along the fly.
+    sgrad_z: F32x4,
+}
+
+fn column_blocks(out: &mut Vec<ColumnBlock>, columns: usize, time: GLfloat) {
+    let s_step = 1.0 / (columns - 1) as GLfloat;
+    let wave = 1.5 * f32::consts::PI;
+
+    out.clear();
+    for block in 0..(columns + 3) / 4 {
+        let first = (4 * block) as GLfloat;
+        let s = F32x4::new([first, first + 1.0, first + 2.0, first + 3.0]) * F32x4::splat(s_step);
+        let phase = F32x4::splat(wave) * (F32x4::splat(time) + s);
+        let (sin, cos) = (simd::sin(phase), simd::cos(phase));
+        out.push(ColumnBlock {
+            s: s,
+            z: F32x4::splat(0.125) * s * sin,
+            sgrad_z: F32x4::splat(0.125) * (sin + s * cos * F32x4::splat(wave)),
+        });
+    }
+}
+
+pub fn amplitude(time: GLfloat) -> GLfloat {
+    0.0625 + 0.03125 * f32::sin(f32::consts::PI * time)
+}
+
+// Fills `vertices`, which holds whole rows of the grid starting at
+// `first_row`, four columns at a time. The result matches
+// calculate_flag_vertex to within rounding.
+fn calculate_rows(
+    vertices: &mut [FlagVertex], blocks: &[ColumnBlock],
+    columns: usize, rows: usize, first_row: usize, amplitude: GLfloat, hoist_offset: GLfloat
+) {
+    let t_step = 1.0 / (rows - 1) as GLfloat;
+    let (one, half, three_quarters) = (F32x4::splat(1.0), F32x4::splat(0.5), F32x4::splat(0.75));
+    let a = F32x4::splat(amplitude);
+
+    for (j, row) in vertices.chunks_mut(columns).enumerate() {
+        let t = t_step * (first_row + j) as GLfloat;
+        let y = 0.75 * t - 0.375 + hoist_offset;
+        let droop = F32x4::splat(t * (t - 1.0));
+        let sgrad_x = one + half * a * droop;
+        let slope = F32x4::splat(2.0 * t - 1.0);
+
+        for (block, lanes) in blocks.iter().zip(row.chunks_mut(4)) {
+            let x = block.s - a * (one - half * block.s) * droop;
+            let tgrad_x = F32x4::splat(0.0) - a * (one - block.s) * slope;
+
+            // cross(tgrad, sgrad), with tgrad = (tgrad_x, 0.75, 0) and
+            // sgrad = (sgrad_x, 0, sgrad_z).
+            let nx = three_quarters * block.sgrad_z;
+            let ny = F32x4::splat(0.0) - tgrad_x * block.sgrad_z;
+            let nz = F32x4::splat(0.0) - three_quarters * sgrad_x;
+            let length = (nx * nx + ny * ny + nz * nz).sqrt();
+            let (nx, ny, nz) = ((nx / length).lanes(), (ny / length).lanes(), (nz / length).lanes());
+            let sgrad_length = (sgrad_x * sgrad_x + block.sgrad_z * block.sgrad_z).sqrt();
+            let (tx, tz) = ((sgrad_x / sgrad_length).lanes(), (block.sgrad_z / sgrad_length).lanes());
+            let (x, z) = (x.lanes(), block.z.lanes());
+
+            for (i, v) in lanes.iter_mut().enumerate() {
+                v.position = [x[i], y, z[i], 0.0];
+                v.normal = [nx[i], ny[i], nz[i], 0.0];
+                v.tangent = [tx[i], 0.0, tz[i], -1.0];
+            }
+        }
+    }
+}
+
+// A band of rows for a worker, pointing into memory the caller of
+// FlagUpdater::update holds until every band is done.
+struct Band {
+    vertices: *mut FlagVertex,
+    vertex_count: usize,
+    blocks: *const ColumnBlock,
+    block_count: usize,
+    columns: usize,
+    rows: usize,
+    first_row: usize,
+    amplitude: GLfloat,
+    hoist_offset: GLfloat,
+}
+
+unsafe impl Send for Band {}
+
+impl Band {
+    unsafe fn run(&self) {
+        calculate_rows(
+            slice::from_raw_parts_mut(self.vertices, self.vertex_count),
+            slice::from_raw_parts(self.blocks, self.block_count),
+            self.columns, self.rows, self.first_row, self.amplitude, self.hoist_offset
+        );
+    }
+}
+
+// Splits the scripted flag's update across a pool of worker threads by rows,
+// with the calling thread taking the first band. Workers start on the first
+// update, and with one thread there are none.
+pub struct FlagUpdater {
+    threads: usize,
+    bands: Vec<Sender<Band>>,
+    done: Option<Receiver<thread::Result<()>>>,
+    workers: Vec<thread::JoinHandle<()>>,
+    blocks: Vec<ColumnBlock>,
+}
+
+impl FlagUpdater {
+    // `threads` counts the calling thread.
+    pub fn new(threads: usize) -> FlagUpdater {
+        FlagUpdater {
+            threads: usize::max(threads, 1),
+            bands: vec![],
+            done: None,
+            workers: vec![],
+            blocks: vec![],
+        }
+    }
+
+    fn start_workers(&mut self) {
+        let (done_sender, done) = channel();
+        for _ in 1..self.threads {
+            let (sender, bands) = channel::<Band>();
+            let done_sender = done_sender.clone();
+            self.workers.push(thread::spawn(move || {
+                for band in bands {
+                    let result = panic::catch_unwind(|| unsafe { band.run() });
+                    if done_sender.send(result).is_err() {
+                        break;
+                    }
+                }
+            }));
+            self.bands.push(sender);
+        }
+        self.done = Some(done);
+    }
+
+    pub fn update(
+        &mut self, vertices: &mut [FlagVertex], columns: usize, rows: usize, time: GLfloat, hoist_offset: GLfloat
+    ) {
+        if self.done.is_none() {
+            self.start_workers();
+        }
+        column_blocks(&mut self.blocks, columns, time);
+        let amplitude = amplitude(time);
+
+        let min_rows = (MIN_BAND_VERTICES + columns - 1) / columns;
+        let rows_per_band = usize::max((rows + self.threads - 1) / self.threads, min_rows);
+        let mut bands = vertices.chunks_mut(rows_per_band * columns);
+        let first = match bands.next() {
+            Some(first) => first,
+            None => return,
+        };
+
+        let mut sent = 0;
+        for (k, band) in bands.enumerate() {
+            let band = Band {
+                vertices: band.as_mut_ptr(),
+                vertex_count: band.len(),
+                blocks: self.blocks.as_ptr(),
+                block_count: self.blocks.len(),
+                columns: columns,
+                rows: rows,
+                first_row: (k + 1) * rows_per_band,
+                amplitude: amplitude,
+                hoist_offset: hoist_offset,
+            };
+            self.bands[k].send(band).unwrap();
+            sent += 1;
+        }
+
+        let blocks = &self.blocks;
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            calculate_rows(first, blocks, columns, rows, 0, amplitude, hoist_offset);
+        }));
+
+        // Every band has to be finished with before the vertices are handed
+        // back, even if one of them failed.
+        let done = self.done.as_ref().unwrap();
+        let mut failure = result.err();
+        for _ in 0..sent {
+            if let Err(e) = done.recv().unwrap() {
+                failure = Some(e);
+            }
+        }
+        if let Some(e) = failure {
+            panic::resume_unwind(e);
+        }
+    }
+}
+
+impl Drop for FlagUpdater {
+    fn drop(&mut self) {
+        // Closing the channels lets the workers run off the end of their
+        // loops.
+        self.bands.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use file_util;
+use file_util::Setting;
+use gl::types::*;
+use meshes::FlagVertex;
+use vec_util;
+use std::f32;
+
+
+pub const CONFIG_FILE: &str = "flagpole.cfg";
+
+// Adjacent profile segments meeting at a sharper angle than this get
+// separate rings of vertices, so the normals do not bleed across the edge.
+const CREASE_COS: GLfloat = f32::consts::FRAC_1_SQRT_2;
+
+// Enough to look round up close while keeping the pole well inside a
+// mesh's vertex limit.
+const MAX_SEGMENTS: usize = 256;
+
+// The pole's shape from the top of the truck down to the foot of the shaft,
+// read from the flagpole file. The rope and the cloth's colliders are laid
+// out from it too.
+#[derive(Copy, Clone, Debug)]
+pub struct FlagpoleProfile {
+    pub truck_top: GLfloat,
+    pub truck_crown: GLfloat,
+    pub truck_bottom: GLfloat,
+    pub shaft_top: GLfloat,
+    pub shaft_bottom: GLfloat,
+    pub truck_top_radius: GLfloat,
+    pub truck_crown_radius: GLfloat,
+    pub truck_bottom_radius: GLfloat,
+    pub shaft_radius: GLfloat,
+    // Around the pole.
+    pub segments: usize,
+}
+
+impl FlagpoleProfile {
+    pub fn default() -> FlagpoleProfile {
+        FlagpoleProfile {
+            truck_top: 0.5,
+            truck_crown: 0.41,
+            truck_bottom: 0.38,
+            shaft_top: 0.3775,
+            // The ground.
+            shaft_bottom: -1.0,
+            truck_top_radius: 0.005,
+            truck_crown_radius: 0.020,
+            truck_bottom_radius: 0.015,
+            shaft_radius: 0.010,
+            segments: 16,
+        }
+    }
+
+    // Settings left out keep their defaults.
+    pub fn parse(settings: &[Setting]) -> Result<FlagpoleProfile, String> {
+        let mut profile = FlagpoleProfile::default();
+
+        for setting in settings {
+            match setting.key.as_str() {
+                "truck_top" => profile.truck_top = try!(setting.number()),
+                "truck_crown" => profile.truck_crown = try!(setting.number()),
+                "truck_bottom" => profile.truck_bottom = try!(setting.number()),
+                "shaft_top" => profile.shaft_top = try!(setting.number()),
+                "shaft_bottom" => profile.shaft_bottom = try!(setting.number()),
+                "truck_top_radius" => profile.truck_top_radius = try!(setting.number()),
+                "truck_crown_radius" => profile.truck_crown_radius = try!(setting.number()),
+                "truck_bottom_radius" => profile.truck_bottom_radius = try!(setting.number()),
+                "shaft_radius" => profile.shaft_radius = try!(setting.number()),
+                "segments" => profile.segments = try!(setting.number()),
+                _ => return Err(setting.unknown()),
+            }
+        }
+
+        let heights = [
+            profile.truck_top, profile.truck_crown, profile.truck_bottom, profile.shaft_top, profile.shaft_bottom
+        ];
+        if heights.windows(2).any(|pair| !(pair[0] >= pair[1])) || profile.shaft_top == profile.shaft_bottom {
+            return Err(String::from("the truck and shaft heights must run from the top of the pole down"));
+        }
+        let radii = [
+            profile.truck_top_radius, profile.truck_crown_radius, profile.truck_bottom_radius, profile.shaft_radius
+        ];
+        if radii.iter().any(|&radius| !(radius > 0.0)) {
+            return Err(String::from("the truck and shaft radii must be positive"));
+        }
+        if profile.segments < 3 || profile.segments > MAX_SEGMENTS {
+            return Err(format!("segments must be between 3 and {}", MAX_SEGMENTS));
+        }
+
+        Ok(profile)
+    }
+
+    pub fn load(filename: &str) -> Result<FlagpoleProfile, String> {
+        let settings = try!(file_util::read_settings(filename));
+        FlagpoleProfile::parse(&settings)
+    }
+
+    // Where the pole stands, with the flag's hoist at x = 0 against the
+    // shaft.
+    pub fn axis_xz(&self) -> [GLfloat; 2] {
+        [-self.shaft_radius, 0.0]
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct ProfileRing {
+    height: GLfloat,
+    radius: GLfloat,
+}
+
+// Builds a flagpole as a surface of revolution around a vertical axis. The
+// profile is listed from the top of the pole down, and a ring of zero radius
+// closes the surface with a cap.
+pub struct FlagpoleBuilder {
+    axis_xz: [GLfloat; 2],
+    segments: usize,
+    rings: Vec<ProfileRing>,
+    tex_lo: [GLfloat; 2],
+    tex_hi: [GLfloat; 2],
+    shininess: GLfloat,
+    specular: [GLubyte; 4],
+}
+
+impl FlagpoleBuilder {
+    pub fn new() -> FlagpoleBuilder {
+        FlagpoleBuilder {
+            axis_xz: [0.0, 0.0],
+            segments: 16,
+            rings: vec![],
+            tex_lo: [0.0, 0.0],
+            tex_hi: [1.0, 1.0],
+            shininess: 0.0,
+            specular: [0; 4],
+        }
+    }
+
+    pub fn axis(mut self, axis_xz: [GLfloat; 2]) -> FlagpoleBuilder {
+        self.axis_xz = axis_xz;
+        self
+    }
+
+    pub fn segments(mut self, segments: usize) -> FlagpoleBuilder {
+        self.segments = segments;
+        self
+    }
+
+    pub fn ring(mut self, height: GLfloat, radius: GLfloat) -> FlagpoleBuilder {
+        self.rings.push(ProfileRing { height: height, radius: radius });
+        self
+    }
+
+    pub fn profile(self, profile: &FlagpoleProfile) -> FlagpoleBuilder {
+        self.segments(profile.segments)
+            .ring(profile.truck_top, 0.0)
+            .ring(profile.truck_top, profile.truck_top_radius)
+            .ring(profile.truck_crown, profile.truck_crown_radius)
+            .ring(profile.truck_bottom, profile.truck_bottom_radius)
+            .ring(profile.shaft_top, profile.shaft_radius)
+            .ring(profile.shaft_bottom, profile.shaft_radius)
+            .ring(profile.shaft_bottom, 0.0)
+    }
+
+    pub fn texcoords(mut self, tex_lo: [GLfloat; 2], tex_hi: [GLfloat; 2]) -> FlagpoleBuilder {
+        self.tex_lo = tex_lo;
+        self.tex_hi = tex_hi;
+        self
+    }
+
+    pub fn material(mut self, shininess: GLfloat, specular: [GLubyte; 4]) -> FlagpoleBuilder {
+        self.shininess = shininess;
+        self.specular = specular;
+        self
+    }
+
+    // Normal of the profile segment from ring `i` down to ring `i + 1`, as
+    // radial and vertical components.
+    fn segment_normal(&self, i: usize) -> [GLfloat; 2] {
+        let a = self.rings[i];
+        let b = self.rings[i + 1];
+        let n = [a.height - b.height, b.radius - a.radius];
+        let length = f32::sqrt(n[0] * n[0] + n[1] * n[1]);
+        if length == 0.0 {
+            return [1.0, 0.0];
+        }
+
+        [n[0] / length, n[1] / length]
+    }
+
+    fn texcoord_t(&self, height: GLfloat) -> GLfloat {
+        let top = self.rings[0].height;
+        let bottom = self.rings[self.rings.len() - 1].height;
+        if top == bottom {
+            return self.tex_lo[1];
+        }
+
+        self.tex_lo[1] + (self.tex_hi[1] - self.tex_lo[1]) * (height - top) / (bottom - top)
+    }
+
+    fn push_ring(
+        &self, vertex_data: &mut Vec<FlagVertex>, ring: ProfileRing, normal: [GLfloat; 2]
+    ) {
+        let theta_step: GLfloat = 2.0 * vec_util::M_PI / (self.segments as GLfloat);
+        let s_step: GLfloat = (self.tex_hi[0] - self.tex_lo[0]) / (self.segments as GLfloat);
+        let t = self.texcoord_t(ring.height);
+
+        // The seam column is duplicated so the texture does not wrap backwards
+        // across the last segment.
+        for j in 0..(self.segments + 1) {
+            let sn: f32 = f32::sin(theta_step * (j as f32));
+            let cs: f32 = f32::cos(theta_step * (j as f32));
+            let mut v = FlagVertex::zero();
+
+            v.position[0] = self.axis_xz[0] + ring.radius * cs;
+            v.position[1] = ring.height;
+            v.position[2] = self.axis_xz[1] + ring.radius * sn;
+            v.position[3] = 1.0;
+            v.normal[0]   = normal[0] * cs;
+            v.normal[1]   = normal[1];
+            v.normal[2]   = normal[0] * sn;
+            v.normal[3]   = 0.0;
+            v.texcoord[0] = self.tex_lo[0] + s_step * (j as f32);
+            v.texcoord[1] = t;
+            v.shininess   = self.shininess;
+            v.specular    = self.specular;
+
+            vertex_data.push(v);
+        }
+    }
+
+    fn push_band(
+        &self, element_data: &mut Vec<GLushort>,
+        upper: usize, lower: usize, upper_radius: GLfloat, lower_radius: GLfloat
+    ) {
+        for j in 0..self.segments {
+            let top         = (upper + j) as GLushort;
+            let next_top    = (upper + j + 1) as GLushort;
+            let bottom      = (lower + j) as GLushort;
+            let next_bottom = (lower + j + 1) as GLushort;
+
+            if lower_radius != 0.0 {
+                element_data.push(bottom);
+                element_data.push(next_bottom);
+                element_data.push(top);
+            }
+            if upper_radius != 0.0 {
+                element_data.push(top);
+                element_data.push(next_bottom);
+                element_data.push(next_top);
+            }
+        }
+    }
+
+    // Appends the pole to the given buffers, so it can share a mesh with the
+    // rest of the scene.
+    pub fn build(&self, vertex_data: &mut Vec<FlagVertex>, element_data: &mut Vec<GLushort>) {
+        if self.rings.len() < 2 || self.segments < 3 {
+            return;
+        }
+
+        let segment_count = self.rings.len() - 1;
+        let normals: Vec<[GLfloat; 2]>
+            = (0..segment_count).map(|i| self.segment_normal(i)).collect();
+
+        // Each segment is a band between an upper and a lower ring of
+        // vertices. Smooth rings are shared between the bands on either side.
+        let mut upper = vertex_data.len();
+        self.push_ring(vertex_data, self.rings[0], normals[0]);
+
+        for i in 0..segment_count {
+            let ring = self.rings[i + 1];
+            let n = normals[i];
+            let lower = vertex_data.len();
+
+            let smooth = i + 1 < segment_count && {
+                let m = normals[i + 1];
+                n[0] * m[0] + n[1] * m[1] >= CREASE_COS
+            };
+            if smooth {
+                let m = normals[i + 1];
+                let mut normal = [n[0] + m[0], n[1] + m[1]];
+                let length = f32::sqrt(normal[0] * normal[0] + normal[1] * normal[1]);
+                normal[0] /= length;
+                normal[1] /= length;
+                self.push_ring(vertex_data, ring, normal);
+            } else {
+                self.push_ring(vertex_data, ring, n);
+            }
+
+            self.push_band(element_data, upper, lower, self.rings[i].radius, ring.radius);
+
+            if smooth || i + 1 == segment_count {
+                upper = lower;
+            } else {
+                upper = vertex_data.len();
+                self.push_ring(vertex_data, ring, normals[i + 1]);
+            }
+        }
+    }
+}
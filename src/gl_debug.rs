@@ -0,0 +1,181 @@
+use gl;
+use gl::types::*;
+use gl_util;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::os::raw;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+// Whether the driver is reporting errors through the callback, which makes
+// checking after each call redundant.
+static DEBUG_OUTPUT: AtomicBool = AtomicBool::new(false);
+// Whether objects can be given names, which graphics debuggers show even
+// when debug output is off.
+static LABELS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // The call sites and errors check has already reported.
+    static REPORTED: RefCell<HashSet<(&'static str, u32, GLenum)>> = RefCell::new(HashSet::new());
+}
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+pub enum Severity {
+    Notification = 0,
+    Low = 1,
+    Medium = 2,
+    High = 3,
+}
+
+impl Severity {
+    fn from_gl(severity: GLenum) -> Severity {
+        match severity {
+            gl::DEBUG_SEVERITY_HIGH => Severity::High,
+            gl::DEBUG_SEVERITY_MEDIUM => Severity::Medium,
+            gl::DEBUG_SEVERITY_LOW => Severity::Low,
+            _ => Severity::Notification,
+        }
+    }
+
+    fn to_gl(&self) -> GLenum {
+        match *self {
+            Severity::High => gl::DEBUG_SEVERITY_HIGH,
+            Severity::Medium => gl::DEBUG_SEVERITY_MEDIUM,
+            Severity::Low => gl::DEBUG_SEVERITY_LOW,
+            Severity::Notification => gl::DEBUG_SEVERITY_NOTIFICATION,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Severity::High => "error",
+            Severity::Medium => "warning",
+            Severity::Low => "note",
+            Severity::Notification => "info",
+        }
+    }
+}
+
+fn source_name(source: GLenum) -> &'static str {
+    match source {
+        gl::DEBUG_SOURCE_API => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    }
+}
+
+fn type_name(message_type: GLenum) -> &'static str {
+    match message_type {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        gl::DEBUG_TYPE_MARKER => "marker",
+        _ => "other",
+    }
+}
+
+fn error_name(error: GLenum) -> &'static str {
+    match error {
+        gl::INVALID_ENUM => "GL_INVALID_ENUM",
+        gl::INVALID_VALUE => "GL_INVALID_VALUE",
+        gl::INVALID_OPERATION => "GL_INVALID_OPERATION",
+        gl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION",
+        gl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+        gl::STACK_UNDERFLOW => "GL_STACK_UNDERFLOW",
+        gl::STACK_OVERFLOW => "GL_STACK_OVERFLOW",
+        _ => "unknown error",
+    }
+}
+
+extern "system" fn log_message(
+    source: GLenum, message_type: GLenum, id: GLuint, severity: GLenum,
+    _length: GLsizei, message: *const GLchar, _user: *mut raw::c_void
+) {
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    eprintln!(
+        "GL {}: {} from {}, id {}: {}",
+        Severity::from_gl(severity).name(), type_name(message_type), source_name(source), id, message.trim_end()
+    );
+}
+
+// Routes the driver's messages of at least `min_severity` to stderr as they
+// happen, if the context was made for debugging and has KHR_debug or
+// ARB_debug_output. Returns whether it does.
+pub fn init(min_severity: Severity) -> bool {
+    let khr_debug = gl_util::has_feature(4, 3, "GL_KHR_debug");
+    LABELS.store(khr_debug && gl::ObjectLabel::is_loaded(), Ordering::Relaxed);
+    if !(khr_debug || gl_util::has_feature(4, 3, "GL_ARB_debug_output")) || !gl::DebugMessageCallback::is_loaded() {
+        return false;
+    }
+
+    let mut flags = 0;
+    unsafe {
+        gl::GetIntegerv(gl::CONTEXT_FLAGS, &mut flags);
+    }
+    if flags as GLenum & gl::CONTEXT_FLAG_DEBUG_BIT == 0 {
+        return false;
+    }
+
+    unsafe {
+        // ARB_debug_output is always on in a debug context.
+        if khr_debug {
+            gl::Enable(gl::DEBUG_OUTPUT);
+        }
+        // So messages arrive during the call that caused them, and a
+        // breakpoint in log_message lands in the culprit's stack.
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(log_message, ptr::null());
+
+        gl::DebugMessageControl(gl::DONT_CARE, gl::DONT_CARE, gl::DONT_CARE, 0, ptr::null(), gl::FALSE);
+        for &severity in [Severity::Notification, Severity::Low, Severity::Medium, Severity::High].iter() {
+            if severity >= min_severity {
+                gl::DebugMessageControl(gl::DONT_CARE, gl::DONT_CARE, severity.to_gl(), 0, ptr::null(), gl::TRUE);
+            }
+        }
+    }
+
+    DEBUG_OUTPUT.store(true, Ordering::Relaxed);
+    true
+}
+
+pub fn active() -> bool {
+    DEBUG_OUTPUT.load(Ordering::Relaxed)
+}
+
+// Names a buffer, texture, shader or program, given its kind as
+// gl::BUFFER, gl::TEXTURE and so on, in the driver's messages and in
+// graphics debuggers.
+pub fn label(identifier: GLenum, name: GLuint, label: &str) {
+    if !LABELS.load(Ordering::Relaxed) || name == 0 {
+        return;
+    }
+
+    let label = CString::new(label).unwrap();
+    unsafe {
+        gl::ObjectLabel(identifier, name, -1, label.as_ptr());
+    }
+}
+
+// Reports every error raised since the last check as coming from `call`.
+// A call site that goes wrong once usually goes wrong every frame, so each
+// is only reported the first time.
+pub fn check(call: &str, file: &'static str, line: u32) {
+    loop {
+        let error = unsafe { gl::GetError() };
+        if error == gl::NO_ERROR {
+            return;
+        }
+
+        let first = REPORTED.with(|reported| reported.borrow_mut().insert((file, line, error)));
+        if first {
+            eprintln!("{}:{}: {} raised {}", file, line, call, error_name(error));
+        }
+    }
+}
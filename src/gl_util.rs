@@ -1,44 +1,223 @@
 use gl;
-use gl::types::{GLenum, GLuint, GLint, GLchar};
+use gl::types::{GLenum, GLuint, GLint, GLchar, GLsizei};
+use std::ffi::{CStr, CString};
 use std::ptr;
+use std::os::raw;
 use file_util;
+use gl_debug;
 
 
 pub fn make_texture(filename: &str) -> GLuint {
-    let (pixels, height, width) = match file_util::read_tga(filename) {
-        Ok(tuple) => tuple,
-        Err(_) => return 0,
-    };
+    match file_util::read_tga(filename) {
+        Ok((pixels, height, width)) => {
+            let texture = make_texture_from_pixels(&pixels, width, height);
+            gl_debug::label(gl::TEXTURE, texture, filename);
+            texture
+        }
+        Err(_) => 0,
+    }
+}
+
+// Takes pixels in the BGR bottom-up order read_tga gives.
+pub fn make_texture_from_pixels(pixels: &[[u8; 3]], width: i32, height: i32) -> GLuint {
+    let mut texture = 0;
+    unsafe {
+        gl_check!(gl::GenTextures(1, &mut texture));
+        gl_check!(gl::BindTexture(gl::TEXTURE_2D, texture));
+        gl_check!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint));
+        gl_check!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint));
+        gl_check!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint));
+        gl_check!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint));
+        gl_check!(gl::TexImage2D(
+            gl::TEXTURE_2D, 0,
+            gl::RGB8 as GLint,
+            width as GLint, height as GLint, 0,
+            gl::BGR, gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const raw::c_void
+        ));
+    }
+
+    texture
+}
+
+// For detail maps, which repeat across a surface many times over and so need
+// mipmaps to stay steady in the distance. Takes pixels as for
+// make_texture_from_pixels.
+pub fn make_tiling_texture(pixels: &[[u8; 3]], width: i32, height: i32) -> GLuint {
     let mut texture = 0;
     unsafe {
         gl::GenTextures(1, &mut texture);
         gl::BindTexture(gl::TEXTURE_2D, texture);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
         gl::TexImage2D(
             gl::TEXTURE_2D, 0,
             gl::RGB8 as GLint,
-            width as GLint, height as GLint, 0,
+            width, height, 0,
+            gl::BGR, gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const raw::c_void
+        );
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+    }
+
+    texture
+}
+
+// Stacks same-size images, in the BGR bottom-up order read_tga gives, into
+// the layers of a 2D array texture.
+pub fn make_texture_array(layers: &[Vec<[u8; 3]>], width: i32, height: i32) -> GLuint {
+    let mut texture = 0;
+    unsafe {
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexImage3D(
+            gl::TEXTURE_2D_ARRAY, 0,
+            gl::RGB8 as GLint,
+            width, height, layers.len() as GLint, 0,
             gl::BGR, gl::UNSIGNED_BYTE,
-            pixels
+            ptr::null()
         );
+        for (k, pixels) in layers.iter().enumerate() {
+            gl::TexSubImage3D(
+                gl::TEXTURE_2D_ARRAY, 0,
+                0, 0, k as GLint, width, height, 1,
+                gl::BGR, gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const raw::c_void
+            );
+        }
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
     }
 
     texture
 }
 
+pub struct Framebuffer {
+    pub framebuffer: GLuint,
+    pub color_buffer: GLuint,
+    pub depth_buffer: GLuint,
+    pub width: GLint,
+    pub height: GLint,
+}
+
+// An offscreen render target with colour and depth renderbuffers, for
+// rendering without a visible window.
+pub fn make_framebuffer(width: GLint, height: GLint) -> Option<Framebuffer> {
+    let mut target = Framebuffer {
+        framebuffer: 0,
+        color_buffer: 0,
+        depth_buffer: 0,
+        width: width,
+        height: height,
+    };
+    unsafe {
+        gl::GenFramebuffers(1, &mut target.framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer);
+
+        gl::GenRenderbuffers(1, &mut target.color_buffer);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, target.color_buffer);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGB8, width, height);
+        gl::FramebufferRenderbuffer(
+            gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, target.color_buffer
+        );
+
+        gl::GenRenderbuffers(1, &mut target.depth_buffer);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, target.depth_buffer);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, width, height);
+        gl::FramebufferRenderbuffer(
+            gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, target.depth_buffer
+        );
+
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            eprintln!("Framebuffer incomplete: 0x{:X}", status);
+            gl::DeleteRenderbuffers(1, &target.color_buffer);
+            gl::DeleteRenderbuffers(1, &target.depth_buffer);
+            gl::DeleteFramebuffers(1, &target.framebuffer);
+            return None;
+        }
+    }
+
+    Some(target)
+}
+
+// Whether the context is at least the given version or offers the extension
+// that brought the feature in earlier.
+pub fn has_feature(major: GLint, minor: GLint, extension: &str) -> bool {
+    unsafe {
+        let (mut context_major, mut context_minor) = (0, 0);
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut context_major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut context_minor);
+        if (context_major, context_minor) >= (major, minor) {
+            return true;
+        }
+
+        let mut count = 0;
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+        (0..count as GLuint).any(|k| {
+            let name = gl::GetStringi(gl::EXTENSIONS, k);
+            !name.is_null() && CStr::from_ptr(name as *const raw::c_char).to_bytes() == extension.as_bytes()
+        })
+    }
+}
+
+// The attribute at `location` as GetAttribLocation gave it, or None for -1,
+// which it returns for attributes the linker dropped as unused.
+pub fn attrib(location: GLint) -> Option<GLuint> {
+    if location < 0 {
+        None
+    } else {
+        Some(location as GLuint)
+    }
+}
+
 pub fn make_shader(shader_type: GLenum, filename: &str) -> GLuint {
     let source = match file_util::file_contents(filename) {
         Ok(val) => val,
         Err(_) => return 0,
     };
 
+    compile_shader(shader_type, &[source], filename)
+}
+
+// Compiles `filename` with the source of `library` inserted after its
+// #version line, as GLSL has no #include.
+pub fn make_shader_with_library(shader_type: GLenum, library: &str, filename: &str) -> GLuint {
+    let (library_source, source) = match (file_util::file_contents(library), file_util::file_contents(filename)) {
+        (Ok(library_source), Ok(source)) => (library_source, source.into_bytes()),
+        _ => return 0,
+    };
+
+    let split = if source.starts_with(b"#version") {
+        source.iter().position(|&c| c == b'\n').map_or(source.len(), |i| i + 1)
+    } else {
+        0
+    };
+    let version = CString::new(&source[..split]).unwrap();
+    let rest = CString::new(&source[split..]).unwrap();
+    // So the compiler's line numbers are the file's again after the library.
+    let line = CString::new(format!("\n#line {}\n", if split > 0 { 2 } else { 1 })).unwrap();
+
+    compile_shader(shader_type, &[version, library_source, line, rest], filename)
+}
+
+// Compiles the concatenation of `sources`, naming it `filename` in errors and
+// debug labels.
+fn compile_shader(shader_type: GLenum, sources: &[CString], filename: &str) -> GLuint {
+    let pointers: Vec<*const GLchar> = sources.iter().map(|source| source.as_ptr()).collect();
+
     unsafe {
         let mut shader_ok = 0;
         let shader = gl::CreateShader(shader_type);
-        gl::ShaderSource(shader, 1, &source.as_ptr(), ptr::null());
+        gl::ShaderSource(shader, pointers.len() as GLsizei, pointers.as_ptr(), ptr::null());
         gl::CompileShader(shader);
         gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut shader_ok);
 
@@ -56,6 +235,7 @@ pub fn make_shader(shader_type: GLenum, filename: &str) -> GLuint {
             return 0;
         }
 
+        gl_debug::label(gl::SHADER, shader, filename);
         shader
     }
 }
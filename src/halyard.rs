@@ -0,0 +1,83 @@
+use gl::types::GLfloat;
+use std::f32;
+
+
+pub const HALYARD_FULL_STAFF: GLfloat = 1.0;
+pub const HALYARD_HALF_MAST: GLfloat = 0.5;
+pub const HALYARD_LOWERED: GLfloat = 0.0;
+
+// Fraction of the full halyard travel covered per second.
+const HALYARD_SPEED: GLfloat = 0.2;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HalyardCommand {
+    Hoist,
+    Lower,
+    HalfMast,
+    Stop,
+}
+
+// The halyard position runs from 0.0 with the flag lowered to the cleat,
+// to 1.0 with the flag hoisted against the truck.
+#[derive(Clone, Debug)]
+pub struct Halyard {
+    start: GLfloat,
+    target: GLfloat,
+    start_time: GLfloat,
+    duration: GLfloat,
+    time: GLfloat,
+    position: GLfloat,
+}
+
+impl Halyard {
+    pub fn new() -> Halyard {
+        Halyard {
+            start: HALYARD_FULL_STAFF,
+            target: HALYARD_FULL_STAFF,
+            start_time: 0.0,
+            duration: 0.0,
+            time: 0.0,
+            position: HALYARD_FULL_STAFF,
+        }
+    }
+
+    pub fn position(&self) -> GLfloat {
+        self.position
+    }
+
+    pub fn command(&mut self, command: HalyardCommand) {
+        let target = match command {
+            HalyardCommand::Hoist => HALYARD_FULL_STAFF,
+            HalyardCommand::Lower => HALYARD_LOWERED,
+            HalyardCommand::HalfMast => HALYARD_HALF_MAST,
+            HalyardCommand::Stop => self.position,
+        };
+
+        self.start = self.position;
+        self.target = target;
+        self.start_time = self.time;
+        self.duration = f32::abs(target - self.position) / HALYARD_SPEED;
+    }
+
+    pub fn update(&mut self, time: GLfloat) {
+        self.time = time;
+        let u = if self.duration > 0.0 {
+            f32::max((time - self.start_time) / self.duration, 0.0)
+        } else {
+            1.0
+        };
+
+        if u >= 1.0 {
+            self.position = self.target;
+            return;
+        }
+
+        self.position = self.start + (self.target - self.start) * ease_in_out(u);
+    }
+}
+
+// Cubic ease in and out, so the flag accelerates off the cleat and
+// settles gently at its target.
+fn ease_in_out(u: GLfloat) -> GLfloat {
+    u * u * (3.0 - 2.0 * u)
+}
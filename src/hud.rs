@@ -0,0 +1,292 @@
+use font;
+use gl;
+use gl_debug;
+use gl::types::*;
+use gl_util;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::mem;
+use std::os::raw;
+
+
+// Screen pixels to a font texel.
+const SCALE: GLfloat = 2.0;
+const MARGIN: GLfloat = 8.0;
+const PADDING: GLfloat = 6.0;
+
+const FRAME_HISTORY: usize = 120;
+const BAR_WIDTH: GLfloat = 2.0;
+const GRAPH_HEIGHT: GLfloat = 48.0;
+// The frame time at the top of the graph, and the one frames should beat,
+// marked across it.
+const GRAPH_FRAME_TIME: f64 = 1.0 / 30.0;
+const TARGET_FRAME_TIME: f64 = 1.0 / 60.0;
+
+const TEXT_COLOR: [GLfloat; 4] = [1.0, 1.0, 1.0, 1.0];
+const PANEL_COLOR: [GLfloat; 4] = [0.0, 0.0, 0.0, 0.6];
+const FAST_COLOR: [GLfloat; 4] = [0.3, 0.9, 0.3, 0.9];
+const SLOW_COLOR: [GLfloat; 4] = [1.0, 0.3, 0.2, 0.9];
+const TARGET_COLOR: [GLfloat; 4] = [1.0, 1.0, 1.0, 0.4];
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct HudVertex {
+    // In pixels down and right from the top left of the window.
+    position: [GLfloat; 2],
+    texcoord: [GLfloat; 2],
+    color: [GLfloat; 4],
+}
+
+struct Uniforms {
+    screen_size: GLint,
+    font: GLint,
+}
+
+struct Attributes {
+    position: GLint,
+    texcoord: GLint,
+    color: GLint,
+}
+
+// Lines of text over a graph of recent frame times, in a panel in the top
+// left corner drawn over the finished frame. Text is in the bitmap font and
+// the panel and bars are its solid glyph, so everything is one draw.
+pub struct Hud {
+    pub visible: bool,
+    program: GLuint,
+    uniforms: Uniforms,
+    attributes: Attributes,
+    vertex_buffer: GLuint,
+    font_texture: GLuint,
+    vertices: Vec<HudVertex>,
+    // Seconds between frames, oldest first.
+    frame_times: VecDeque<f64>,
+    last_frame: Option<f64>,
+}
+
+impl Hud {
+    pub fn new() -> Hud {
+        Hud {
+            visible: false,
+            program: 0,
+            uniforms: Uniforms {
+                screen_size: 0,
+                font: 0,
+            },
+            attributes: Attributes {
+                position: 0,
+                texcoord: 0,
+                color: 0,
+            },
+            vertex_buffer: 0,
+            font_texture: 0,
+            vertices: vec![],
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+            last_frame: None,
+        }
+    }
+
+    // Returns false, leaving the overlay unavailable, if its shaders can't be
+    // loaded.
+    pub fn init(&mut self) -> bool {
+        let vertex_shader = gl_util::make_shader(gl::VERTEX_SHADER, "shaders/hud.v.glsl");
+        let fragment_shader = gl_util::make_shader(gl::FRAGMENT_SHADER, "shaders/hud.f.glsl");
+        if vertex_shader == 0 || fragment_shader == 0 {
+            return false;
+        }
+        self.program = gl_util::make_program(vertex_shader, fragment_shader);
+        if self.program == 0 {
+            return false;
+        }
+        gl_debug::label(gl::PROGRAM, self.program, "hud");
+
+        let texels = font::rasterize();
+        unsafe {
+            gl::GenTextures(1, &mut self.font_texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.font_texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0,
+                gl::R8 as GLint,
+                font::GLYPH_WIDTH * font::GLYPH_COUNT, font::GLYPH_HEIGHT, 0,
+                gl::RED, gl::UNSIGNED_BYTE,
+                texels.as_ptr() as *const raw::c_void
+            );
+
+            gl::GenBuffers(1, &mut self.vertex_buffer);
+            gl_debug::label(gl::TEXTURE, self.font_texture, "font");
+            gl_debug::label(gl::BUFFER, self.vertex_buffer, "hud vertices");
+
+            let uniform = |name: &str| {
+                let name = CString::new(name).unwrap();
+                gl::GetUniformLocation(self.program, name.as_ptr())
+            };
+            self.uniforms = Uniforms {
+                screen_size: uniform("screen_size"),
+                font: uniform("font"),
+            };
+            let attribute = |name: &str| {
+                let name = CString::new(name).unwrap();
+                gl::GetAttribLocation(self.program, name.as_ptr())
+            };
+            self.attributes = Attributes {
+                position: attribute("position"),
+                texcoord: attribute("texcoord"),
+                color: attribute("color"),
+            };
+        }
+
+        true
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible && self.program != 0;
+        println!("Overlay {}", if self.visible { "on" } else { "off" });
+    }
+
+    // Notes a frame starting at `now`, in seconds of real time.
+    pub fn frame(&mut self, now: f64) {
+        if let Some(last) = self.last_frame {
+            if self.frame_times.len() == FRAME_HISTORY {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(f64::max(now - last, 0.0));
+        }
+        self.last_frame = Some(now);
+    }
+
+    // Frames per second and the mean frame time in milliseconds over the
+    // graphed frames.
+    pub fn frame_rate(&self) -> (f64, f64) {
+        if self.frame_times.is_empty() {
+            return (0.0, 0.0);
+        }
+        let mean = self.frame_times.iter().sum::<f64>() / self.frame_times.len() as f64;
+        (if mean > 0.0 { 1.0 / mean } else { 0.0 }, mean * 1.0e3)
+    }
+
+    fn rect(&mut self, lo: [GLfloat; 2], hi: [GLfloat; 2], color: [GLfloat; 4]) {
+        // The middle of the solid glyph, clear of its neighbours.
+        let u = ((font::SOLID_GLYPH - font::FIRST_GLYPH) as GLfloat + 0.5) / font::GLYPH_COUNT as GLfloat;
+        self.quad(lo, hi, [u, 0.5], [u, 0.5], color);
+    }
+
+    fn quad(
+        &mut self, lo: [GLfloat; 2], hi: [GLfloat; 2],
+        texcoord_lo: [GLfloat; 2], texcoord_hi: [GLfloat; 2], color: [GLfloat; 4]
+    ) {
+        let corner = |x: usize, y: usize| HudVertex {
+            position: [if x == 0 { lo[0] } else { hi[0] }, if y == 0 { lo[1] } else { hi[1] }],
+            texcoord: [
+                if x == 0 { texcoord_lo[0] } else { texcoord_hi[0] },
+                if y == 0 { texcoord_lo[1] } else { texcoord_hi[1] },
+            ],
+            color: color,
+        };
+        self.vertices.extend_from_slice(&[
+            corner(0, 0), corner(1, 0), corner(1, 1),
+            corner(0, 0), corner(1, 1), corner(0, 1),
+        ]);
+    }
+
+    fn text(&mut self, origin: [GLfloat; 2], text: &str, color: [GLfloat; 4]) {
+        let (width, height) = (font::GLYPH_WIDTH as GLfloat * SCALE, font::GLYPH_HEIGHT as GLfloat * SCALE);
+        for (k, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            let glyph = font::glyph_index(c) as GLfloat;
+            let x = origin[0] + k as GLfloat * width;
+            let count = font::GLYPH_COUNT as GLfloat;
+            self.quad(
+                [x, origin[1]], [x + width, origin[1] + height],
+                [glyph / count, 0.0], [(glyph + 1.0) / count, 1.0], color
+            );
+        }
+    }
+
+    fn graph(&mut self, origin: [GLfloat; 2]) {
+        let times: Vec<f64> = self.frame_times.iter().cloned().collect();
+        let bottom = origin[1] + GRAPH_HEIGHT;
+        for (k, &time) in times.iter().enumerate() {
+            let x = origin[0] + k as GLfloat * BAR_WIDTH;
+            let height = (GRAPH_HEIGHT as f64 * f64::min(time / GRAPH_FRAME_TIME, 1.0)) as GLfloat;
+            let color = if time <= TARGET_FRAME_TIME { FAST_COLOR } else { SLOW_COLOR };
+            self.rect([x, bottom - height], [x + BAR_WIDTH, bottom], color);
+        }
+
+        let target = bottom - (GRAPH_HEIGHT as f64 * TARGET_FRAME_TIME / GRAPH_FRAME_TIME) as GLfloat;
+        let right = origin[0] + FRAME_HISTORY as GLfloat * BAR_WIDTH;
+        self.rect([origin[0], target], [right, target + 1.0], TARGET_COLOR);
+    }
+
+    // Draws `lines` and the frame time graph into whatever framebuffer is
+    // bound, which is `width` by `height`.
+    pub fn render(&mut self, lines: &[String], width: GLsizei, height: GLsizei) {
+        if !self.visible {
+            return;
+        }
+
+        let line_height = font::GLYPH_HEIGHT as GLfloat * SCALE;
+        let longest = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        let text_width = longest as GLfloat * font::GLYPH_WIDTH as GLfloat * SCALE;
+        let panel_width = f32::max(text_width, FRAME_HISTORY as GLfloat * BAR_WIDTH) + 2.0 * PADDING;
+        let panel_height = lines.len() as GLfloat * line_height + GRAPH_HEIGHT + 3.0 * PADDING;
+
+        self.vertices.clear();
+        self.rect([MARGIN, MARGIN], [MARGIN + panel_width, MARGIN + panel_height], PANEL_COLOR);
+        let left = MARGIN + PADDING;
+        for (k, line) in lines.iter().enumerate() {
+            self.text([left, MARGIN + PADDING + k as GLfloat * line_height], line, TEXT_COLOR);
+        }
+        self.graph([left, MARGIN + 2.0 * PADDING + lines.len() as GLfloat * line_height]);
+
+        let stride = mem::size_of::<HudVertex>() as GLint;
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+            gl::UseProgram(self.program);
+            gl::Uniform2f(self.uniforms.screen_size, width as GLfloat, height as GLfloat);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.font_texture);
+            gl::Uniform1i(self.uniforms.font, 0);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (self.vertices.len() * mem::size_of::<HudVertex>()) as GLsizeiptr,
+                self.vertices.as_ptr() as *const raw::c_void,
+                gl::STREAM_DRAW
+            );
+            gl::EnableVertexAttribArray(self.attributes.position as GLuint);
+            gl::EnableVertexAttribArray(self.attributes.texcoord as GLuint);
+            gl::EnableVertexAttribArray(self.attributes.color as GLuint);
+            gl::VertexAttribPointer(
+                self.attributes.position as GLuint, 2, gl::FLOAT, gl::FALSE, stride,
+                offset_of!(HudVertex, position) as *const raw::c_void
+            );
+            gl::VertexAttribPointer(
+                self.attributes.texcoord as GLuint, 2, gl::FLOAT, gl::FALSE, stride,
+                offset_of!(HudVertex, texcoord) as *const raw::c_void
+            );
+            gl::VertexAttribPointer(
+                self.attributes.color as GLuint, 4, gl::FLOAT, gl::FALSE, stride,
+                offset_of!(HudVertex, color) as *const raw::c_void
+            );
+            gl::DrawArrays(gl::TRIANGLES, 0, self.vertices.len() as GLsizei);
+            gl::DisableVertexAttribArray(self.attributes.position as GLuint);
+            gl::DisableVertexAttribArray(self.attributes.texcoord as GLuint);
+            gl::DisableVertexAttribArray(self.attributes.color as GLuint);
+
+            gl::Disable(gl::BLEND);
+            gl::Enable(gl::CULL_FACE);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+}
@@ -2,10 +2,57 @@ extern crate glfw;
 extern crate gl;
 extern crate tga;
 
+// Defined ahead of the modules so they can use it too.
+macro_rules! offset_of {
+    ($ty:ty, $field:ident) => {
+        &(*(0 as *const $ty)).$field as *const _ as usize
+    }
+}
+
+// Runs a GL call and, in debug builds where the driver isn't reporting
+// errors itself, reports any the call raised along with where it was made.
+macro_rules! gl_check {
+    ($call:expr) => {{
+        let result = $call;
+        if cfg!(debug_assertions) && !::gl_debug::active() {
+            ::gl_debug::check(stringify!($call), file!(), line!());
+        }
+        result
+    }}
+}
+
+mod gl_debug;
 mod file_util;
 mod gl_util;
 mod vec_util;
 mod meshes;
+mod flagpole;
+mod mesh_builder;
+mod obj;
+mod png;
+mod export;
+mod capture;
+mod video;
+mod halyard;
+mod simulation;
+mod simd;
+mod flag_update;
+mod bench;
+mod time_control;
+mod cloth;
+mod collision;
+mod picking;
+mod paint;
+mod vertex_stream;
+mod atlas;
+mod detail_maps;
+mod flag_field;
+mod environment;
+mod post;
+mod scene;
+mod debug_views;
+mod font;
+mod hud;
 
 use glfw::{Glfw, Action, Context, Key};
 use gl::types::*;
@@ -13,24 +60,54 @@ use std::os::raw;
 use std::mem;
 use std::ptr;
 use std::env;
+use std::fs;
+use std::process;
 use std::f32;
 use std::ffi::CString;
+use std::rc::Rc;
 
 
+#[derive(Copy, Clone)]
 struct Uniforms {
     texture: GLint,
+    detail_normal_map: GLint,
+    roughness_map: GLint,
+    detail_scale: GLint,
+    irradiance_map: GLint,
+    prefiltered_map: GLint,
+    environment_max_lod: GLint,
+    fog: scene::FogUniforms,
+    // Only the physically based shader has these.
+    lobe: GLint,
+    metallic: GLint,
+    roughness: GLint,
+    sheen: GLint,
+    anisotropy: GLint,
+    brdf_lut: GLint,
     p_matrix: GLint,
     mv_matrix: GLint,
 }
 
+#[derive(Copy, Clone)]
 struct Attributes {
     position: GLint,
     normal: GLint,
+    tangent: GLint,
     texcoord: GLint,
     shininess: GLint,
     specular: GLint,
 }
 
+impl Attributes {
+    // The ones the program has. Shaders that don't read an attribute lose it
+    // when linked.
+    fn active(&self) -> Vec<GLuint> {
+        [self.position, self.normal, self.tangent, self.texcoord, self.shininess, self.specular]
+            .iter().filter_map(|&location| gl_util::attrib(location)).collect()
+    }
+}
+
+#[derive(Copy, Clone)]
 struct FlagProgram {
     vertex_shader: GLuint,
     fragment_shader: GLuint,
@@ -39,15 +116,86 @@ struct FlagProgram {
     attributes: Attributes,
 }
 
+impl FlagProgram {
+    fn new() -> FlagProgram {
+        FlagProgram {
+            vertex_shader: 0,
+            fragment_shader: 0,
+            program: 0,
+            uniforms: Uniforms {
+                texture: 0,
+                detail_normal_map: 0,
+                roughness_map: 0,
+                detail_scale: 0,
+                irradiance_map: 0,
+                prefiltered_map: 0,
+                environment_max_lod: 0,
+                fog: scene::FogUniforms::new(),
+                lobe: 0,
+                metallic: 0,
+                roughness: 0,
+                sheen: 0,
+                anisotropy: 0,
+                brdf_lut: 0,
+                p_matrix: 0,
+                mv_matrix: 0,
+            },
+            attributes: Attributes {
+                position: 0,
+                normal: 0,
+                tangent: 0,
+                texcoord: 0,
+                shininess: 0,
+                specular: 0,
+            },
+        }
+    }
+}
+
+// These programs, and the debug views' surface programs, share the vertex
+// shader.
+const PHONG_FRAGMENT_SHADER: &str = "shaders/flag.f.glsl";
+const PBR_FRAGMENT_SHADER: &str = "shaders/pbr.f.glsl";
+
 struct GResources {
     flag: meshes::FlagMesh,
     background: meshes::FlagMesh,
+    flagpole: meshes::FlagMesh,
+    rope: meshes::FlagMesh,
+    props: Vec<meshes::FlagMesh>,
+    field: flag_field::FlagField,
+    environment: environment::Environment,
+    post: post::PostProcess,
+    scene: scene::SceneOptions,
+    debug: debug_views::DebugViews,
+    hud: hud::Hud,
     flag_vertex_array: Vec<meshes::FlagVertex>,
+    flag_element_array: Rc<Vec<GLushort>>,
+    flag_stream: vertex_stream::VertexStream,
+    readout_time: f64,
+    rope_vertex_array: Vec<meshes::FlagVertex>,
+    simulation: simulation::Simulation,
+    time_control: time_control::TimeControl,
+    capture: capture::Capture,
+    offscreen: Option<gl_util::Framebuffer>,
     flag_program: FlagProgram,
+    pbr_program: FlagProgram,
+    // Whether meshes are drawn with pbr_program rather than the Phong
+    // flag_program.
+    physically_based: bool,
+    // Draw meshes in place of either while debug.surface isn't Shaded, one
+    // for each of debug_views::SURFACE_VIEWS. A view whose shader failed to
+    // load has a program of 0 and is skipped.
+    debug_programs: [FlagProgram; 3],
+    detail_maps_enabled: bool,
     p_matrix: [GLfloat; 16],
     mv_matrix: [GLfloat; 16],
     eye_offset: [GLfloat; 2],
     window_size: [GLfloat; 2],
+    grab_distance: Option<GLfloat>,
+    dragging_view: bool,
+    paint: paint::Paint,
+    painting: bool,
 }
 
 impl GResources {
@@ -58,35 +206,66 @@ impl GResources {
                 element_buffer: 0,
                 element_count: 0,
                 texture: 0,
+                detail: meshes::DetailMaps::none(),
+                material: meshes::Material::standard(1.0, 0.0),
             },
             background: meshes::FlagMesh {
                 vertex_buffer: 0,
                 element_buffer: 0,
                 element_count: 0,
                 texture: 0,
+                detail: meshes::DetailMaps::none(),
+                material: meshes::Material::standard(1.0, 0.0),
             },
-            flag_vertex_array: vec![],
-            flag_program: FlagProgram {
-                vertex_shader: 0,
-                fragment_shader: 0,
-                program: 0,
-                uniforms: Uniforms {
-                    texture: 0,
-                    p_matrix: 0,
-                    mv_matrix: 0,
-                },
-                attributes: Attributes {
-                    position: 0,
-                    normal: 0,
-                    texcoord: 0,
-                    shininess: 0,
-                    specular: 0,
-                },
+            flagpole: meshes::FlagMesh {
+                vertex_buffer: 0,
+                element_buffer: 0,
+                element_count: 0,
+                texture: 0,
+                detail: meshes::DetailMaps::none(),
+                material: meshes::Material::standard(1.0, 0.0),
+            },
+            rope: meshes::FlagMesh {
+                vertex_buffer: 0,
+                element_buffer: 0,
+                element_count: 0,
+                texture: 0,
+                detail: meshes::DetailMaps::none(),
+                material: meshes::Material::standard(1.0, 0.0),
             },
+            props: vec![],
+            field: flag_field::FlagField::new(),
+            environment: environment::Environment::new(),
+            post: post::PostProcess::new(post::PostOptions::default()),
+            scene: scene::SceneOptions::default(),
+            debug: debug_views::DebugViews::new(),
+            hud: hud::Hud::new(),
+            flag_vertex_array: vec![],
+            flag_element_array: Rc::new(vec![]),
+            flag_stream: vertex_stream::VertexStream::new(vertex_stream::UploadStrategy::Orphan),
+            readout_time: 0.0,
+            rope_vertex_array: vec![],
+            simulation: simulation::Simulation::new(
+                simulation::SimulationState::new(vec![], vec![], vec![]),
+                flagpole::FlagpoleProfile::default(),
+                &simulation::SimulationOptions::default()
+            ),
+            time_control: time_control::TimeControl::new(),
+            capture: capture::Capture::new(),
+            offscreen: None,
+            detail_maps_enabled: true,
+            flag_program: FlagProgram::new(),
+            pbr_program: FlagProgram::new(),
+            debug_programs: [FlagProgram::new(); 3],
+            physically_based: false,
             p_matrix: [0.0; 16],
             mv_matrix: [0.0; 16],
             eye_offset: [0.0; 2],
             window_size: [0.0; 2],
+            grab_distance: None,
+            dragging_view: false,
+            paint: paint::Paint::new(),
+            painting: false,
         }
     }
 
@@ -100,7 +279,11 @@ fn init_gl_state() {
     unsafe {
         gl::Enable(gl::DEPTH_TEST);
         gl::Enable(gl::CULL_FACE);
+        gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
         gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+        // Pixels handed to GL are tightly packed everywhere, BGR rows of any
+        // width included, so rows never start on a padded boundary.
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
     }
 }
 
@@ -136,50 +319,65 @@ fn update_mv_matrix(matrix: &mut [GLfloat], eye_offset: &[GLfloat]) {
     matrix[15] = 1.0;
 }
 
-macro_rules! offset_of {
-    ($ty:ty, $field:ident) => {
-        &(*(0 as *const $ty)).$field as *const _ as usize
+fn current_program(g_resources: &GResources) -> &FlagProgram {
+    if let Some(k) = g_resources.debug.surface.index() {
+        &g_resources.debug_programs[k]
+    } else if g_resources.physically_based {
+        &g_resources.pbr_program
+    } else {
+        &g_resources.flag_program
     }
 }
 
-fn render_mesh(g_resources: &GResources, mesh: &meshes::FlagMesh) {
+// Positions and normals come from `stream` instead of the mesh when given.
+fn render_mesh(g_resources: &GResources, mesh: &meshes::FlagMesh, stream: Option<&vertex_stream::VertexStream>) {
+    let program = current_program(g_resources);
+    let detail_scale = if g_resources.detail_maps_enabled { mesh.detail.scale } else { [0.0; 2] };
+    let material = &mesh.material;
     unsafe {
-        gl::BindTexture(gl::TEXTURE_2D, mesh.texture);
-
-        gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vertex_buffer);
-        gl::VertexAttribPointer(
-            g_resources.flag_program.attributes.position as GLuint,
-            3, gl::FLOAT, gl::FALSE, mem::size_of::<meshes::FlagVertex>() as GLint,
-            offset_of!(meshes::FlagVertex, position) as *const raw::c_void
-        );
-        gl::VertexAttribPointer(
-            g_resources.flag_program.attributes.normal as GLuint,
-            3, gl::FLOAT, gl::FALSE, mem::size_of::<meshes::FlagVertex>() as GLint,
-            offset_of!(meshes::FlagVertex, normal) as *const raw::c_void
-        );
-        gl::VertexAttribPointer(
-            g_resources.flag_program.attributes.texcoord as GLuint,
-            2, gl::FLOAT, gl::FALSE, mem::size_of::<meshes::FlagVertex>() as GLint,
-            offset_of!(meshes::FlagVertex, texcoord) as *const raw::c_void
-        );
-        gl::VertexAttribPointer(
-            g_resources.flag_program.attributes.shininess as GLuint,
-            1, gl::FLOAT, gl::FALSE, mem::size_of::<meshes::FlagVertex>() as GLint,
-            offset_of!(meshes::FlagVertex, shininess) as *const raw::c_void
-        );
-        gl::VertexAttribPointer(
-            g_resources.flag_program.attributes.specular as GLuint,
-            4, gl::UNSIGNED_BYTE, gl::TRUE, mem::size_of::<meshes::FlagVertex>() as GLint,
-            offset_of!(meshes::FlagVertex, specular) as *const raw::c_void
-        );
+        gl_check!(gl::Uniform1i(program.uniforms.lobe, material.lobe as GLint));
+        gl_check!(gl::Uniform1f(program.uniforms.metallic, material.metallic));
+        gl_check!(gl::Uniform1f(program.uniforms.roughness, material.roughness));
+        gl_check!(gl::Uniform3f(program.uniforms.sheen, material.sheen[0], material.sheen[1], material.sheen[2]));
+        gl_check!(gl::Uniform1f(program.uniforms.anisotropy, material.anisotropy));
+
+        gl_check!(gl::ActiveTexture(gl::TEXTURE1));
+        gl_check!(gl::BindTexture(gl::TEXTURE_2D, mesh.detail.normal_map));
+        gl_check!(gl::ActiveTexture(gl::TEXTURE2));
+        gl_check!(gl::BindTexture(gl::TEXTURE_2D, mesh.detail.roughness_map));
+        gl_check!(gl::Uniform2f(program.uniforms.detail_scale, detail_scale[0], detail_scale[1]));
+        gl_check!(gl::ActiveTexture(gl::TEXTURE0));
+        gl_check!(gl::BindTexture(gl::TEXTURE_2D, mesh.texture));
+
+        let attributes = &program.attributes;
+        let pointers = [
+            (attributes.position, 3, gl::FLOAT, gl::FALSE, offset_of!(meshes::FlagVertex, position)),
+            (attributes.normal, 3, gl::FLOAT, gl::FALSE, offset_of!(meshes::FlagVertex, normal)),
+            (attributes.tangent, 4, gl::FLOAT, gl::FALSE, offset_of!(meshes::FlagVertex, tangent)),
+            (attributes.texcoord, 2, gl::FLOAT, gl::FALSE, offset_of!(meshes::FlagVertex, texcoord)),
+            (attributes.shininess, 1, gl::FLOAT, gl::FALSE, offset_of!(meshes::FlagVertex, shininess)),
+            (attributes.specular, 4, gl::UNSIGNED_BYTE, gl::TRUE, offset_of!(meshes::FlagVertex, specular)),
+        ];
+        gl_check!(gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vertex_buffer));
+        for &(location, size, kind, normalized, offset) in pointers.iter() {
+            if let Some(location) = gl_util::attrib(location) {
+                gl_check!(gl::VertexAttribPointer(
+                    location, size, kind, normalized, mem::size_of::<meshes::FlagVertex>() as GLint,
+                    offset as *const raw::c_void
+                ));
+            }
+        }
+        if let Some(stream) = stream {
+            stream.bind(attributes.position, attributes.normal, attributes.tangent);
+        }
 
-        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, mesh.element_buffer);
-        gl::DrawElements(
+        gl_check!(gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, mesh.element_buffer));
+        gl_check!(gl::DrawElements(
             gl::TRIANGLES,
             mesh.element_count,
             gl::UNSIGNED_SHORT,
             ptr::null()
-        );
+        ));
     }
 }
 
@@ -187,43 +385,84 @@ const INITIAL_WINDOW_WIDTH: u32 = 640;
 const INITIAL_WINDOW_HEIGHT: u32 = 480;
 
 fn enact_flag_program(
-    g_resources: &mut GResources,
+    flag_program: &mut FlagProgram,
     vertex_shader: GLuint, fragment_shader: GLuint, program: GLuint
 ) {
-    g_resources.flag_program.vertex_shader = vertex_shader;
-    g_resources.flag_program.fragment_shader = fragment_shader;
+    flag_program.vertex_shader = vertex_shader;
+    flag_program.fragment_shader = fragment_shader;
 
-    g_resources.flag_program.program = program;
+    flag_program.program = program;
 
     unsafe {
         let texture_cstr = CString::new("texture").unwrap();
-        g_resources.flag_program.uniforms.texture
+        flag_program.uniforms.texture
             = gl::GetUniformLocation(program, texture_cstr.as_ptr());
+        let detail_normal_map_cstr = CString::new("detail_normal_map").unwrap();
+        flag_program.uniforms.detail_normal_map
+            = gl::GetUniformLocation(program, detail_normal_map_cstr.as_ptr());
+        let roughness_map_cstr = CString::new("roughness_map").unwrap();
+        flag_program.uniforms.roughness_map
+            = gl::GetUniformLocation(program, roughness_map_cstr.as_ptr());
+        let detail_scale_cstr = CString::new("detail_scale").unwrap();
+        flag_program.uniforms.detail_scale
+            = gl::GetUniformLocation(program, detail_scale_cstr.as_ptr());
+        let irradiance_map_cstr = CString::new("irradiance_map").unwrap();
+        flag_program.uniforms.irradiance_map
+            = gl::GetUniformLocation(program, irradiance_map_cstr.as_ptr());
+        let prefiltered_map_cstr = CString::new("prefiltered_map").unwrap();
+        flag_program.uniforms.prefiltered_map
+            = gl::GetUniformLocation(program, prefiltered_map_cstr.as_ptr());
+        let environment_max_lod_cstr = CString::new("environment_max_lod").unwrap();
+        flag_program.uniforms.environment_max_lod
+            = gl::GetUniformLocation(program, environment_max_lod_cstr.as_ptr());
+        flag_program.uniforms.fog = scene::FogUniforms::locate(program);
+        let lobe_cstr = CString::new("lobe").unwrap();
+        flag_program.uniforms.lobe
+            = gl::GetUniformLocation(program, lobe_cstr.as_ptr());
+        let metallic_cstr = CString::new("metallic").unwrap();
+        flag_program.uniforms.metallic
+            = gl::GetUniformLocation(program, metallic_cstr.as_ptr());
+        let roughness_cstr = CString::new("roughness").unwrap();
+        flag_program.uniforms.roughness
+            = gl::GetUniformLocation(program, roughness_cstr.as_ptr());
+        let sheen_cstr = CString::new("sheen").unwrap();
+        flag_program.uniforms.sheen
+            = gl::GetUniformLocation(program, sheen_cstr.as_ptr());
+        let anisotropy_cstr = CString::new("anisotropy").unwrap();
+        flag_program.uniforms.anisotropy
+            = gl::GetUniformLocation(program, anisotropy_cstr.as_ptr());
+        let brdf_lut_cstr = CString::new("brdf_lut").unwrap();
+        flag_program.uniforms.brdf_lut
+            = gl::GetUniformLocation(program, brdf_lut_cstr.as_ptr());
         let p_matrix_cstr = CString::new("p_matrix").unwrap();
-        g_resources.flag_program.uniforms.p_matrix
+        flag_program.uniforms.p_matrix
             = gl::GetUniformLocation(program, p_matrix_cstr.as_ptr());
         let mv_matrix_cstr = CString::new("mv_matrix").unwrap();
-        g_resources.flag_program.uniforms.mv_matrix
+        flag_program.uniforms.mv_matrix
             = gl::GetUniformLocation(program, mv_matrix_cstr.as_ptr());
         let position_cstr = CString::new("position").unwrap();
-        g_resources.flag_program.attributes.position
+        flag_program.attributes.position
             = gl::GetAttribLocation(program, position_cstr.as_ptr());
         let normal_cstr = CString::new("normal").unwrap();
-        g_resources.flag_program.attributes.normal
+        flag_program.attributes.normal
             = gl::GetAttribLocation(program, normal_cstr.as_ptr());
+        let tangent_cstr = CString::new("tangent").unwrap();
+        flag_program.attributes.tangent
+            = gl::GetAttribLocation(program, tangent_cstr.as_ptr());
         let texcoord_cstr = CString::new("texcoord").unwrap();
-        g_resources.flag_program.attributes.texcoord
+        flag_program.attributes.texcoord
             = gl::GetAttribLocation(program, texcoord_cstr.as_ptr());
         let shininess_cstr = CString::new("shininess").unwrap();
-        g_resources.flag_program.attributes.shininess
+        flag_program.attributes.shininess
             = gl::GetAttribLocation(program, shininess_cstr.as_ptr());
         let specular_cstr = CString::new("specular").unwrap();
-        g_resources.flag_program.attributes.specular
+        flag_program.attributes.specular
             = gl::GetAttribLocation(program, specular_cstr.as_ptr());
         }
 }
 
 fn make_flag_program(
+    fragment_shader_path: &str,
     vertex_shader: &mut GLuint, 
     fragment_shader: &mut GLuint, program: &mut GLuint) -> isize {
 
@@ -232,7 +471,9 @@ fn make_flag_program(
         return 0;
     }
 
-    *fragment_shader = gl_util::make_shader(gl::FRAGMENT_SHADER, "shaders/flag.f.glsl");
+    *fragment_shader = gl_util::make_shader_with_library(
+        gl::FRAGMENT_SHADER, scene::FOG_SHADER, fragment_shader_path
+    );
     if *fragment_shader == 0 {
         return 0;
     }
@@ -241,62 +482,272 @@ fn make_flag_program(
     if *program == 0 {
         return 0;
     }
+    gl_debug::label(gl::PROGRAM, *program, fragment_shader_path);
 
     return 1;
 }
 
-fn delete_flag_program(g_resources: &GResources) {
+fn delete_flag_program(flag_program: &FlagProgram) {
     unsafe {
         gl::DetachShader(
-            g_resources.flag_program.program,
-            g_resources.flag_program.vertex_shader
+            flag_program.program,
+            flag_program.vertex_shader
         );
         gl::DetachShader(
-            g_resources.flag_program.program,
-            g_resources.flag_program.fragment_shader
+            flag_program.program,
+            flag_program.fragment_shader
         );
-        gl::DeleteProgram(g_resources.flag_program.program);
-        gl::DeleteShader(g_resources.flag_program.vertex_shader);
-        gl::DeleteShader(g_resources.flag_program.fragment_shader);
+        gl::DeleteProgram(flag_program.program);
+        gl::DeleteShader(flag_program.vertex_shader);
+        gl::DeleteShader(flag_program.fragment_shader);
     }
 }
 
-fn update_flag_program(g_resources: &mut GResources) {
-    println!("reloading program\n");
+fn update_flag_program(flag_program: &mut FlagProgram, fragment_shader_path: &str) {
     let mut vertex_shader: GLuint = 0; 
     let mut fragment_shader: GLuint = 0; 
     let mut program: GLuint = 0;
 
-    if make_flag_program(&mut vertex_shader, &mut fragment_shader, &mut program) != 0 {
-        delete_flag_program(g_resources);
-        enact_flag_program(g_resources, vertex_shader, fragment_shader, program);
+    if make_flag_program(fragment_shader_path, &mut vertex_shader, &mut fragment_shader, &mut program) != 0 {
+        delete_flag_program(flag_program);
+        enact_flag_program(flag_program, vertex_shader, fragment_shader, program);
     }
 }
 
-fn make_resources() -> Option<GResources> {
+fn update_flag_programs(g_resources: &mut GResources) {
+    println!("reloading program\n");
+    update_flag_program(&mut g_resources.flag_program, PHONG_FRAGMENT_SHADER);
+    update_flag_program(&mut g_resources.pbr_program, PBR_FRAGMENT_SHADER);
+    for (program, view) in g_resources.debug_programs.iter_mut().zip(debug_views::SURFACE_VIEWS.iter()) {
+        update_flag_program(program, view.fragment_shader().unwrap());
+    }
+}
+
+// Keeps the current settings if the scene file can't be read.
+fn load_scene(g_resources: &mut GResources) {
+    match scene::SceneOptions::load(scene::SCENE_FILE) {
+        Ok(options) => g_resources.scene = options,
+        Err(e) => eprintln!("Ignoring {}: {}", scene::SCENE_FILE, e),
+    }
+}
+
+// Falls back to the default pole if the flagpole file can't be read.
+fn load_flagpole() -> flagpole::FlagpoleProfile {
+    match flagpole::FlagpoleProfile::load(flagpole::CONFIG_FILE) {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("Ignoring {}: {}", flagpole::CONFIG_FILE, e);
+            flagpole::FlagpoleProfile::default()
+        }
+    }
+}
+
+const PROPS_DIRECTORY: &str = "assets/props";
+const ATLAS_SIZE: i32 = 1024;
+// Tiles of detail across the flag, fewer along t as it is shorter that way,
+// and across the atlas the flagpole is drawn from.
+const FLAG_WEAVE_REPEATS: [GLfloat; 2] = [24.0, 18.0];
+const FLAGPOLE_GRAIN_REPEATS: [GLfloat; 2] = [32.0, 32.0];
+
+// Roughness here matches the average of each mesh's roughness map, so
+// toggling detail maps doesn't change the overall look.
+const FLAG_ROUGHNESS: GLfloat = 0.7;
+const FLAG_SHEEN: [GLfloat; 3] = [0.3, 0.3, 0.3];
+const FLAGPOLE_ROUGHNESS: GLfloat = 0.5;
+// The pole's grain runs around it, so highlights stretch along its length.
+const FLAGPOLE_ANISOTROPY: GLfloat = -0.6;
+const ROPE_ROUGHNESS: GLfloat = 0.9;
+const ROPE_SHEEN: [GLfloat; 3] = [0.2, 0.2, 0.2];
+
+// Uploads a normal and roughness map pair as textures that tile `scale`
+// times across a mesh's texture coordinates.
+fn make_detail_maps((normal_map, roughness_map): (atlas::Image, atlas::Image), scale: [GLfloat; 2]) -> meshes::DetailMaps {
+    meshes::DetailMaps {
+        normal_map: gl_util::make_tiling_texture(&normal_map.pixels, normal_map.width, normal_map.height),
+        roughness_map: gl_util::make_tiling_texture(&roughness_map.pixels, roughness_map.width, roughness_map.height),
+        scale: scale,
+    }
+}
+
+// Every OBJ model in the props directory is drawn as part of the scene.
+// Models without a TGA diffuse map share the background texture. Props are
+// packed into the atlas with the background where their textures allow.
+fn load_props(g_resources: &mut GResources, atlas: &mut atlas::Atlas, background_region: &atlas::Region) {
+    let entries = match fs::read_dir(PROPS_DIRECTORY) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => continue,
+        };
+        if path.extension().map_or(true, |ext| ext != "obj") {
+            continue;
+        }
+
+        let obj_meshes = match obj::load_obj(&path) {
+            Ok(obj_meshes) => obj_meshes,
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        for mut obj_mesh in obj_meshes {
+            // The MTL file's exponent is carried on every vertex.
+            let shininess = obj_mesh.builder.vertices.first().map_or(0.0, |v| v.shininess);
+            let mut mesh = meshes::FlagMesh {
+                vertex_buffer: 0,
+                element_buffer: 0,
+                element_count: 0,
+                texture: atlas.texture,
+                detail: meshes::DetailMaps::none(),
+                material: meshes::Material::from_shininess(shininess),
+            };
+
+            // Props without a texture of their own draw from the background's.
+            let mut region = *background_region;
+            let diffuse_map = obj_mesh.diffuse_map.as_ref().and_then(|p| p.to_str());
+            if let Some(image) = diffuse_map.and_then(|filename| atlas::Image::load(filename).ok()) {
+                let packed = if atlas::fits_region(&obj_mesh.builder.vertices) { atlas.add(&image) } else { None };
+                match packed {
+                    Some(packed) => region = packed,
+                    None => {
+                        // Tiling textures, and any the atlas has no room
+                        // left for, keep a binding of their own.
+                        mesh.texture = gl_util::make_texture_from_pixels(&image.pixels, image.width, image.height);
+                        region = atlas::Region::whole();
+                    }
+                }
+            }
+            region.remap_vertices(&mut obj_mesh.builder.vertices);
+            obj_mesh.builder.build(&mut mesh, gl::STATIC_DRAW);
+
+            g_resources.props.push(mesh);
+        }
+    }
+}
+
+fn label_mesh(mesh: &meshes::FlagMesh, name: &str) {
+    gl_debug::label(gl::BUFFER, mesh.vertex_buffer, &format!("{} vertices", name));
+    gl_debug::label(gl::BUFFER, mesh.element_buffer, &format!("{} elements", name));
+}
+
+fn make_resources(options: &simulation::SimulationOptions) -> Option<GResources> {
     let mut vertex_shader: GLuint = 0;
     let mut fragment_shader: GLuint = 0;
     let mut program: GLuint = 0;
 
     let mut g_resources: GResources = GResources::new();
 
+    // The background, rope and props share one texture where they can.
+    let mut atlas = atlas::Atlas::new(ATLAS_SIZE, ATLAS_SIZE);
+    let background_region = match atlas::Image::load("assets/background.tga").ok().and_then(|image| atlas.add(&image)) {
+        Some(region) => region,
+        None => return None,
+    };
+
     // Load meshes.
-    g_resources.flag_vertex_array = meshes::init_flag_mesh(&mut g_resources.flag);
-    meshes::init_background_mesh(&mut g_resources.background);
+    let flagpole = load_flagpole();
+    let (flag_vertices, flag_elements) = meshes::init_flag_mesh(&mut g_resources.flag);
+    g_resources.flag_vertex_array = flag_vertices;
+    meshes::init_background_mesh(&mut g_resources.background, &background_region);
+    meshes::init_flagpole_mesh(&mut g_resources.flagpole, &background_region, &flagpole);
+    g_resources.rope_vertex_array = meshes::init_rope_mesh(&mut g_resources.rope, &background_region, &flagpole);
+    g_resources.simulation = simulation::Simulation::new(
+        simulation::SimulationState::new(
+            g_resources.flag_vertex_array.clone(), flag_elements, g_resources.rope_vertex_array.clone()
+        ),
+        flagpole,
+        options
+    );
+    g_resources.flag_element_array = g_resources.simulation.current.flag_elements.clone();
 
     // Create textures.
     g_resources.flag.texture = gl_util::make_texture("assets/flag.tga");
-    g_resources.background.texture = gl_util::make_texture("assets/background.tga");
+    g_resources.background.texture = atlas.texture;
+    g_resources.flagpole.texture = atlas.texture;
+    g_resources.rope.texture = atlas.texture;
 
-    if g_resources.flag.texture == 0 || g_resources.background.texture == 0 {
+    if g_resources.flag.texture == 0 {
         return None;
     }
 
-    if make_flag_program(&mut vertex_shader, &mut fragment_shader, &mut program) == 0 {
+    if let Err(e) = g_resources.paint.load("assets/flag.tga", g_resources.flag.texture) {
+        eprintln!("Painting unavailable: {}", e);
+    }
+
+    load_props(&mut g_resources, &mut atlas, &background_region);
+    atlas.upload();
+    gl_debug::label(gl::TEXTURE, atlas.texture, "atlas");
+    label_mesh(&g_resources.flag, "flag");
+    label_mesh(&g_resources.background, "background");
+    label_mesh(&g_resources.flagpole, "flagpole");
+    label_mesh(&g_resources.rope, "rope");
+    for (k, prop) in g_resources.props.iter().enumerate() {
+        label_mesh(prop, &format!("prop {}", k));
+    }
+
+    g_resources.flag.detail = make_detail_maps(detail_maps::weave(), FLAG_WEAVE_REPEATS);
+    g_resources.flagpole.detail = make_detail_maps(detail_maps::grain(), FLAGPOLE_GRAIN_REPEATS);
+
+    g_resources.flag.material = meshes::Material::cloth(FLAG_ROUGHNESS, FLAG_SHEEN);
+    g_resources.flagpole.material = meshes::Material::anisotropic_metal(FLAGPOLE_ROUGHNESS, FLAGPOLE_ANISOTROPY);
+    g_resources.rope.material = meshes::Material::cloth(ROPE_ROUGHNESS, ROPE_SHEEN);
+
+    if !g_resources.field.init() {
+        eprintln!("Flag field unavailable");
+    }
+
+    if !g_resources.environment.init() {
+        eprintln!("Sky unavailable");
+    }
+
+    load_scene(&mut g_resources);
+
+    let post_options = match post::PostOptions::load(post::CONFIG_FILE) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Ignoring {}: {}", post::CONFIG_FILE, e);
+            post::PostOptions::default()
+        }
+    };
+    g_resources.post = post::PostProcess::new(post_options);
+    if !g_resources.post.init() {
+        eprintln!("Post-processing unavailable");
+    }
+
+    if !g_resources.debug.init() {
+        eprintln!("Debug overlays unavailable");
+    }
+
+    if !g_resources.hud.init() {
+        eprintln!("Overlay unavailable");
+    }
+
+    if make_flag_program(PHONG_FRAGMENT_SHADER, &mut vertex_shader, &mut fragment_shader, &mut program) == 0 {
         return None;
     }
 
-    enact_flag_program(&mut g_resources, vertex_shader, fragment_shader, program);
+    enact_flag_program(&mut g_resources.flag_program, vertex_shader, fragment_shader, program);
+
+    if make_flag_program(PBR_FRAGMENT_SHADER, &mut vertex_shader, &mut fragment_shader, &mut program) == 0 {
+        return None;
+    }
+
+    enact_flag_program(&mut g_resources.pbr_program, vertex_shader, fragment_shader, program);
+
+    // The scene can do without these, as with the overlays.
+    for (k, view) in debug_views::SURFACE_VIEWS.iter().enumerate() {
+        let fragment_shader_path = view.fragment_shader().unwrap();
+        if make_flag_program(fragment_shader_path, &mut vertex_shader, &mut fragment_shader, &mut program) == 0 {
+            eprintln!("Debug {} view unavailable", view.name());
+            continue;
+        }
+        enact_flag_program(&mut g_resources.debug_programs[k], vertex_shader, fragment_shader, program);
+    }
 
     g_resources.eye_offset[0] = 0.0;
     g_resources.eye_offset[1] = 0.0;
@@ -313,9 +764,35 @@ fn make_resources() -> Option<GResources> {
     return Some(g_resources);
 }
 
+// Seconds between refreshes of the upload figures in the title bar.
+const READOUT_INTERVAL: f64 = 1.0;
+
 fn update(g_resources: &mut GResources, glfw: &mut Glfw, window: &mut glfw::Window) {
-    let seconds = glfw.get_time() as GLfloat;
-    meshes::update_flag_mesh(&g_resources.flag, &mut g_resources.flag_vertex_array, seconds);
+    g_resources.hud.frame(glfw.get_time());
+    let frame_time = g_resources.capture.frame_time(glfw);
+    let clock = g_resources.time_control.advance(frame_time);
+    g_resources.simulation.advance(clock);
+    g_resources.simulation.interpolate(
+        &mut g_resources.flag_vertex_array, &mut g_resources.rope_vertex_array
+    );
+    g_resources.flag_stream.upload(&g_resources.flag_vertex_array);
+    meshes::upload_vertices(&g_resources.rope, &g_resources.rope_vertex_array, gl::STREAM_DRAW);
+
+    // Tearing, stepping back over a tear or mending the flag changes its
+    // triangles, and may add or drop vertices along with their unchanging
+    // attributes.
+    if !Rc::ptr_eq(&g_resources.flag_element_array, &g_resources.simulation.current.flag_elements) {
+        g_resources.flag_element_array = g_resources.simulation.current.flag_elements.clone();
+        meshes::upload_elements(&mut g_resources.flag, &g_resources.flag_element_array);
+        meshes::upload_vertices(&g_resources.flag, &g_resources.flag_vertex_array, gl::DYNAMIC_DRAW);
+        label_mesh(&g_resources.flag, "flag");
+    }
+
+    let now = glfw.get_time();
+    if now - g_resources.readout_time >= READOUT_INTERVAL {
+        g_resources.readout_time = now;
+        window.set_title(&format!("Flag - {}", g_resources.flag_stream.readout()));
+    }
 
     // Poll events.
     glfw.poll_events();
@@ -329,17 +806,191 @@ fn drag(g_resources: &mut GResources, x: i32, y: i32) {
     update_mv_matrix(&mut g_resources.mv_matrix, &g_resources.eye_offset);
 }
 
+fn cursor_ray(g_resources: &GResources, x: i32, y: i32) -> Option<picking::Ray> {
+    picking::cursor_ray(
+        &g_resources.p_matrix, &g_resources.mv_matrix,
+        x as GLfloat, y as GLfloat, g_resources.window_size
+    )
+}
+
+// Picks the flag under the cursor and pins the nearest vertex of the hit
+// triangle to it, switching the cloth solver on if need be.
+fn grab(g_resources: &mut GResources, x: i32, y: i32) -> bool {
+    let ray = match cursor_ray(g_resources, x, y) {
+        Some(ray) => ray,
+        None => return false,
+    };
+    let hit = match picking::intersect_mesh(
+        &ray, &g_resources.flag_vertex_array, &g_resources.flag_element_array
+    ) {
+        Some(hit) => hit,
+        None => return false,
+    };
+
+    if !g_resources.simulation.cloth_enabled() {
+        g_resources.simulation.toggle_cloth();
+        println!("Cloth simulation on");
+    }
+    g_resources.simulation.grab(hit.nearest_vertex(), ray.at(hit.distance));
+    g_resources.grab_distance = Some(hit.distance);
+    true
+}
+
+// Paints where the cursor meets the flag, lifting the brush off the edges.
+fn paint_at(g_resources: &mut GResources, x: i32, y: i32) {
+    let hit = cursor_ray(g_resources, x, y).and_then(|ray| {
+        picking::intersect_mesh(&ray, &g_resources.flag_vertex_array, &g_resources.flag_element_array)
+    });
+    match hit {
+        Some(hit) => {
+            let texcoord = hit.texcoord(&g_resources.flag_vertex_array);
+            g_resources.paint.stroke(texcoord);
+        }
+        None => g_resources.paint.lift(),
+    }
+}
+
 fn mouse(g_resources: &mut GResources, button: glfw::MouseButton, state: i32, x: i32, y: i32) {
-    if button == glfw::MouseButton::Button1 && state == 1 /* && (state == GLUT_UP) */ {
+    if button != glfw::MouseButton::Button1 {
+        return;
+    }
+
+    if state == 0 /* GLUT_DOWN */ {
+        if g_resources.paint.enabled {
+            g_resources.painting = true;
+            paint_at(g_resources, x, y);
+        } else if !grab(g_resources, x, y) {
+            g_resources.dragging_view = true;
+            drag(g_resources, x, y);
+        }
+        return;
+    }
+
+    if g_resources.painting {
+        g_resources.painting = false;
+        g_resources.paint.lift();
+    }
+    if g_resources.grab_distance.take().is_some() {
+        g_resources.simulation.release();
+    }
+    if g_resources.dragging_view {
+        g_resources.dragging_view = false;
         g_resources.eye_offset[0] = 0.0;
         g_resources.eye_offset[1] = 0.0;
         update_mv_matrix(&mut g_resources.mv_matrix, &g_resources.eye_offset);
     }
 }
 
+fn motion(g_resources: &mut GResources, x: i32, y: i32) {
+    if g_resources.painting {
+        paint_at(g_resources, x, y);
+    } else if let Some(distance) = g_resources.grab_distance {
+        if let Some(ray) = cursor_ray(g_resources, x, y) {
+            g_resources.simulation.move_grab(ray.at(distance));
+        }
+    } else if g_resources.dragging_view {
+        drag(g_resources, x, y);
+    }
+}
+
+fn paint_keyboard(g_resources: &mut GResources, key: Key) -> bool {
+    let paint = &mut g_resources.paint;
+    match key {
+        Key::Num1 => paint.select_color(0),
+        Key::Num2 => paint.select_color(1),
+        Key::Num3 => paint.select_color(2),
+        Key::Num4 => paint.select_color(3),
+        Key::Num5 => paint.select_color(4),
+        Key::Num6 => paint.select_color(5),
+        Key::Num7 => paint.select_color(6),
+        Key::Num8 => paint.select_color(7),
+        Key::LeftBracket => paint.resize_brush(false),
+        Key::RightBracket => paint.resize_brush(true),
+        Key::F6 => match paint.save() {
+            Ok(filename) => println!("Saved {}", filename),
+            Err(e) => eprintln!("Failed to save the painted flag: {}", e),
+        },
+        _ => return false,
+    }
+
+    true
+}
+
 fn keyboard(g_resources: &mut GResources, key: Key, x: i32, y: i32) {
-    if key == Key::R {
-        update_flag_program(g_resources);
+    if g_resources.paint.enabled && paint_keyboard(g_resources, key) {
+        return;
+    }
+
+    match key {
+        Key::R => {
+            update_flag_programs(g_resources);
+            load_scene(g_resources);
+        }
+        Key::Up => g_resources.simulation.current.halyard.command(halyard::HalyardCommand::Hoist),
+        Key::Down => g_resources.simulation.current.halyard.command(halyard::HalyardCommand::Lower),
+        Key::H => g_resources.simulation.current.halyard.command(halyard::HalyardCommand::HalfMast),
+        Key::S => g_resources.simulation.current.halyard.command(halyard::HalyardCommand::Stop),
+        Key::F12 => g_resources.capture.request_screenshot(),
+        Key::F9 => g_resources.capture.toggle_recording(),
+        Key::F8 => g_resources.capture.cycle_format(),
+        Key::Space => g_resources.time_control.toggle_pause(),
+        Key::Minus => g_resources.time_control.slower(),
+        Key::Equal => g_resources.time_control.faster(),
+        Key::Num0 => g_resources.time_control.reset_scale(),
+        Key::Period | Key::Comma => step(g_resources, key),
+        Key::P => g_resources.paint.toggle(),
+        Key::F => g_resources.field.toggle(),
+        Key::F1 => g_resources.post.toggle(),
+        Key::F2 => g_resources.post.cycle_msaa(),
+        Key::F3 => g_resources.post.cycle_tone_mapping(),
+        Key::B => g_resources.post.toggle_bloom(),
+        Key::X => g_resources.post.toggle_fxaa(),
+        Key::V => g_resources.post.toggle_vignette(),
+        Key::G => g_resources.post.toggle_grading(),
+        Key::O => {
+            g_resources.scene.fog = !g_resources.scene.fog;
+            println!("Fog {}", if g_resources.scene.fog { "on" } else { "off" });
+        }
+        Key::M => {
+            g_resources.physically_based = !g_resources.physically_based;
+            println!("{} shading", if g_resources.physically_based { "Physically based" } else { "Phong" });
+        }
+        Key::N => {
+            g_resources.detail_maps_enabled = !g_resources.detail_maps_enabled;
+            println!("Detail maps {}", if g_resources.detail_maps_enabled { "on" } else { "off" });
+        }
+        Key::C => {
+            let enabled = g_resources.simulation.toggle_cloth();
+            println!("Cloth simulation {}", if enabled { "on" } else { "off" });
+        }
+        Key::U => {
+            g_resources.flag_stream.cycle_strategy();
+            println!("Uploading flag vertices by {}", g_resources.flag_stream.strategy().name());
+        }
+        Key::F4 => g_resources.debug.toggle_wireframe(),
+        Key::F5 => g_resources.debug.toggle_normals(),
+        Key::F7 => {
+            let programs = &g_resources.debug_programs;
+            g_resources.debug.cycle_surface(|view| view.index().map_or(false, |k| programs[k].program != 0));
+        }
+        Key::F10 => g_resources.debug.toggle_strain(),
+        Key::Tab => g_resources.hud.toggle(),
+        Key::W => {
+            let storm = g_resources.simulation.toggle_storm();
+            println!("Storm {}", if storm { "on" } else { "off" });
+        }
+        _ => {}
+    }
+}
+
+// Single steps pause the animation first. Holding the key repeats the step,
+// which scrubs through the recent history.
+fn step(g_resources: &mut GResources, key: Key) {
+    g_resources.time_control.pause();
+    if key == Key::Period {
+        g_resources.simulation.step_forward();
+    } else if !g_resources.simulation.step_back() {
+        println!("No earlier states to step back to");
     }
 }
 
@@ -352,56 +1003,174 @@ fn reshape(g_resources: &mut GResources, w: i32, h: i32) {
     }
 }
 
+fn render_debug_views(g_resources: &mut GResources) {
+    let g = g_resources;
+    let mut meshes = vec![&g.background, &g.flagpole, &g.rope];
+    meshes.extend(g.props.iter());
+    g.debug.render_wireframe(&g.p_matrix, &g.mv_matrix, &g.flag, &g.flag_stream, &meshes);
+    g.debug.render_normals(&g.p_matrix, &g.mv_matrix, &[&g.flag_vertex_array, &g.rope_vertex_array]);
+    if let Some(ref cloth) = g.simulation.current.cloth {
+        g.debug.render_strain(&g.p_matrix, &g.mv_matrix, &g.flag_vertex_array, cloth.constraints());
+    }
+}
+
+fn hud_lines(g_resources: &GResources) -> Vec<String> {
+    let g = g_resources;
+    let (frame_rate, frame_time) = g.hud.frame_rate();
+    let step_time = g.simulation.step_time();
+    let step_time = step_time.as_secs() as f64 * 1.0e3 + step_time.subsec_nanos() as f64 * 1.0e-6;
+
+    let mut meshes = vec![&g.flag, &g.background, &g.flagpole, &g.rope];
+    meshes.extend(g.props.iter());
+    let triangles: GLsizei = meshes.iter().map(|mesh| mesh.element_count / 3).sum();
+
+    let wind = match g.simulation.wind() {
+        Some(wind) => format!(
+            "Wind {:.1}, {:.1}, {:.1}{}", wind[0], wind[1], wind[2],
+            if g.simulation.current.cloth.as_ref().map_or(false, |cloth| cloth.storm()) { ", storm" } else { "" }
+        ),
+        None => format!("Scripted wave, amplitude {:.3}", g.simulation.wave_amplitude()),
+    };
+
+    let mut modes = vec![String::from(if g.physically_based { "PBR" } else { "Phong" })];
+    if g.debug.surface != debug_views::SurfaceView::Shaded {
+        modes.push(String::from(g.debug.surface.name()));
+    }
+    modes.push(if g.time_control.paused() {
+        String::from("paused")
+    } else {
+        format!("{}x", g.time_control.scale())
+    });
+    if g.simulation.cloth_enabled() {
+        modes.push(String::from("cloth"));
+    }
+    if g.post.options.enabled {
+        modes.push(format!("post {}", g.post.options.tone_mapping.name()));
+    }
+    let switches = [
+        (g.scene.fog, "fog"),
+        (g.field.enabled, "field"),
+        (g.paint.enabled, "paint"),
+        (g.debug.wireframe, "wireframe"),
+        (g.debug.normals, "normals"),
+        (g.debug.strain, "strain"),
+    ];
+    modes.extend(switches.iter().filter(|&&(on, _)| on).map(|&(_, name)| String::from(name)));
+
+    vec![
+        format!("{:.1} FPS, {:.2} ms", frame_rate, frame_time),
+        format!("Step {:.2} ms at {} Hz", step_time, 1.0 / g.simulation.timestep.step()),
+        format!(
+            "{} flag and {} rope vertices, {} triangles",
+            g.flag_vertex_array.len(), g.rope_vertex_array.len(), triangles
+        ),
+        format!(
+            "Eye {:.2}, {:.2}, {:.2}",
+            BASE_EYE_POSITION[0] + g.eye_offset[0], BASE_EYE_POSITION[1] + g.eye_offset[1], BASE_EYE_POSITION[2]
+        ),
+        wind,
+        modes.join(", "),
+    ]
+}
+
 fn render(g_resources: &mut GResources, window: &mut glfw::Window) {
+    let program = *current_program(g_resources);
+    let (output, (width, height)) = match g_resources.offscreen {
+        Some(ref target) => (target.framebuffer, (target.width, target.height)),
+        None => (0, window.get_framebuffer_size()),
+    };
+    g_resources.post.begin(width, height);
     unsafe {
-        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-
-        gl::UseProgram(g_resources.flag_program.program);
+        gl_check!(gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT));
+    }
 
-        gl::ActiveTexture(gl::TEXTURE0);
-        gl::Uniform1i(g_resources.flag_program.uniforms.texture, 0);
+    g_resources.environment.render_sky(&g_resources.p_matrix, &g_resources.mv_matrix);
 
-        gl::UniformMatrix4fv(
-            g_resources.flag_program.uniforms.p_matrix,
+    unsafe {
+        gl_check!(gl::UseProgram(program.program));
+
+        g_resources.environment.bind_maps(gl::TEXTURE3);
+        gl_check!(gl::Uniform1i(program.uniforms.texture, 0));
+        gl_check!(gl::Uniform1i(program.uniforms.detail_normal_map, 1));
+        gl_check!(gl::Uniform1i(program.uniforms.roughness_map, 2));
+        gl_check!(gl::Uniform1i(program.uniforms.irradiance_map, 3));
+        gl_check!(gl::Uniform1i(program.uniforms.prefiltered_map, 4));
+        gl_check!(gl::Uniform1i(program.uniforms.brdf_lut, 5));
+        gl_check!(gl::Uniform1f(program.uniforms.environment_max_lod, g_resources.environment.max_lod));
+        program.uniforms.fog.set(&g_resources.scene);
+
+        gl_check!(gl::UniformMatrix4fv(
+            program.uniforms.p_matrix,
             1, gl::FALSE,
             g_resources.p_matrix.as_ptr()
-        );
+        ));
 
-        gl::UniformMatrix4fv(
-            g_resources.flag_program.uniforms.mv_matrix,
+        gl_check!(gl::UniformMatrix4fv(
+            program.uniforms.mv_matrix,
             1, gl::FALSE,
             g_resources.mv_matrix.as_ptr()
-        );
+        ));
+
+        let attributes = program.attributes.active();
+        for &location in attributes.iter() {
+            gl_check!(gl::EnableVertexAttribArray(location));
+        }
+
+        render_mesh(g_resources, &g_resources.flag, Some(&g_resources.flag_stream));
+        g_resources.flag_stream.fence();
+        render_mesh(g_resources, &g_resources.background, None);
+        render_mesh(g_resources, &g_resources.flagpole, None);
+        render_mesh(g_resources, &g_resources.rope, None);
+        for prop in g_resources.props.iter() {
+            render_mesh(g_resources, prop, None);
+        }
+
+        for &location in attributes.iter() {
+            gl_check!(gl::DisableVertexAttribArray(location));
+        }
+    }
+
+    g_resources.field.render(
+        &g_resources.p_matrix, &g_resources.mv_matrix, g_resources.simulation.time() as GLfloat,
+        &g_resources.scene, &g_resources.environment
+    );
 
-        gl::EnableVertexAttribArray(g_resources.flag_program.attributes.position as GLuint);
-        gl::EnableVertexAttribArray(g_resources.flag_program.attributes.normal as GLuint);
-        gl::EnableVertexAttribArray(g_resources.flag_program.attributes.texcoord as GLuint);
-        gl::EnableVertexAttribArray(g_resources.flag_program.attributes.shininess as GLuint);
-        gl::EnableVertexAttribArray(g_resources.flag_program.attributes.specular as GLuint);
+    render_debug_views(g_resources);
 
-        render_mesh(g_resources, &g_resources.flag);
-        render_mesh(g_resources, &g_resources.background);
+    g_resources.post.end(output);
+    g_resources.capture.capture(width, height);
 
-        gl::DisableVertexAttribArray(g_resources.flag_program.attributes.position as GLuint);
-        gl::DisableVertexAttribArray(g_resources.flag_program.attributes.normal as GLuint);
-        gl::DisableVertexAttribArray(g_resources.flag_program.attributes.texcoord as GLuint);
-        gl::DisableVertexAttribArray(g_resources.flag_program.attributes.shininess as GLuint);
-        gl::DisableVertexAttribArray(g_resources.flag_program.attributes.specular as GLuint);
+    // Left out of screenshots and recordings.
+    if g_resources.hud.visible {
+        let lines = hud_lines(g_resources);
+        g_resources.hud.render(&lines, width, height);
     }
+
     window.swap_buffers();
 }
 
-fn handle_window_event(g_resources: &mut GResources, window: &mut glfw::Window, (time, event): (f64, glfw::WindowEvent)) {
-    println!("TIME: {:?}; EVENT: {:?}", time, event);
+fn handle_window_event(g_resources: &mut GResources, window: &mut glfw::Window, (_, event): (f64, glfw::WindowEvent)) {
     match event {
         glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
             window.set_should_close(true);
         }
-        glfw::WindowEvent::Key(key,_,_,_) => {
+        // Keys act once per press. They used to act on release and repeat
+        // too, which reloaded the programs twice for each tap of R and would
+        // flip every toggle straight back.
+        glfw::WindowEvent::Key(key, _, Action::Press, _) => {
             keyboard(g_resources, key, 0, 0);
         },
-        glfw::WindowEvent::MouseButton(button, Action::Press, _) => {
-            mouse(g_resources, button, 1, 0, 0);
+        glfw::WindowEvent::Key(key @ Key::Period, _, Action::Repeat, _)
+        | glfw::WindowEvent::Key(key @ Key::Comma, _, Action::Repeat, _) => {
+            step(g_resources, key);
+        },
+        glfw::WindowEvent::MouseButton(button, action, _) => {
+            let (x, y) = window.get_cursor_pos();
+            let state = if action == Action::Release { 1 } else { 0 };
+            mouse(g_resources, button, state, x as i32, y as i32);
+        },
+        glfw::WindowEvent::CursorPos(x, y) => {
+            motion(g_resources, x as i32, y as i32);
         },
         glfw::WindowEvent::Size(w, h) => {
             reshape(g_resources, w, h);
@@ -413,9 +1182,92 @@ fn handle_window_event(g_resources: &mut GResources, window: &mut glfw::Window,
     }
 }
 
+// Renders a fixed number of frames into an offscreen framebuffer and records
+// them, leaving the window hidden throughout.
+fn record_offscreen(
+    g_resources: &mut GResources, glfw: &mut Glfw, window: &mut glfw::Window,
+    options: &capture::RecordOptions
+) {
+    let target = match gl_util::make_framebuffer(options.width, options.height) {
+        Some(target) => target,
+        None => {
+            eprintln!("Offscreen rendering is not supported.");
+            process::exit(1);
+        }
+    };
+    unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer);
+    }
+    reshape(g_resources, options.width, options.height);
+    g_resources.offscreen = Some(target);
+
+    g_resources.capture.set_format(options.format);
+    g_resources.capture.start_recording(options.output.clone());
+    for _ in 0..options.frame_count() {
+        update(g_resources, glfw, window);
+        render(g_resources, window);
+    }
+    g_resources.capture.stop_recording();
+}
+
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "export" {
+        let options = match export::ExportOptions::parse(&args[2..]) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}\n{}", e, export::USAGE);
+                process::exit(1);
+            }
+        };
+        if let Err(e) = export::export(&options, &load_flagpole()) {
+            eprintln!("Export failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "bench" {
+        match bench::BenchOptions::parse(&args[2..]) {
+            Ok(options) => bench::run(&options),
+            Err(e) => {
+                eprintln!("{}\n{}", e, bench::USAGE);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let record_options = if args.len() > 1 && args[1] == "record" {
+        match capture::RecordOptions::parse(&args[2..]) {
+            Ok(options) => Some(options),
+            Err(e) => {
+                eprintln!("{}\n{}", e, capture::USAGE);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let simulation_options = if record_options.is_some() {
+        simulation::SimulationOptions::default()
+    } else {
+        match simulation::SimulationOptions::parse(&args[1..]) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}\n{}", e, simulation::USAGE);
+                process::exit(1);
+            }
+        }
+    };
+
     // Initialize our resources.
     let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
+    if record_options.is_some() {
+        glfw.window_hint(glfw::WindowHint::Visible(false));
+    }
+    glfw.window_hint(glfw::WindowHint::OpenGlDebugContext(cfg!(debug_assertions)));
 
     // Create a windowed mode window and its OpenGL context
     let (mut window, events) = glfw.create_window(INITIAL_WINDOW_WIDTH, INITIAL_WINDOW_HEIGHT, "Flag", glfw::WindowMode::Windowed)
@@ -425,6 +1277,7 @@ fn main() {
     window.make_current();
     window.set_key_polling(true);
     window.set_mouse_button_polling(true);
+    window.set_cursor_pos_polling(true);
     window.set_size_polling(true);
     window.set_refresh_polling(true);
 
@@ -432,9 +1285,18 @@ fn main() {
     gl::load_with(|symbol| { window.get_proc_address(symbol) as *const _ });
 
     // Initialize GL.
+    if !gl_debug::init(gl_debug::Severity::Low) && cfg!(debug_assertions) {
+        eprintln!("No GL debug output, checking for errors after GL calls instead");
+    }
     init_gl_state();
 
-    let mut g_resources = make_resources().expect("Failed to load resources.");
+    let mut g_resources = make_resources(&simulation_options).expect("Failed to load resources.");
+
+    if let Some(options) = record_options {
+        record_offscreen(&mut g_resources, &mut glfw, &mut window, &options);
+        g_resources.cleanup();
+        return;
+    }
 
     // Loop until the user closes the window
     while !window.should_close() {
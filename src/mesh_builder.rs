@@ -0,0 +1,524 @@
+use gl::types::*;
+use meshes;
+use meshes::{FlagMesh, FlagVertex};
+use vec_util;
+use std::collections::HashMap;
+use std::f32;
+
+
+// Element buffers hold GLushort indices, and 0xffff is kept clear of so it
+// never reads as a primitive restart.
+pub const MAX_VERTICES: usize = 0xffff;
+
+// The scene uses a left-handed frame, so a triangle faces outward when the
+// right-handed cross product of its edges points against its normal. All of
+// the generators below orient their triangles accordingly.
+pub struct MeshBuilder {
+    pub vertices: Vec<FlagVertex>,
+    pub elements: Vec<GLushort>,
+    shininess: GLfloat,
+    specular: [GLubyte; 4],
+}
+
+fn sub(u: &[GLfloat], v: &[GLfloat]) -> [GLfloat; 3] {
+    [u[0] - v[0], u[1] - v[1], u[2] - v[2]]
+}
+
+fn dot(u: &[GLfloat], v: &[GLfloat]) -> GLfloat {
+    u[0]*v[0] + u[1]*v[1] + u[2]*v[2]
+}
+
+impl MeshBuilder {
+    pub fn new() -> MeshBuilder {
+        MeshBuilder {
+            vertices: vec![],
+            elements: vec![],
+            shininess: 0.0,
+            specular: [0; 4],
+        }
+    }
+
+    // Applies to every vertex added after the call.
+    pub fn material(&mut self, shininess: GLfloat, specular: [GLubyte; 4]) {
+        self.shininess = shininess;
+        self.specular = specular;
+    }
+
+    pub fn vertex(
+        &mut self, position: [GLfloat; 3], normal: [GLfloat; 3], texcoord: [GLfloat; 2]
+    ) -> GLushort {
+        let mut v = FlagVertex::zero();
+        v.position  = [position[0], position[1], position[2], 1.0];
+        v.normal    = [normal[0], normal[1], normal[2], 0.0];
+        v.texcoord  = texcoord;
+        v.shininess = self.shininess;
+        v.specular  = self.specular;
+        assert!(self.vertices.len() < MAX_VERTICES, "mesh has more vertices than GLushort indices can reach");
+        self.vertices.push(v);
+
+        (self.vertices.len() - 1) as GLushort
+    }
+
+    // Whether `count` more vertices fit, for callers that split large meshes
+    // into several.
+    pub fn has_room(&self, count: usize) -> bool {
+        self.vertices.len() + count <= MAX_VERTICES
+    }
+
+    pub fn triangle(&mut self, a: GLushort, b: GLushort, c: GLushort) {
+        self.elements.push(a);
+        self.elements.push(b);
+        self.elements.push(c);
+    }
+
+    fn face_cross(&self, a: GLushort, b: GLushort, c: GLushort) -> [GLfloat; 3] {
+        let pa = self.vertices[a as usize].position;
+        let pb = self.vertices[b as usize].position;
+        let pc = self.vertices[c as usize].position;
+        let mut cross = [0.0; 3];
+        vec_util::vec_cross(&mut cross, &sub(&pb, &pa), &sub(&pc, &pa));
+
+        cross
+    }
+
+    // Adds a triangle wound to face the same way as the normal of its first
+    // vertex, dropping it if it has no area.
+    fn facing_triangle(&mut self, a: GLushort, b: GLushort, c: GLushort) {
+        let cross = self.face_cross(a, b, c);
+        let d = dot(&cross, &self.vertices[a as usize].normal);
+        if d < 0.0 {
+            self.triangle(a, b, c);
+        } else if d > 0.0 {
+            self.triangle(a, c, b);
+        }
+    }
+
+    // Samples a surface over the unit square, with `f` returning the
+    // position, normal and texture coordinate at each (u, v).
+    pub fn parametric<F>(&mut self, u_segments: usize, v_segments: usize, f: F)
+        where F: Fn(GLfloat, GLfloat) -> ([GLfloat; 3], [GLfloat; 3], [GLfloat; 2])
+    {
+        let base = self.vertices.len();
+        for j in 0..(v_segments + 1) {
+            for i in 0..(u_segments + 1) {
+                let u = (i as GLfloat) / (u_segments as GLfloat);
+                let v = (j as GLfloat) / (v_segments as GLfloat);
+                let (position, normal, texcoord) = f(u, v);
+                self.vertex(position, normal, texcoord);
+            }
+        }
+
+        let row = u_segments + 1;
+        for j in 0..v_segments {
+            for i in 0..u_segments {
+                let a = (base + j * row + i) as GLushort;
+                let b = a + 1;
+                let c = a + 1 + row as GLushort;
+                let d = a + row as GLushort;
+                self.facing_triangle(a, b, c);
+                self.facing_triangle(a, c, d);
+            }
+        }
+    }
+
+    pub fn plane(
+        &mut self, center: [GLfloat; 3], u_axis: [GLfloat; 3], v_axis: [GLfloat; 3],
+        subdivisions: usize
+    ) {
+        let mut normal = [0.0; 3];
+        vec_util::vec_cross(&mut normal, &v_axis, &u_axis);
+        vec_util::vec_normalize(&mut normal);
+
+        self.parametric(subdivisions, subdivisions, |u, v| {
+            let a = 2.0 * u - 1.0;
+            let b = 2.0 * v - 1.0;
+            let position = [
+                center[0] + a * u_axis[0] + b * v_axis[0],
+                center[1] + a * u_axis[1] + b * v_axis[1],
+                center[2] + a * u_axis[2] + b * v_axis[2],
+            ];
+            (position, normal, [u, v])
+        });
+    }
+
+    pub fn cuboid(&mut self, center: [GLfloat; 3], half_extents: [GLfloat; 3]) {
+        let (hx, hy, hz) = (half_extents[0], half_extents[1], half_extents[2]);
+        let faces: [([GLfloat; 3], [GLfloat; 3], [GLfloat; 3]); 6] = [
+            ([ hx, 0.0, 0.0], [0.0, 0.0,  hz], [0.0,  hy, 0.0]),
+            ([-hx, 0.0, 0.0], [0.0, 0.0, -hz], [0.0,  hy, 0.0]),
+            ([0.0,  hy, 0.0], [ hx, 0.0, 0.0], [0.0, 0.0,  hz]),
+            ([0.0, -hy, 0.0], [ hx, 0.0, 0.0], [0.0, 0.0, -hz]),
+            ([0.0, 0.0,  hz], [-hx, 0.0, 0.0], [0.0,  hy, 0.0]),
+            ([0.0, 0.0, -hz], [ hx, 0.0, 0.0], [0.0,  hy, 0.0]),
+        ];
+
+        for &(offset, u_axis, v_axis) in faces.iter() {
+            let face_center = [
+                center[0] + offset[0], center[1] + offset[1], center[2] + offset[2]
+            ];
+            self.plane(face_center, u_axis, v_axis, 1);
+        }
+    }
+
+    pub fn cylinder(
+        &mut self, base: [GLfloat; 3], radius: GLfloat, height: GLfloat, segments: usize
+    ) {
+        let two_pi = 2.0 * vec_util::M_PI;
+        self.parametric(segments, 1, |u, v| {
+            let (sn, cs) = f32::sin_cos(two_pi * u);
+            let position = [base[0] + radius * cs, base[1] + height * v, base[2] + radius * sn];
+            (position, [cs, 0.0, sn], [u, v])
+        });
+
+        for &(y, ny) in [(0.0, -1.0), (height, 1.0)].iter() {
+            self.parametric(segments, 1, |u, v| {
+                let (sn, cs) = f32::sin_cos(two_pi * u);
+                let r = radius * v;
+                let position = [base[0] + r * cs, base[1] + y, base[2] + r * sn];
+                (position, [0.0, ny, 0.0], [0.5 + 0.5 * v * cs, 0.5 + 0.5 * v * sn])
+            });
+        }
+    }
+
+    pub fn sphere(
+        &mut self, center: [GLfloat; 3], radius: GLfloat, segments: usize, rings: usize
+    ) {
+        let two_pi = 2.0 * vec_util::M_PI;
+        self.parametric(segments, rings, |u, v| {
+            let (sn, cs) = f32::sin_cos(two_pi * u);
+            let (sn_phi, cs_phi) = f32::sin_cos(vec_util::M_PI * v);
+            let normal = [sn_phi * cs, -cs_phi, sn_phi * sn];
+            let position = [
+                center[0] + radius * normal[0],
+                center[1] + radius * normal[1],
+                center[2] + radius * normal[2],
+            ];
+            (position, normal, [u, v])
+        });
+    }
+
+    pub fn torus(
+        &mut self, center: [GLfloat; 3], major_radius: GLfloat, minor_radius: GLfloat,
+        major_segments: usize, minor_segments: usize
+    ) {
+        let two_pi = 2.0 * vec_util::M_PI;
+        self.parametric(major_segments, minor_segments, |u, v| {
+            let (sn, cs) = f32::sin_cos(two_pi * u);
+            let (sn_phi, cs_phi) = f32::sin_cos(two_pi * v);
+            let normal = [cs_phi * cs, sn_phi, cs_phi * sn];
+            let r = major_radius + minor_radius * cs_phi;
+            let position = [
+                center[0] + r * cs, center[1] + minor_radius * sn_phi, center[2] + r * sn
+            ];
+            (position, normal, [u, v])
+        });
+    }
+
+    // Sets each vertex that has no normal, left as zero, to the area-weighted
+    // average of the normals of the faces around it. Normals already set are
+    // kept, so a mesh built without any gets smooth normals throughout.
+    pub fn compute_missing_normals(&mut self) {
+        let mut normals = vec![[0.0 as GLfloat; 3]; self.vertices.len()];
+        for i in 0..(self.elements.len() / 3) {
+            let (a, b, c) = (self.elements[3*i], self.elements[3*i + 1], self.elements[3*i + 2]);
+            let cross = self.face_cross(a, b, c);
+            for &k in [a, b, c].iter() {
+                let n = &mut normals[k as usize];
+                n[0] -= cross[0];
+                n[1] -= cross[1];
+                n[2] -= cross[2];
+            }
+        }
+
+        for (v, n) in self.vertices.iter_mut().zip(normals.iter_mut()) {
+            if v.normal[0..3] != [0.0, 0.0, 0.0] {
+                continue;
+            }
+            if vec_util::vec_length(n) > 0.0 {
+                vec_util::vec_normalize(n);
+            }
+            v.normal = [n[0], n[1], n[2], 0.0];
+        }
+    }
+
+    // Gives every triangle its own vertices, carrying its face normal. Fails,
+    // leaving the mesh as it was, if that would take more vertices than
+    // GLushort indices can reach.
+    pub fn compute_flat_normals(&mut self) -> Result<(), String> {
+        if self.elements.len() > MAX_VERTICES {
+            return Err(format!(
+                "{} triangles is too many to give each its own vertices", self.elements.len() / 3
+            ));
+        }
+
+        let mut vertices = Vec::with_capacity(self.elements.len());
+        for i in 0..(self.elements.len() / 3) {
+            let (a, b, c) = (self.elements[3*i], self.elements[3*i + 1], self.elements[3*i + 2]);
+            let mut n = self.face_cross(a, b, c);
+            if vec_util::vec_length(&n) > 0.0 {
+                vec_util::vec_normalize(&mut n);
+            }
+
+            for &k in [a, b, c].iter() {
+                let mut v = self.vertices[k as usize];
+                v.normal = [-n[0], -n[1], -n[2], 0.0];
+                vertices.push(v);
+            }
+        }
+
+        self.elements = (0..vertices.len()).map(|i| i as GLushort).collect();
+        self.vertices = vertices;
+
+        Ok(())
+    }
+
+    pub fn compute_tangents(&mut self) {
+        compute_tangents(&mut self.vertices, &self.elements);
+    }
+
+    // Merges vertices whose attributes all agree to within `epsilon`.
+    pub fn weld(&mut self, epsilon: GLfloat) {
+        let quantize = |x: GLfloat| (x / epsilon).round() as i64;
+        let mut seen: HashMap<Vec<i64>, GLushort> = HashMap::new();
+        let mut remap = Vec::with_capacity(self.vertices.len());
+        let mut vertices: Vec<FlagVertex> = vec![];
+
+        for v in self.vertices.iter() {
+            let mut key: Vec<i64> = v.position[0..3].iter()
+                .chain(v.normal[0..3].iter())
+                .chain(v.texcoord.iter())
+                .map(|&x| quantize(x))
+                .collect();
+            key.push(quantize(v.shininess));
+            key.extend(v.specular.iter().map(|&x| x as i64));
+
+            let index = *seen.entry(key).or_insert_with(|| {
+                vertices.push(*v);
+                (vertices.len() - 1) as GLushort
+            });
+            remap.push(index);
+        }
+
+        for e in self.elements.iter_mut() {
+            *e = remap[*e as usize];
+        }
+        self.vertices = vertices;
+    }
+
+    // Adds the triangles of `other` to this mesh.
+    pub fn append(&mut self, other: &MeshBuilder) {
+        assert!(self.has_room(other.vertices.len()), "mesh has more vertices than GLushort indices can reach");
+        let base = self.vertices.len() as GLushort;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.elements.extend(other.elements.iter().map(|&e| base + e));
+    }
+
+    pub fn build(&self, out_mesh: &mut FlagMesh, hint: GLenum) {
+        meshes::init_mesh(
+            out_mesh,
+            &self.vertices, self.vertices.len() as GLsizei,
+            &self.elements, self.elements.len() as GLsizei,
+            hint
+        );
+    }
+}
+
+// Sets each vertex's tangent from the texture coordinates of the triangles
+// around it, made perpendicular to its normal, which has to be set already.
+// Vertices with no usable triangles get an arbitrary one.
+pub fn compute_tangents(vertices: &mut [FlagVertex], elements: &[GLushort]) {
+    let mut tan_s = vec![[0.0 as GLfloat; 3]; vertices.len()];
+    let mut tan_t = vec![[0.0 as GLfloat; 3]; vertices.len()];
+
+    for triangle in elements.chunks(3) {
+        let k = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let (va, vb, vc) = (&vertices[k[0]], &vertices[k[1]], &vertices[k[2]]);
+        let e1 = sub(&vb.position, &va.position);
+        let e2 = sub(&vc.position, &va.position);
+        let ds1 = vb.texcoord[0] - va.texcoord[0];
+        let dt1 = vb.texcoord[1] - va.texcoord[1];
+        let ds2 = vc.texcoord[0] - va.texcoord[0];
+        let dt2 = vc.texcoord[1] - va.texcoord[1];
+        let det = ds1 * dt2 - ds2 * dt1;
+        if det == 0.0 {
+            continue;
+        }
+
+        let r = 1.0 / det;
+        for &j in k.iter() {
+            for axis in 0..3 {
+                tan_s[j][axis] += (e1[axis] * dt2 - e2[axis] * dt1) * r;
+                tan_t[j][axis] += (e2[axis] * ds1 - e1[axis] * ds2) * r;
+            }
+        }
+    }
+
+    for (i, v) in vertices.iter_mut().enumerate() {
+        let n = v.normal;
+        let s = tan_s[i];
+        // Gram-Schmidt against the normal.
+        let d = dot(&n, &s);
+        let mut t = [s[0] - n[0] * d, s[1] - n[1] * d, s[2] - n[2] * d];
+        if vec_util::vec_length(&t) == 0.0 {
+            v.tangent = [1.0, 0.0, 0.0, 1.0];
+            continue;
+        }
+        vec_util::vec_normalize(&mut t);
+
+        let mut b = [0.0; 3];
+        vec_util::vec_cross(&mut b, &n, &t);
+        let w = if dot(&b, &tan_t[i]) < 0.0 { -1.0 } else { 1.0 };
+
+        v.tangent = [t[0], t[1], t[2], w];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gl::types::*;
+    use super::{MeshBuilder, MAX_VERTICES, dot, sub};
+    use vec_util;
+
+    // Checks that every triangle with any area faces away from the point
+    // `inside(centroid)` returns for it.
+    fn assert_outward<F>(builder: &MeshBuilder, inside: F)
+        where F: Fn([GLfloat; 3]) -> [GLfloat; 3]
+    {
+        for triangle in builder.elements.chunks(3) {
+            let cross = builder.face_cross(triangle[0], triangle[1], triangle[2]);
+            if vec_util::vec_length(&cross) < 1e-6 {
+                continue;
+            }
+
+            let mut centroid = [0.0; 3];
+            for &k in triangle.iter() {
+                let p = builder.vertices[k as usize].position;
+                for axis in 0..3 {
+                    centroid[axis] += p[axis] / 3.0;
+                }
+            }
+            let outward = sub(&centroid, &inside(centroid));
+            assert!(dot(&cross, &outward) < 0.0, "triangle {:?} faces inward", triangle);
+        }
+    }
+
+    #[test]
+    fn plane() {
+        let mut builder = MeshBuilder::new();
+        builder.plane([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], 3);
+        assert_eq!(builder.vertices.len(), 16);
+        assert_eq!(builder.elements.len(), 54);
+        // v_axis × u_axis is up, so that's the way the plane faces.
+        assert_outward(&builder, |p| [p[0], p[1] - 1.0, p[2]]);
+    }
+
+    #[test]
+    fn cuboid() {
+        let mut builder = MeshBuilder::new();
+        builder.cuboid([1.0, 2.0, 3.0], [0.5, 1.0, 2.0]);
+        assert_eq!(builder.vertices.len(), 24);
+        assert_eq!(builder.elements.len(), 36);
+        assert_outward(&builder, |_| [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn cylinder() {
+        let mut builder = MeshBuilder::new();
+        builder.cylinder([0.0, -1.0, 0.0], 0.5, 2.0, 12);
+        assert_eq!(builder.vertices.len(), 3 * 2 * 13);
+        // The caps lose the triangles that collapse onto their centres.
+        assert_eq!(builder.elements.len(), 3 * (2 * 12 + 2 * 12));
+        assert_outward(&builder, |_| [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn sphere() {
+        let mut builder = MeshBuilder::new();
+        builder.sphere([0.0, 1.0, 0.0], 2.0, 8, 4);
+        assert_eq!(builder.vertices.len(), 9 * 5);
+        assert!(builder.elements.len() <= 3 * 2 * 8 * 4);
+        assert_outward(&builder, |_| [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn torus() {
+        let mut builder = MeshBuilder::new();
+        builder.torus([0.0, 0.0, 0.0], 1.0, 0.25, 16, 8);
+        assert_eq!(builder.vertices.len(), 17 * 9);
+        assert_eq!(builder.elements.len(), 3 * 2 * 16 * 8);
+        // Inside is the nearest point on the tube's centre line.
+        assert_outward(&builder, |p| {
+            let mut ring = [p[0], 0.0, p[2]];
+            vec_util::vec_normalize(&mut ring);
+            ring
+        });
+    }
+
+    #[test]
+    fn flat_normals_and_weld() {
+        let mut builder = MeshBuilder::new();
+        builder.cuboid([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+        builder.compute_flat_normals().unwrap();
+        assert_eq!(builder.vertices.len(), 36);
+        assert_outward(&builder, |_| [0.0, 0.0, 0.0]);
+
+        // The two triangles of each face share an edge's worth of vertices.
+        builder.weld(1e-4);
+        assert_eq!(builder.vertices.len(), 24);
+        assert_eq!(builder.elements.len(), 36);
+        assert_outward(&builder, |_| [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn too_many_triangles_for_flat_normals() {
+        let mut builder = MeshBuilder::new();
+        builder.plane([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], 1);
+        for _ in 0..(MAX_VERTICES / 3 + 1) {
+            builder.triangle(0, 1, 2);
+        }
+        let elements = builder.elements.len();
+        assert!(builder.compute_flat_normals().is_err());
+        assert_eq!(builder.vertices.len(), 4);
+        assert_eq!(builder.elements.len(), elements);
+    }
+
+    #[test]
+    fn append() {
+        let mut builder = MeshBuilder::new();
+        builder.cuboid([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+        let mut other = MeshBuilder::new();
+        other.cuboid([3.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+        builder.append(&other);
+        assert_eq!(builder.vertices.len(), 48);
+        assert_eq!(builder.elements.len(), 72);
+        assert_outward(&builder, |p| [if p[0] > 1.5 { 3.0 } else { 0.0 }, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn missing_normals() {
+        let mut builder = MeshBuilder::new();
+        builder.cuboid([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+        let given = builder.vertices[1].normal;
+        builder.vertices[0].normal = [0.0; 4];
+        builder.compute_missing_normals();
+        assert_eq!(builder.vertices[0].normal, given);
+        assert_eq!(builder.vertices[1].normal, given);
+    }
+
+    #[test]
+    fn room() {
+        let mut builder = MeshBuilder::new();
+        builder.plane([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], 1);
+        assert!(builder.has_room(MAX_VERTICES - 4));
+        assert!(!builder.has_room(MAX_VERTICES - 3));
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_many_vertices() {
+        let mut builder = MeshBuilder::new();
+        for _ in 0..(MAX_VERTICES + 1) {
+            builder.vertex([0.0; 3], [0.0, 1.0, 0.0], [0.0; 2]);
+        }
+    }
+}
@@ -2,13 +2,20 @@ use gl;
 use gl::types::*;
 use std::mem;
 use vec_util;
+use flagpole::{FlagpoleBuilder, FlagpoleProfile};
+use mesh_builder::MeshBuilder;
+use collision::Collider;
+use atlas::Region;
 use std::f32;
 
-const FLAG_X_RES: GLushort = 100;
-const FLAG_Y_RES: GLushort = 75;
+pub const FLAG_X_RES: GLushort = 100;
+pub const FLAG_Y_RES: GLushort = 75;
 const FLAG_VERTEX_COUNT: GLushort = FLAG_X_RES * FLAG_Y_RES;
 const FLAG_S_STEP: GLfloat = 1.0 / ((FLAG_X_RES - 1) as GLfloat);
 const FLAG_T_STEP: GLfloat = 1.0 / ((FLAG_Y_RES - 1) as GLfloat);
+// A faint sheen for the weave's roughness map to break up.
+const FLAG_SHININESS: GLfloat = 8.0;
+const FLAG_SPECULAR: [GLubyte; 4] = [48, 48, 48, 0];
 
 
 pub struct FlagMesh {
@@ -16,22 +23,109 @@ pub struct FlagMesh {
     pub element_buffer: GLuint,
     pub element_count: GLsizei,
     pub texture: GLuint,
+    pub detail: DetailMaps,
+    pub material: Material,
+}
+
+// Tangent-space normal and roughness maps tiled `scale` times across the
+// mesh's texture coordinates in s and t. A scale of zero leaves them off.
+#[derive(Copy, Clone, Debug)]
+pub struct DetailMaps {
+    pub normal_map: GLuint,
+    pub roughness_map: GLuint,
+    pub scale: [GLfloat; 2],
+}
+
+impl DetailMaps {
+    pub fn none() -> DetailMaps {
+        DetailMaps {
+            normal_map: 0,
+            roughness_map: 0,
+            scale: [0.0; 2],
+        }
+    }
+}
+
+// The specular lobes of the physically based shader, numbered as it takes
+// them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Lobe {
+    // GGX over Lambert diffuse.
+    Standard = 0,
+    // A sheen lobe in place of GGX, for fabric.
+    Cloth = 1,
+    // GGX stretched along the tangent or bitangent, for brushed or turned
+    // metal.
+    AnisotropicMetal = 2,
+}
+
+// How the physically based shader lights a mesh. The Phong shader goes by
+// the per-vertex shininess and specular instead.
+#[derive(Copy, Clone, Debug)]
+pub struct Material {
+    pub lobe: Lobe,
+    pub metallic: GLfloat,
+    // The roughness map stands in for this while detail maps are on.
+    pub roughness: GLfloat,
+    // The colour of the cloth lobe's sheen.
+    pub sheen: [GLfloat; 3],
+    // From -1 to 1, with positive values stretching highlights along the
+    // tangent and negative ones along the bitangent.
+    pub anisotropy: GLfloat,
+}
+
+impl Material {
+    pub fn standard(roughness: GLfloat, metallic: GLfloat) -> Material {
+        Material {
+            lobe: Lobe::Standard,
+            metallic: metallic,
+            roughness: roughness,
+            sheen: [0.0; 3],
+            anisotropy: 0.0,
+        }
+    }
+
+    pub fn cloth(roughness: GLfloat, sheen: [GLfloat; 3]) -> Material {
+        Material {
+            lobe: Lobe::Cloth,
+            sheen: sheen,
+            ..Material::standard(roughness, 0.0)
+        }
+    }
+
+    pub fn anisotropic_metal(roughness: GLfloat, anisotropy: GLfloat) -> Material {
+        Material {
+            lobe: Lobe::AnisotropicMetal,
+            anisotropy: anisotropy,
+            ..Material::standard(roughness, 1.0)
+        }
+    }
+
+    // A dielectric about as glossy as a Phong exponent, by the usual
+    // correspondence between the exponent and GGX roughness.
+    pub fn from_shininess(shininess: GLfloat) -> Material {
+        Material::standard(f32::sqrt(2.0 / (shininess + 2.0)), 0.0)
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct FlagVertex {
     pub position: [GLfloat; 4],
     pub normal: [GLfloat; 4],
+    // Points along increasing s, with the sign of the bitangent, which
+    // points along increasing t, in w: bitangent = w * cross(normal, tangent).
+    pub tangent: [GLfloat; 4],
     pub texcoord: [GLfloat; 2],
     pub shininess: GLfloat,
     pub specular: [GLubyte; 4],
 }
 
 impl FlagVertex {
-    fn zero() -> FlagVertex {
+    pub fn zero() -> FlagVertex {
         FlagVertex {
             position: [0.0; 4],
             normal: [0.0; 4],
+            tangent: [0.0; 4],
             texcoord: [0.0; 2],
             shininess: 0.0,
             specular: [0; 4],
@@ -46,25 +140,27 @@ pub fn init_mesh(
     hint: GLenum
 ) {
     unsafe {
-        gl::GenBuffers(1, &mut out_mesh.vertex_buffer);
-        gl::GenBuffers(1, &mut out_mesh.element_buffer);
+        gl_check!(gl::GenBuffers(1, &mut out_mesh.vertex_buffer));
+        gl_check!(gl::GenBuffers(1, &mut out_mesh.element_buffer));
         out_mesh.element_count = element_count;
 
-        gl::BindBuffer(gl::ARRAY_BUFFER, out_mesh.vertex_buffer);
-        gl::BufferData(
+        gl_check!(gl::BindBuffer(gl::ARRAY_BUFFER, out_mesh.vertex_buffer));
+        gl_check!(gl::BufferData(
             gl::ARRAY_BUFFER,
             (vertex_count * (mem::size_of::<FlagVertex>() as GLsizei)) as  GLsizeiptr,
             mem::transmute(&vertex_data[0]),
             hint
-        );
+        ));
 
-        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, out_mesh.element_buffer);
-        gl::BufferData(
+        // Meshes that update their vertices may rebuild their elements too.
+        let element_hint = if hint == gl::STATIC_DRAW { gl::STATIC_DRAW } else { gl::DYNAMIC_DRAW };
+        gl_check!(gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, out_mesh.element_buffer));
+        gl_check!(gl::BufferData(
             gl::ELEMENT_ARRAY_BUFFER,
             (element_count * mem::size_of::<GLushort>() as GLsizei) as  GLsizeiptr,
             mem::transmute(&element_data[0]),
-            gl::STATIC_DRAW
-        );
+            element_hint
+        ));
     }
 }
 
@@ -94,9 +190,30 @@ pub fn calculate_flag_vertex(
     vec_util::vec_cross(&mut v.normal, &tgrad, &sgrad);
     vec_util::vec_normalize(&mut v.normal);
     v.normal[3] = 0.0;
+
+    // sgrad is already perpendicular to the normal. Since the normal is
+    // cross(tgrad, sgrad), cross(normal, sgrad) always points against
+    // tgrad.
+    v.tangent = [sgrad[0], sgrad[1], sgrad[2], 0.0];
+    vec_util::vec_normalize(&mut v.tangent);
+    v.tangent[3] = -1.0;
+}
+
+// The flag laid out flat, as calculate_flag_vertex has it with no wave.
+pub fn flag_rest_positions() -> Vec<[GLfloat; 3]> {
+    let mut positions = Vec::with_capacity(FLAG_VERTEX_COUNT as usize);
+    for t in 0..FLAG_Y_RES {
+        for s in 0..FLAG_X_RES {
+            let ss: GLfloat = FLAG_S_STEP * (s as GLfloat);
+            let tt: GLfloat = FLAG_T_STEP * (t as GLfloat);
+            positions.push([ss, 0.75 * tt - 0.375, 0.0]);
+        }
+    }
+
+    positions
 }
 
-pub fn init_flag_mesh(out_mesh: &mut FlagMesh) -> Vec<FlagVertex> {
+pub fn build_flag_mesh() -> (Vec<FlagVertex>, Vec<GLushort>) {
     let mut vertex_data = vec![FlagVertex::zero(); FLAG_VERTEX_COUNT as usize];
     let element_count = 6 * (FLAG_X_RES - 1) * (FLAG_Y_RES - 1);
     let mut element_data = vec![0 as GLushort; element_count as usize];
@@ -111,11 +228,8 @@ pub fn init_flag_mesh(out_mesh: &mut FlagMesh) -> Vec<FlagVertex> {
 
             vertex_data[i].texcoord[0] = ss;
             vertex_data[i].texcoord[1] = tt;
-            vertex_data[i].shininess   = 0.0;
-            vertex_data[i].specular[0] = 0;
-            vertex_data[i].specular[1] = 0;
-            vertex_data[i].specular[2] = 0;
-            vertex_data[i].specular[3] = 0;
+            vertex_data[i].shininess   = FLAG_SHININESS;
+            vertex_data[i].specular    = FLAG_SPECULAR;
 
             i += 1;
         }
@@ -144,555 +258,366 @@ pub fn init_flag_mesh(out_mesh: &mut FlagMesh) -> Vec<FlagVertex> {
         index += 1;
     }
 
+    (vertex_data, element_data)
+}
+
+pub fn init_flag_mesh(out_mesh: &mut FlagMesh) -> (Vec<FlagVertex>, Vec<GLushort>) {
+    let (vertex_data, element_data) = build_flag_mesh();
+
     init_mesh(
         out_mesh,
-        &vertex_data, FLAG_VERTEX_COUNT as GLsizei,
-        &element_data, element_count as GLsizei,
-        gl::STREAM_DRAW
+        &vertex_data, vertex_data.len() as GLsizei,
+        &element_data, element_data.len() as GLsizei,
+        gl::DYNAMIC_DRAW
     );
 
-    vertex_data
+    (vertex_data, element_data)
 }
 
-const FLAGPOLE_TRUCK_TOP: GLfloat           = 0.5;
-const FLAGPOLE_TRUCK_CROWN: GLfloat         = 0.41;
-const FLAGPOLE_TRUCK_BOTTOM: GLfloat        = 0.38;
-const FLAGPOLE_SHAFT_TOP: GLfloat           = 0.3775;
-const FLAGPOLE_SHAFT_BOTTOM: GLfloat        = -1.0;
-const FLAGPOLE_TRUCK_TOP_RADIUS: GLfloat    = 0.005;
-const FLAGPOLE_TRUCK_CROWN_RADIUS: GLfloat  = 0.020;
-const FLAGPOLE_TRUCK_BOTTOM_RADIUS: GLfloat = 0.015;
-const FLAGPOLE_SHAFT_RADIUS: GLfloat        = 0.010;
 const FLAGPOLE_SHININESS: GLfloat           = 4.0;
-
-pub fn init_background_mesh(out_mesh: &mut FlagMesh) {
-    const FLAGPOLE_RES: GLsizei = 16;
-    const FLAGPOLE_SLICE: GLsizei = 6;
-
-    let FLAGPOLE_AXIS_XZ: [GLfloat; 2] = [-FLAGPOLE_SHAFT_RADIUS, 0.0];
-    
-    const FLAGPOLE_SPECULAR: [GLubyte; 4] = [255, 255, 192, 0];
-
-    let GROUND_LO: [GLfloat; 3] = [-0.875, FLAGPOLE_SHAFT_BOTTOM, -2.45];
-    let GROUND_HI: [GLfloat; 3] = [1.875, FLAGPOLE_SHAFT_BOTTOM,  0.20];
-    let WALL_LO: [GLfloat; 3] = [GROUND_LO[0], FLAGPOLE_SHAFT_BOTTOM, GROUND_HI[2]];
-    let WALL_HI: [GLfloat; 3] = [GROUND_HI[0], FLAGPOLE_SHAFT_BOTTOM + 3.0, GROUND_HI[2]];
-
-    static TEX_FLAGPOLE_LO: [GLfloat; 2] = [ 0.0,    0.0 ];
-    static TEX_FLAGPOLE_HI: [GLfloat; 2] = [ 0.03125,  1.0 ];
-    static TEX_GROUND_LO: [GLfloat; 2]   = [ 0.03125,  0.0078125 ];
-    static TEX_GROUND_HI: [GLfloat; 2]   = [ 0.515625, 0.9921875 ];
-    static TEX_WALL_LO: [GLfloat; 2]     = [ 0.515625, 0.0078125 ];
-    static TEX_WALL_HI: [GLfloat; 2]     = [ 1.0,      0.9921875 ];
-
-    macro_rules! __flagpole_t {
-        ($x:ident) => {
-            TEX_FLAGPOLE_LO[1] 
-                + (TEX_FLAGPOLE_HI[1] - TEX_FLAGPOLE_LO[1])
-                * ($x - FLAGPOLE_TRUCK_TOP) / (FLAGPOLE_SHAFT_BOTTOM - FLAGPOLE_TRUCK_TOP)
-        };
+const FLAGPOLE_CLEAT: GLfloat               = -0.9;
+
+const CLEAT_STANDOFF: GLfloat     = 0.006;
+const CLEAT_HORN_RADIUS: GLfloat  = 0.002;
+const CLEAT_HORN_LENGTH: GLfloat  = 0.04;
+const CLEAT_ARM_SPACING: GLfloat  = 0.012;
+const CLEAT_SIDES: usize          = 6;
+
+const FLAG_HOIST_TOP: GLfloat    = 0.375;
+const FLAG_HOIST_BOTTOM: GLfloat = -0.375;
+const FLAG_CLEAT_CLEARANCE: GLfloat = 0.05;
+const FLAG_HOIST_TRAVEL: GLfloat
+    = FLAG_HOIST_BOTTOM - (FLAGPOLE_CLEAT + FLAG_CLEAT_CLEARANCE);
+
+const GROUND_HEIGHT: GLfloat = -1.0;
+const GROUND_LO: [GLfloat; 3] = [-0.875, GROUND_HEIGHT, -2.45];
+const GROUND_HI: [GLfloat; 3] = [1.875, GROUND_HEIGHT,  0.20];
+const WALL_LO: [GLfloat; 3] = [GROUND_LO[0], GROUND_HEIGHT, GROUND_HI[2]];
+const WALL_HI: [GLfloat; 3] = [GROUND_HI[0], GROUND_HEIGHT + 3.0, GROUND_HI[2]];
+
+const FLAGPOLE_SPECULAR: [GLubyte; 4] = [255, 255, 192, 0];
+
+const TEX_FLAGPOLE_LO: [GLfloat; 2] = [ 0.0,    0.0 ];
+const TEX_FLAGPOLE_HI: [GLfloat; 2] = [ 0.03125,  1.0 ];
+const TEX_GROUND_LO: [GLfloat; 2]   = [ 0.03125,  0.0078125 ];
+const TEX_GROUND_HI: [GLfloat; 2]   = [ 0.515625, 0.9921875 ];
+const TEX_WALL_LO: [GLfloat; 2]     = [ 0.515625, 0.0078125 ];
+const TEX_WALL_HI: [GLfloat; 2]     = [ 1.0,      0.9921875 ];
+
+// Maps texture coordinates over the unit square into [lo, hi].
+fn remap_texcoords(vertices: &mut [FlagVertex], lo: [GLfloat; 2], hi: [GLfloat; 2]) {
+    for v in vertices.iter_mut() {
+        v.texcoord[0] = lo[0] + (hi[0] - lo[0]) * v.texcoord[0];
+        v.texcoord[1] = lo[1] + (hi[1] - lo[1]) * v.texcoord[1];
     }
+}
 
-    
-    let theta_step: GLfloat = 2.0 * vec_util::M_PI / (FLAGPOLE_RES as GLfloat);
-    let s_step: GLfloat = (TEX_FLAGPOLE_HI[0] - TEX_FLAGPOLE_LO[0]) / (FLAGPOLE_RES as GLfloat);
-    let t_truck_top: GLfloat    = TEX_FLAGPOLE_LO[1];
-    let t_truck_crown: GLfloat  = __flagpole_t!(FLAGPOLE_TRUCK_CROWN);
-    let t_truck_bottom: GLfloat = __flagpole_t!(FLAGPOLE_TRUCK_BOTTOM);
-    let t_shaft_top: GLfloat    = __flagpole_t!(FLAGPOLE_SHAFT_TOP);
-    let t_shaft_bottom: GLfloat = __flagpole_t!(FLAGPOLE_SHAFT_BOTTOM);
+fn add_ground_and_wall(builder: &mut MeshBuilder) {
+    let half = |lo: GLfloat, hi: GLfloat| 0.5 * (hi - lo);
 
-    let flagpole_vertex_count: GLsizei = 2 + FLAGPOLE_RES * FLAGPOLE_SLICE;
-    let wall_vertex_count: GLsizei = 4;
-    let ground_vertex_count: GLsizei = 4;
-    let vertex_count: GLsizei = flagpole_vertex_count + wall_vertex_count + ground_vertex_count;
+    let start = builder.vertices.len();
+    builder.plane(
+        [0.5 * (GROUND_LO[0] + GROUND_HI[0]), GROUND_LO[1], 0.5 * (GROUND_LO[2] + GROUND_HI[2])],
+        [half(GROUND_LO[0], GROUND_HI[0]), 0.0, 0.0],
+        [0.0, 0.0, half(GROUND_LO[2], GROUND_HI[2])],
+        1
+    );
+    remap_texcoords(&mut builder.vertices[start..], TEX_GROUND_LO, TEX_GROUND_HI);
+
+    let start = builder.vertices.len();
+    builder.plane(
+        [0.5 * (WALL_LO[0] + WALL_HI[0]), 0.5 * (WALL_LO[1] + WALL_HI[1]), WALL_LO[2]],
+        [half(WALL_LO[0], WALL_HI[0]), 0.0, 0.0],
+        [0.0, half(WALL_LO[1], WALL_HI[1]), 0.0],
+        1
+    );
+    remap_texcoords(&mut builder.vertices[start..], TEX_WALL_LO, TEX_WALL_HI);
+}
 
-    let mut element_i = 0;
+// Where the halyard's fall is made fast, standing out from the shaft on the
+// side away from the flag.
+fn cleat_xz(flagpole: &FlagpoleProfile) -> [GLfloat; 2] {
+    let axis_xz = flagpole.axis_xz();
+    [axis_xz[0], axis_xz[1] + flagpole.shaft_radius + CLEAT_STANDOFF]
+}
 
-    let flagpole_element_count: GLsizei = 3 * ((FLAGPOLE_SLICE - 1) * 2 * FLAGPOLE_RES);
-    let wall_element_count: GLsizei = 6;
-    let ground_element_count: GLsizei = 6;
-    let element_count: GLsizei = flagpole_element_count + wall_element_count + ground_element_count;
+// A cast cleat: a horn held off the shaft by two arms, with rounded tips,
+// shaded in facets.
+fn add_cleat(builder: &mut MeshBuilder, flagpole: &FlagpoleProfile) {
+    let xz = cleat_xz(flagpole);
+    let (bottom, top) = (FLAGPOLE_CLEAT - 0.5 * CLEAT_HORN_LENGTH, FLAGPOLE_CLEAT + 0.5 * CLEAT_HORN_LENGTH);
+    // The arms start inside the shaft so no gap shows where they meet it.
+    let arm_z = flagpole.axis_xz()[1] + 0.5 * flagpole.shaft_radius;
+    let arm_half_extents = [CLEAT_HORN_RADIUS, CLEAT_HORN_RADIUS, 0.5 * (xz[1] - arm_z)];
+
+    let mut cleat = MeshBuilder::new();
+    cleat.material(FLAGPOLE_SHININESS, FLAGPOLE_SPECULAR);
+    cleat.cylinder([xz[0], bottom, xz[1]], CLEAT_HORN_RADIUS, CLEAT_HORN_LENGTH, CLEAT_SIDES);
+    cleat.sphere([xz[0], bottom, xz[1]], CLEAT_HORN_RADIUS, CLEAT_SIDES, CLEAT_SIDES / 2);
+    cleat.sphere([xz[0], top, xz[1]], CLEAT_HORN_RADIUS, CLEAT_SIDES, CLEAT_SIDES / 2);
+    for &y in [FLAGPOLE_CLEAT - CLEAT_ARM_SPACING, FLAGPOLE_CLEAT + CLEAT_ARM_SPACING].iter() {
+        cleat.cuboid([xz[0], y, 0.5 * (arm_z + xz[1])], arm_half_extents);
+    }
+    cleat.compute_flat_normals().expect("the cleat has few enough triangles");
+    // Flat normals split every vertex between its triangles; only the ones
+    // on the same facet come back together.
+    cleat.weld(1e-5);
+    remap_texcoords(&mut cleat.vertices, TEX_FLAGPOLE_LO, TEX_FLAGPOLE_HI);
 
-    let mut vertex_data = vec![FlagVertex::zero(); vertex_count as usize]; 
+    builder.append(&cleat);
+}
 
-    let mut element_data = vec![0 as GLushort; element_count as usize];
+fn add_flagpole(builder: &mut MeshBuilder, profile: &FlagpoleProfile) {
+    FlagpoleBuilder::new()
+        .axis(profile.axis_xz())
+        .profile(profile)
+        .texcoords(TEX_FLAGPOLE_LO, TEX_FLAGPOLE_HI)
+        .material(FLAGPOLE_SHININESS, FLAGPOLE_SPECULAR)
+        .build(&mut builder.vertices, &mut builder.elements);
+    add_cleat(builder, profile);
+}
 
-    vertex_data[0].position[0] = GROUND_LO[0];
-    vertex_data[0].position[1] = GROUND_LO[1];
-    vertex_data[0].position[2] = GROUND_LO[2];
-    vertex_data[0].position[3] = 1.0;
-    vertex_data[0].normal[0]   = 0.0;
-    vertex_data[0].normal[1]   = 1.0;
-    vertex_data[0].normal[2]   = 0.0;
-    vertex_data[0].normal[3]   = 0.0;
-    vertex_data[0].texcoord[0] = TEX_GROUND_LO[0];
-    vertex_data[0].texcoord[1] = TEX_GROUND_LO[1];
-    vertex_data[0].shininess   = 0.0;
-    vertex_data[0].specular[0] = 0;
-    vertex_data[0].specular[1] = 0;
-    vertex_data[0].specular[2] = 0;
-    vertex_data[0].specular[3] = 0;
-
-    vertex_data[1].position[0] = GROUND_HI[0];
-    vertex_data[1].position[1] = GROUND_LO[1];
-    vertex_data[1].position[2] = GROUND_LO[2];
-    vertex_data[1].position[3] = 1.0;
-    vertex_data[1].normal[0]   = 0.0;
-    vertex_data[1].normal[1]   = 1.0;
-    vertex_data[1].normal[2]   = 0.0;
-    vertex_data[1].normal[3]   = 0.0;
-    vertex_data[1].texcoord[0] = TEX_GROUND_HI[0];
-    vertex_data[1].texcoord[1] = TEX_GROUND_LO[1];
-    vertex_data[1].shininess   = 0.0;
-    vertex_data[1].specular[0] = 0;
-    vertex_data[1].specular[1] = 0;
-    vertex_data[1].specular[2] = 0;
-    vertex_data[1].specular[3] = 0;
-
-    vertex_data[2].position[0] = GROUND_HI[0];
-    vertex_data[2].position[1] = GROUND_LO[1];
-    vertex_data[2].position[2] = GROUND_HI[2];
-    vertex_data[2].position[3] = 1.0;
-    vertex_data[2].normal[0]   = 0.0;
-    vertex_data[2].normal[1]   = 1.0;
-    vertex_data[2].normal[2]   = 0.0;
-    vertex_data[2].normal[3]   = 0.0;
-    vertex_data[2].texcoord[0] = TEX_GROUND_HI[0];
-    vertex_data[2].texcoord[1] = TEX_GROUND_HI[1];
-    vertex_data[2].shininess   = 0.0;
-    vertex_data[2].specular[0] = 0;
-    vertex_data[2].specular[1] = 0;
-    vertex_data[2].specular[2] = 0;
-    vertex_data[2].specular[3] = 0;
-
-    vertex_data[3].position[0] = GROUND_LO[0];
-    vertex_data[3].position[1] = GROUND_LO[1];
-    vertex_data[3].position[2] = GROUND_HI[2];
-    vertex_data[3].position[3] = 1.0;
-    vertex_data[3].normal[0]   = 0.0;
-    vertex_data[3].normal[1]   = 1.0;
-    vertex_data[3].normal[2]   = 0.0;
-    vertex_data[3].normal[3]   = 0.0;
-    vertex_data[3].texcoord[0] = TEX_GROUND_LO[0];
-    vertex_data[3].texcoord[1] = TEX_GROUND_HI[1];
-    vertex_data[3].shininess   = 0.0;
-    vertex_data[3].specular[0] = 0;
-    vertex_data[3].specular[1] = 0;
-    vertex_data[3].specular[2] = 0;
-    vertex_data[3].specular[3] = 0;
-
-    vertex_data[4].position[0] = WALL_LO[0];
-    vertex_data[4].position[1] = WALL_LO[1];
-    vertex_data[4].position[2] = WALL_LO[2];
-    vertex_data[4].position[3] = 1.0;
-    vertex_data[4].normal[0]   = 0.0;
-    vertex_data[4].normal[1]   = 0.0;
-    vertex_data[4].normal[2]   = -1.0;
-    vertex_data[4].normal[3]   = 0.0;
-    vertex_data[4].texcoord[0] = TEX_WALL_LO[0];
-    vertex_data[4].texcoord[1] = TEX_WALL_LO[1];
-    vertex_data[4].shininess   = 0.0;
-    vertex_data[4].specular[0] = 0;
-    vertex_data[4].specular[1] = 0;
-    vertex_data[4].specular[2] = 0;
-    vertex_data[4].specular[3] = 0;
-
-    vertex_data[5].position[0] = WALL_HI[0];
-    vertex_data[5].position[1] = WALL_LO[1];
-    vertex_data[5].position[2] = WALL_LO[2];
-    vertex_data[5].position[3] = 1.0;
-    vertex_data[5].normal[0]   = 0.0;
-    vertex_data[5].normal[1]   = 0.0;
-    vertex_data[5].normal[2]   = -1.0;
-    vertex_data[5].normal[3]   = 0.0;
-    vertex_data[5].texcoord[0] = TEX_WALL_HI[0];
-    vertex_data[5].texcoord[1] = TEX_WALL_LO[1];
-    vertex_data[5].shininess   = 0.0;
-    vertex_data[5].specular[0] = 0;
-    vertex_data[5].specular[1] = 0;
-    vertex_data[5].specular[2] = 0;
-    vertex_data[5].specular[3] = 0;
-
-    vertex_data[6].position[0] = WALL_HI[0];
-    vertex_data[6].position[1] = WALL_HI[1];
-    vertex_data[6].position[2] = WALL_LO[2];
-    vertex_data[6].position[3] = 1.0;
-    vertex_data[6].normal[0]   = 0.0;
-    vertex_data[6].normal[1]   = 0.0;
-    vertex_data[6].normal[2]   = -1.0;
-    vertex_data[6].normal[3]   = 0.0;
-    vertex_data[6].texcoord[0] = TEX_WALL_HI[0];
-    vertex_data[6].texcoord[1] = TEX_WALL_HI[1];
-    vertex_data[6].shininess   = 0.0;
-    vertex_data[6].specular[0] = 0;
-    vertex_data[6].specular[1] = 0;
-    vertex_data[6].specular[2] = 0;
-    vertex_data[6].specular[3] = 0;
-
-    vertex_data[7].position[0] = WALL_LO[0];
-    vertex_data[7].position[1] = WALL_HI[1];
-    vertex_data[7].position[2] = WALL_LO[2];
-    vertex_data[7].position[3] = 1.0;
-    vertex_data[7].normal[0]   = 0.0;
-    vertex_data[7].normal[1]   = 0.0;
-    vertex_data[7].normal[2]   = -1.0;
-    vertex_data[7].normal[3]   = 0.0;
-    vertex_data[7].texcoord[0] = TEX_WALL_LO[0];
-    vertex_data[7].texcoord[1] = TEX_WALL_HI[1];
-    vertex_data[7].shininess   = 0.0;
-    vertex_data[7].specular[0] = 0;
-    vertex_data[7].specular[1] = 0;
-    vertex_data[7].specular[2] = 0;
-    vertex_data[7].specular[3] = 0;
-
-    vertex_data[8].position[0] = FLAGPOLE_AXIS_XZ[0];
-    vertex_data[8].position[1] = FLAGPOLE_TRUCK_TOP;
-    vertex_data[8].position[2] = FLAGPOLE_AXIS_XZ[1];
-    vertex_data[8].position[3] = 1.0;
-    vertex_data[8].normal[0]   = 0.0;
-    vertex_data[8].normal[1]   = 1.0;
-    vertex_data[8].normal[2]   = 0.0;
-    vertex_data[8].normal[3]   = 0.0;
-    vertex_data[8].texcoord[0] = TEX_FLAGPOLE_LO[0];
-    vertex_data[8].texcoord[1] = t_truck_top;
-    vertex_data[8].shininess   = FLAGPOLE_SHININESS;
-    vertex_data[8].specular[0] = 0;
-    vertex_data[8].specular[1] = 0;
-    vertex_data[8].specular[2] = 0;
-    vertex_data[8].specular[3] = 0;
-
-    let mut vertex_i = 9;
-    for i in 0..FLAGPOLE_RES {
-        let sn: f32 = f32::sin(theta_step * (i as f32));
-        let cs: f32 = f32::cos(theta_step * (i as f32));
-        let s: f32 = TEX_FLAGPOLE_LO[0] + s_step * (i as f32);
-
-        vertex_data[vertex_i].position[0]
-            = FLAGPOLE_AXIS_XZ[0] + FLAGPOLE_TRUCK_TOP_RADIUS * cs;
-        vertex_data[vertex_i].position[1] = FLAGPOLE_TRUCK_TOP;
-        vertex_data[vertex_i].position[2]
-            = FLAGPOLE_AXIS_XZ[1] + FLAGPOLE_TRUCK_TOP_RADIUS * sn;
-        vertex_data[vertex_i].position[3] = 1.0;
-        vertex_data[vertex_i].normal[0]   = cs * 0.5;
-        vertex_data[vertex_i].normal[1]   = f32::sqrt(3.0/4.0);
-        vertex_data[vertex_i].normal[2]   = sn * 0.5;
-        vertex_data[vertex_i].normal[3]   = 0.0;
-        vertex_data[vertex_i].texcoord[0] = s;
-        vertex_data[vertex_i].texcoord[1] = t_truck_top;
-        vertex_data[vertex_i].shininess   = FLAGPOLE_SHININESS;
-        vertex_data[vertex_i].specular[0] = FLAGPOLE_SPECULAR[0];
-        vertex_data[vertex_i].specular[1] = FLAGPOLE_SPECULAR[1];
-        vertex_data[vertex_i].specular[2] = FLAGPOLE_SPECULAR[2];
-        vertex_data[vertex_i].specular[3] = FLAGPOLE_SPECULAR[3];
-        vertex_i += 1;
-
-        vertex_data[vertex_i].position[0]
-            = FLAGPOLE_AXIS_XZ[0] + FLAGPOLE_TRUCK_CROWN_RADIUS * cs;
-        vertex_data[vertex_i].position[1] = FLAGPOLE_TRUCK_CROWN;
-        vertex_data[vertex_i].position[2]
-            = FLAGPOLE_AXIS_XZ[1] + FLAGPOLE_TRUCK_CROWN_RADIUS * sn;
-        vertex_data[vertex_i].position[3] = 1.0;
-        vertex_data[vertex_i].normal[0]   = cs;
-        vertex_data[vertex_i].normal[1]   = 0.0;
-        vertex_data[vertex_i].normal[2]   = sn;
-        vertex_data[vertex_i].normal[3]   = 0.0;
-        vertex_data[vertex_i].texcoord[0] = s;
-        vertex_data[vertex_i].texcoord[1] = t_truck_crown;
-        vertex_data[vertex_i].shininess   = FLAGPOLE_SHININESS;
-        vertex_data[vertex_i].specular[0] = FLAGPOLE_SPECULAR[0];
-        vertex_data[vertex_i].specular[1] = FLAGPOLE_SPECULAR[1];
-        vertex_data[vertex_i].specular[2] = FLAGPOLE_SPECULAR[2];
-        vertex_data[vertex_i].specular[3] = FLAGPOLE_SPECULAR[3];
-        vertex_i += 1;
-
-        vertex_data[vertex_i].position[0]
-            = FLAGPOLE_AXIS_XZ[0] + FLAGPOLE_TRUCK_BOTTOM_RADIUS * cs;
-        vertex_data[vertex_i].position[1] = FLAGPOLE_TRUCK_BOTTOM;
-        vertex_data[vertex_i].position[2]
-            = FLAGPOLE_AXIS_XZ[1] + FLAGPOLE_TRUCK_BOTTOM_RADIUS * sn;
-        vertex_data[vertex_i].position[3] = 1.0;
-        vertex_data[vertex_i].normal[0]   = cs * f32::sqrt(15.0/16.0);
-        vertex_data[vertex_i].normal[1]   = -0.25;
-        vertex_data[vertex_i].normal[2]   = sn * f32::sqrt(15.0/16.0);
-        vertex_data[vertex_i].normal[3]   = 0.0;
-        vertex_data[vertex_i].texcoord[0] = s;
-        vertex_data[vertex_i].texcoord[1] = t_truck_bottom;
-        vertex_data[vertex_i].shininess   = FLAGPOLE_SHININESS;
-        vertex_data[vertex_i].specular[0] = FLAGPOLE_SPECULAR[0];
-        vertex_data[vertex_i].specular[1] = FLAGPOLE_SPECULAR[1];
-        vertex_data[vertex_i].specular[2] = FLAGPOLE_SPECULAR[2];
-        vertex_data[vertex_i].specular[3] = FLAGPOLE_SPECULAR[3];
-        vertex_i += 1;
-
-        vertex_data[vertex_i].position[0]
-            = FLAGPOLE_AXIS_XZ[0] + FLAGPOLE_SHAFT_RADIUS * cs;
-        vertex_data[vertex_i].position[1] = FLAGPOLE_SHAFT_TOP;
-        vertex_data[vertex_i].position[2]
-            = FLAGPOLE_AXIS_XZ[1] + FLAGPOLE_SHAFT_RADIUS * sn;
-        vertex_data[vertex_i].position[3] = 1.0;
-        vertex_data[vertex_i].normal[0]   = cs;
-        vertex_data[vertex_i].normal[1]   = 0.0;
-        vertex_data[vertex_i].normal[2]   = sn;
-        vertex_data[vertex_i].normal[3]   = 0.0;
-        vertex_data[vertex_i].texcoord[0] = s;
-        vertex_data[vertex_i].texcoord[1] = t_shaft_top;
-        vertex_data[vertex_i].shininess   = FLAGPOLE_SHININESS;
-        vertex_data[vertex_i].specular[0] = FLAGPOLE_SPECULAR[0];
-        vertex_data[vertex_i].specular[1] = FLAGPOLE_SPECULAR[1];
-        vertex_data[vertex_i].specular[2] = FLAGPOLE_SPECULAR[2];
-        vertex_data[vertex_i].specular[3] = FLAGPOLE_SPECULAR[3];
-        vertex_i += 1;
-
-        vertex_data[vertex_i].position[0]
-            = FLAGPOLE_AXIS_XZ[0] + FLAGPOLE_SHAFT_RADIUS * cs;
-        vertex_data[vertex_i].position[1] = FLAGPOLE_SHAFT_BOTTOM;
-        vertex_data[vertex_i].position[2]
-            = FLAGPOLE_AXIS_XZ[1] + FLAGPOLE_TRUCK_BOTTOM_RADIUS * sn;
-        vertex_data[vertex_i].position[3] = 1.0;
-        vertex_data[vertex_i].normal[0]   = cs;
-        vertex_data[vertex_i].normal[1]   = 0.0;
-        vertex_data[vertex_i].normal[2]   = sn;
-        vertex_data[vertex_i].normal[3]   = 0.0;
-        vertex_data[vertex_i].texcoord[0] = s;
-        vertex_data[vertex_i].texcoord[1] = t_shaft_bottom;
-        vertex_data[vertex_i].shininess   = FLAGPOLE_SHININESS;
-        vertex_data[vertex_i].specular[0] = FLAGPOLE_SPECULAR[0];
-        vertex_data[vertex_i].specular[1] = FLAGPOLE_SPECULAR[1];
-        vertex_data[vertex_i].specular[2] = FLAGPOLE_SPECULAR[2];
-        vertex_data[vertex_i].specular[3] = FLAGPOLE_SPECULAR[3];
-        vertex_i += 1;
-
-        vertex_data[vertex_i].position[0]
-            = FLAGPOLE_AXIS_XZ[0] + FLAGPOLE_SHAFT_RADIUS * cs;
-        vertex_data[vertex_i].position[1] = FLAGPOLE_SHAFT_BOTTOM;
-        vertex_data[vertex_i].position[2]
-            = FLAGPOLE_AXIS_XZ[1] + FLAGPOLE_TRUCK_BOTTOM_RADIUS * sn;
-        vertex_data[vertex_i].position[3] =  1.0;
-        vertex_data[vertex_i].normal[0]   =  0.0;
-        vertex_data[vertex_i].normal[1]   = -1.0;
-        vertex_data[vertex_i].normal[2]   =  0.0;
-        vertex_data[vertex_i].normal[3]   =  0.0;
-        vertex_data[vertex_i].texcoord[0] =  s;
-        vertex_data[vertex_i].texcoord[1] =  t_shaft_bottom;
-        vertex_data[vertex_i].shininess   =  FLAGPOLE_SHININESS;
-        vertex_data[vertex_i].specular[0] = FLAGPOLE_SPECULAR[0];
-        vertex_data[vertex_i].specular[1] = FLAGPOLE_SPECULAR[1];
-        vertex_data[vertex_i].specular[2] = FLAGPOLE_SPECULAR[2];
-        vertex_data[vertex_i].specular[3] = FLAGPOLE_SPECULAR[3];
-        vertex_i += 1;
-    }
+// The ground, wall and flagpole together, as exported.
+pub fn build_background_mesh(flagpole: &FlagpoleProfile) -> MeshBuilder {
+    let mut builder = MeshBuilder::new();
+    add_ground_and_wall(&mut builder);
+    add_flagpole(&mut builder, flagpole);
 
-    vertex_data[vertex_i].position[0] =  0.0;
-    vertex_data[vertex_i].position[1] =  FLAGPOLE_SHAFT_BOTTOM;
-    vertex_data[vertex_i].position[2] =  0.0;
-    vertex_data[vertex_i].position[3] =  1.0;
-    vertex_data[vertex_i].normal[0]   =  0.0;
-    vertex_data[vertex_i].normal[1]   = -1.0;
-    vertex_data[vertex_i].normal[2]   =  0.0;
-    vertex_data[vertex_i].normal[3]   =  0.0;
-    vertex_data[vertex_i].texcoord[0] =  0.5;
-    vertex_data[vertex_i].texcoord[1] =  t_shaft_bottom;
-    vertex_data[vertex_i].shininess   =  FLAGPOLE_SHININESS;
-    vertex_data[vertex_i].specular[0] = FLAGPOLE_SPECULAR[0];
-    vertex_data[vertex_i].specular[1] = FLAGPOLE_SPECULAR[1];
-    vertex_data[vertex_i].specular[2] = FLAGPOLE_SPECULAR[2];
-    vertex_data[vertex_i].specular[3] = FLAGPOLE_SPECULAR[3];
-
-    element_i = 0;
-
-    element_data[element_i] = 0;
-    element_i += 1;
-    element_data[element_i] = 1;
-    element_i += 1;
-    element_data[element_i] = 2;
-    element_i += 1;
-
-    element_data[element_i] = 0;
-    element_i += 1;
-    element_data[element_i] = 2;
-    element_i += 1;
-    element_data[element_i] = 3;
-    element_i += 1;
-
-    element_data[element_i] = 4;
-    element_i += 1;
-    element_data[element_i] = 5;
-    element_i += 1;
-    element_data[element_i] = 6;
-    element_i += 1;
-
-    element_data[element_i] = 4;
-    element_i += 1;
-    element_data[element_i] = 6;
-    element_i += 1;
-    element_data[element_i] = 7;
-    element_i += 1;
-
-    for i in 0..(FLAGPOLE_RES - 1) {
-        element_data[element_i] = 8;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i        ) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1)    ) as GLushort;
-        element_i += 1;
-
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i        ) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 1) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1)    ) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 1) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 1) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1)    ) as GLushort;
-        element_i += 1;
-
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 1) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 2) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 1) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 2) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 2) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 1) as GLushort;
-        element_i += 1;
-
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 2) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 3) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 2) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 3) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 3) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 2) as GLushort;
-        element_i += 1;
-
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 3) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 4) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 3) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 4) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 4) as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 3) as GLushort;
-        element_i += 1;
-
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*i     + 5) as GLushort;
-        element_i += 1;
-        element_data[element_i] = vertex_i as GLushort;
-        element_i += 1;
-        element_data[element_i] = 9 + (FLAGPOLE_SLICE*(i+1) + 5) as GLushort;
-        element_i += 1;
-    }
+    builder
+}
 
-    element_data[element_i] = 8;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1)    ) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9;
-    element_i += 1;
-
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1)    ) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 1) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 1) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 1;
-    element_i += 1;
-    element_data[element_i] = 9;
-    element_i += 1;
-
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 1) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 2) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 1;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 2) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 2;
-    element_i += 1;
-    element_data[element_i] = 9 + 1;
-    element_i += 1;
-
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 2) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 3) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 2;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 3) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 3;
-    element_i += 1;
-    element_data[element_i] = 9 + 2;
-    element_i += 1;
-
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 3) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 4) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 3;
-    element_i += 1;
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 4) as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 4;
-    element_i += 1;
-    element_data[element_i] = 9 + 3;
-    element_i += 1;
-
-    element_data[element_i] = 9 + (FLAGPOLE_SLICE*(FLAGPOLE_RES-1) + 5) as GLushort;
-    element_i += 1;
-    element_data[element_i] = vertex_i as GLushort;
-    element_i += 1;
-    element_data[element_i] = 9 + 5;
-    element_i += 1;
+// `region` is where the background texture sits in the texture the mesh is
+// drawn with. The flagpole is left to init_flagpole_mesh so it can have a
+// material of its own.
+pub fn init_background_mesh(out_mesh: &mut FlagMesh, region: &Region) {
+    let mut builder = MeshBuilder::new();
+    add_ground_and_wall(&mut builder);
+    builder.compute_tangents();
+    region.remap_vertices(&mut builder.vertices);
+    builder.build(out_mesh, gl::STATIC_DRAW);
+}
 
-    init_mesh(
-        out_mesh,
-        &vertex_data, vertex_count,
-        &element_data, element_count,
-        gl::STATIC_DRAW
-    );
+pub fn init_flagpole_mesh(out_mesh: &mut FlagMesh, region: &Region, flagpole: &FlagpoleProfile) {
+    let mut builder = MeshBuilder::new();
+    add_flagpole(&mut builder, flagpole);
+    builder.compute_tangents();
+    region.remap_vertices(&mut builder.vertices);
+    builder.build(out_mesh, gl::STATIC_DRAW);
 }
 
-pub fn update_flag_mesh(
-    mesh: &FlagMesh, vertex_data: &mut [FlagVertex], time: GLfloat
+// The solid parts of the background for the cloth to collide with: the
+// shaft, the truck's crown, the halyard's fall to the cleat, and the ground
+// and wall planes.
+pub fn scene_colliders(flagpole: &FlagpoleProfile) -> Vec<Collider> {
+    let axis_xz = flagpole.axis_xz();
+    let (x, z) = (axis_xz[0], axis_xz[1]);
+    let fall_xz = cleat_xz(flagpole);
+
+    vec![
+        Collider::Cylinder {
+            a: [x, flagpole.shaft_bottom, z],
+            b: [x, flagpole.shaft_top, z],
+            radius: flagpole.shaft_radius,
+        },
+        Collider::Sphere {
+            center: [x, flagpole.truck_crown, z],
+            radius: flagpole.truck_crown_radius,
+        },
+        Collider::Capsule {
+            a: [fall_xz[0], FLAGPOLE_CLEAT, fall_xz[1]],
+            b: [fall_xz[0], flagpole.truck_bottom, fall_xz[1]],
+            radius: ROPE_RADIUS,
+        },
+        Collider::Plane { point: GROUND_LO, normal: [0.0, 1.0, 0.0] },
+        Collider::Plane { point: WALL_LO, normal: [0.0, 0.0, -1.0] },
+    ]
+}
+
+pub fn halyard_offset(halyard_position: GLfloat) -> GLfloat {
+    (halyard_position - 1.0) * FLAG_HOIST_TRAVEL
+}
+
+pub fn calculate_flag_mesh(vertex_data: &mut [FlagVertex], time: GLfloat, hoist_offset: GLfloat) {
+    calculate_flag_grid(vertex_data, FLAG_X_RES as usize, FLAG_Y_RES as usize, time, hoist_offset);
+}
+
+// The flag at any resolution, one vertex at a time. flag_update does the same
+// faster, and is measured against this.
+pub fn calculate_flag_grid(
+    vertex_data: &mut [FlagVertex], columns: usize, rows: usize, time: GLfloat, hoist_offset: GLfloat
 ) {
+    let s_step = 1.0 / ((columns - 1) as GLfloat);
+    let t_step = 1.0 / ((rows - 1) as GLfloat);
     let mut i = 0;
-    for t in 0..FLAG_Y_RES {
-        for s in 0..FLAG_X_RES {
-            let ss: GLfloat = FLAG_S_STEP * (s as GLfloat);
-            let tt: GLfloat = FLAG_T_STEP * (t as GLfloat);
+    for t in 0..rows {
+        for s in 0..columns {
+            let ss: GLfloat = s_step * (s as GLfloat);
+            let tt: GLfloat = t_step * (t as GLfloat);
 
             calculate_flag_vertex(&mut vertex_data[i], ss, tt, time);
+            vertex_data[i].position[1] += hoist_offset;
 
             i += 1;
         }
     }
+}
 
+// Replaces the vertex data of a mesh created with the same non-static hint.
+pub fn upload_vertices(mesh: &FlagMesh, vertex_data: &[FlagVertex], hint: GLenum) {
     unsafe {
         gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vertex_buffer);
         gl::BufferData(
             gl::ARRAY_BUFFER,
-            (FLAG_VERTEX_COUNT as usize * mem::size_of::<FlagVertex>()) as GLsizeiptr,
+            (vertex_data.len() * mem::size_of::<FlagVertex>()) as GLsizeiptr,
             mem::transmute(&vertex_data[0]),
-            gl::STREAM_DRAW
+            hint
         );
     }
 }
+
+// Replaces the elements of a mesh created with a non-static hint, such as
+// the flag's after a tear.
+pub fn upload_elements(mesh: &mut FlagMesh, element_data: &[GLushort]) {
+    mesh.element_count = element_data.len() as GLsizei;
+    if element_data.is_empty() {
+        return;
+    }
+
+    unsafe {
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, mesh.element_buffer);
+        gl::BufferData(
+            gl::ELEMENT_ARRAY_BUFFER,
+            (element_data.len() * mem::size_of::<GLushort>()) as GLsizeiptr,
+            mem::transmute(&element_data[0]),
+            gl::DYNAMIC_DRAW
+        );
+    }
+}
+
+// Blends positions and normals between two states of the same mesh, taking
+// everything else from the later one.
+pub fn interpolate_vertices(
+    out: &mut [FlagVertex], previous: &[FlagVertex], current: &[FlagVertex], alpha: GLfloat
+) {
+    for ((o, a), b) in out.iter_mut().zip(previous.iter()).zip(current.iter()) {
+        *o = *b;
+        for c in 0..3 {
+            o.position[c] = a.position[c] + (b.position[c] - a.position[c]) * alpha;
+            o.normal[c] = a.normal[c] + (b.normal[c] - a.normal[c]) * alpha;
+            o.tangent[c] = a.tangent[c] + (b.tangent[c] - a.tangent[c]) * alpha;
+        }
+        if vec_util::vec_length(&o.normal) > 0.0 {
+            vec_util::vec_normalize(&mut o.normal);
+        }
+        if vec_util::vec_length(&o.tangent) > 0.0 {
+            vec_util::vec_normalize(&mut o.tangent);
+        }
+    }
+}
+
+const ROPE_SIDES: GLsizei = 4;
+const ROPE_SEGMENTS: GLsizei = 3;
+const ROPE_RADIUS: GLfloat = 0.0015;
+const ROPE_TURN_SEGMENTS: usize = 8;
+const ROPE_SHININESS: GLfloat = 0.0;
+const ROPE_TEXCOORD: [GLfloat; 2] = [0.015625, 0.5];
+
+fn calculate_rope_segment(
+    vertex_data: &mut [FlagVertex], axis_xz: [GLfloat; 2], bottom: GLfloat, top: GLfloat
+) {
+    let theta_step: GLfloat = 2.0 * vec_util::M_PI / (ROPE_SIDES as GLfloat);
+
+    for i in 0..ROPE_SIDES {
+        let sn: f32 = f32::sin(theta_step * (i as f32));
+        let cs: f32 = f32::cos(theta_step * (i as f32));
+
+        for (j, y) in [bottom, top].iter().enumerate() {
+            let v = &mut vertex_data[(2 * i) as usize + j];
+            v.position[0] = axis_xz[0] + ROPE_RADIUS * cs;
+            v.position[1] = *y;
+            v.position[2] = axis_xz[1] + ROPE_RADIUS * sn;
+            v.position[3] = 1.0;
+            v.normal[0]   = cs;
+            v.normal[1]   = 0.0;
+            v.normal[2]   = sn;
+            v.normal[3]   = 0.0;
+        }
+    }
+}
+
+// The halyard runs from the cleat up over the truck and back down to the
+// hoist of the flag, with a second length from the bottom of the hoist
+// back to the cleat. Only the lengths on the flag side move with it.
+pub fn calculate_rope(vertex_data: &mut [FlagVertex], hoist_offset: GLfloat, flagpole: &FlagpoleProfile) {
+    let segment_vertex_count = (2 * ROPE_SIDES) as usize;
+    let flag_side_xz: [GLfloat; 2] = [ROPE_RADIUS, 0.0];
+    let cleat_side_xz = cleat_xz(flagpole);
+
+    let (upper, rest) = vertex_data.split_at_mut(segment_vertex_count);
+    let (lower, fall) = rest.split_at_mut(segment_vertex_count);
+    calculate_rope_segment(
+        upper, flag_side_xz, FLAG_HOIST_TOP + hoist_offset, flagpole.truck_bottom
+    );
+    calculate_rope_segment(
+        lower, flag_side_xz, FLAGPOLE_CLEAT, FLAG_HOIST_BOTTOM + hoist_offset
+    );
+    calculate_rope_segment(
+        fall, cleat_side_xz, FLAGPOLE_CLEAT, flagpole.truck_bottom
+    );
+}
+
+// The rope takes its colour from a point in the background texture, found
+// through `region` as for init_background_mesh. After the lengths
+// calculate_rope moves comes a turn taken round the cleat, which stays put.
+pub fn init_rope_mesh(out_mesh: &mut FlagMesh, region: &Region, flagpole: &FlagpoleProfile) -> Vec<FlagVertex> {
+    let vertex_count: GLsizei = ROPE_SEGMENTS * 2 * ROPE_SIDES;
+    let element_count: GLsizei = ROPE_SEGMENTS * 6 * ROPE_SIDES;
+    let mut vertex_data = vec![FlagVertex::zero(); vertex_count as usize];
+    let mut element_data = vec![0 as GLushort; element_count as usize];
+
+    calculate_rope(&mut vertex_data, 0.0, flagpole);
+    let cleat = cleat_xz(flagpole);
+    let mut turn = MeshBuilder::new();
+    turn.torus(
+        [cleat[0], FLAGPOLE_CLEAT, cleat[1]], CLEAT_HORN_RADIUS + ROPE_RADIUS, ROPE_RADIUS,
+        ROPE_TURN_SEGMENTS, ROPE_SIDES as usize
+    );
+    let base = vertex_data.len() as GLushort;
+    vertex_data.extend_from_slice(&turn.vertices);
+
+    let texcoord = region.remap(ROPE_TEXCOORD);
+    for v in vertex_data.iter_mut() {
+        v.texcoord[0] = texcoord[0];
+        v.texcoord[1] = texcoord[1];
+        v.shininess   = ROPE_SHININESS;
+    }
+
+    let mut element_i = 0;
+    for segment in 0..ROPE_SEGMENTS {
+        let base = segment * 2 * ROPE_SIDES;
+        for i in 0..ROPE_SIDES {
+            let bottom      = (base + 2 * i) as GLushort;
+            let top         = (base + 2 * i + 1) as GLushort;
+            let next_bottom = (base + 2 * ((i + 1) % ROPE_SIDES)) as GLushort;
+            let next_top    = (base + 2 * ((i + 1) % ROPE_SIDES) + 1) as GLushort;
+
+            element_data[element_i]     = bottom;
+            element_data[element_i + 1] = next_bottom;
+            element_data[element_i + 2] = top;
+            element_data[element_i + 3] = top;
+            element_data[element_i + 4] = next_bottom;
+            element_data[element_i + 5] = next_top;
+            element_i += 6;
+        }
+    }
+    element_data.extend(turn.elements.iter().map(|&e| base + e));
+
+    init_mesh(
+        out_mesh,
+        &vertex_data, vertex_data.len() as GLsizei,
+        &element_data, element_data.len() as GLsizei,
+        gl::STREAM_DRAW
+    );
+
+    vertex_data
+}
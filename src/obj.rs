@@ -0,0 +1,363 @@
+use gl::types::*;
+use mesh_builder::MeshBuilder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::f32;
+
+
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub shininess: GLfloat,
+    pub specular: [GLubyte; 4],
+    pub diffuse_map: Option<PathBuf>,
+}
+
+impl ObjMaterial {
+    fn default() -> ObjMaterial {
+        ObjMaterial {
+            shininess: 0.0,
+            specular: [0; 4],
+            diffuse_map: None,
+        }
+    }
+}
+
+pub struct ObjMesh {
+    pub builder: MeshBuilder,
+    pub diffuse_map: Option<PathBuf>,
+}
+
+fn invalid_data(path: &Path, line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path.display(), line, message)
+    )
+}
+
+fn parse_floats(path: &Path, line: usize, fields: &[&str], count: usize) -> io::Result<Vec<GLfloat>> {
+    if fields.len() < count {
+        return Err(invalid_data(path, line, "too few components"));
+    }
+
+    fields[0..count].iter().map(|field| {
+        field.parse::<GLfloat>().map_err(|_| invalid_data(path, line, "malformed number"))
+    }).collect()
+}
+
+fn read_to_string(path: &Path) -> io::Result<String> {
+    let mut file = try!(File::open(path));
+    let mut source = String::new();
+    try!(file.read_to_string(&mut source));
+
+    Ok(source)
+}
+
+// Arguments taken by each texture map option, such as -s's three scale
+// factors. Options come before the file name, which may contain spaces.
+fn option_arguments(option: &str) -> usize {
+    match option {
+        "-blendu" | "-blendv" | "-cc" | "-clamp" | "-imfchan" | "-texres" | "-boost" | "-bm" => 1,
+        "-mm" => 2,
+        "-o" | "-s" | "-t" => 3,
+        _ => 0,
+    }
+}
+
+fn texture_name(fields: &[&str]) -> String {
+    let mut i = 0;
+    while i < fields.len() && fields[i].starts_with('-') {
+        let option = fields[i];
+        i += 1;
+        // -o, -s and -t can leave out their last factors.
+        let count = option_arguments(option);
+        let mut taken = 0;
+        while taken < count && i < fields.len() && (taken == 0 || fields[i].parse::<GLfloat>().is_ok()) {
+            i += 1;
+            taken += 1;
+        }
+    }
+
+    fields[i..].join(" ")
+}
+
+pub fn load_mtl(path: &Path) -> io::Result<HashMap<String, ObjMaterial>> {
+    let source = try!(read_to_string(path));
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue;
+        }
+
+        if fields[0] == "newmtl" {
+            let name = fields[1..].join(" ");
+            materials.insert(name.clone(), ObjMaterial::default());
+            current = Some(name);
+            continue;
+        }
+
+        let material = match current {
+            Some(ref name) => materials.get_mut(name).unwrap(),
+            None => continue,
+        };
+        match fields[0] {
+            "Ns" => {
+                let ns = try!(parse_floats(path, i + 1, &fields[1..], 1));
+                material.shininess = ns[0];
+            }
+            "Ks" => {
+                let ks = try!(parse_floats(path, i + 1, &fields[1..], 3));
+                for c in 0..3 {
+                    material.specular[c] = (f32::min(f32::max(ks[c], 0.0), 1.0) * 255.0) as GLubyte;
+                }
+            }
+            "map_Kd" => {
+                let name = texture_name(&fields[1..]);
+                if !name.is_empty() {
+                    material.diffuse_map = Some(directory.join(name));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+// A face corner, as 0-based position, texcoord and normal indices.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+fn resolve_index(path: &Path, line: usize, field: &str, count: usize) -> io::Result<usize> {
+    let index = try!(field.parse::<isize>().map_err(|_| invalid_data(path, line, "malformed index")));
+    let resolved = if index < 0 { count as isize + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as isize {
+        return Err(invalid_data(path, line, "index out of range"));
+    }
+
+    Ok(resolved as usize)
+}
+
+fn parse_corner(
+    path: &Path, line: usize, field: &str, counts: (usize, usize, usize)
+) -> io::Result<Corner> {
+    let mut parts = field.split('/');
+    let v = try!(resolve_index(path, line, parts.next().unwrap_or(""), counts.0));
+    let vt = match parts.next() {
+        Some(s) if !s.is_empty() => Some(try!(resolve_index(path, line, s, counts.1))),
+        _ => None,
+    };
+    let vn = match parts.next() {
+        Some(s) if !s.is_empty() => Some(try!(resolve_index(path, line, s, counts.2))),
+        _ => None,
+    };
+
+    Ok((v, vt, vn))
+}
+
+// Splits a polygon into triangles by ear clipping in the plane it mostly
+// faces, so concave faces come out right. Returns indices into `polygon`.
+fn triangulate(polygon: &[[GLfloat; 3]]) -> Vec<[usize; 3]> {
+    let n = polygon.len();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method for the polygon normal.
+    let mut normal = [0.0 as GLfloat; 3];
+    for i in 0..n {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % n];
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    let (u, v) = if f32::abs(normal[0]) > f32::abs(normal[1]) && f32::abs(normal[0]) > f32::abs(normal[2]) {
+        (1, 2)
+    } else if f32::abs(normal[1]) > f32::abs(normal[2]) {
+        (2, 0)
+    } else {
+        (0, 1)
+    };
+    let axis = if u == 1 { 0 } else if u == 2 { 1 } else { 2 };
+    let sign = if normal[axis] < 0.0 { -1.0 } else { 1.0 };
+    let points: Vec<[GLfloat; 2]> = polygon.iter().map(|p| [p[u], p[v]]).collect();
+
+    let cross = |a: [GLfloat; 2], b: [GLfloat; 2], c: [GLfloat; 2]| {
+        sign * ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]))
+    };
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let mut clipped = false;
+        for i in 0..m {
+            let (ia, ib, ic) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            let (a, b, c) = (points[ia], points[ib], points[ic]);
+            if cross(a, b, c) <= 0.0 {
+                continue;
+            }
+
+            let contains_other = remaining.iter().any(|&k| {
+                k != ia && k != ib && k != ic
+                    && cross(a, b, points[k]) >= 0.0
+                    && cross(b, c, points[k]) >= 0.0
+                    && cross(c, a, points[k]) >= 0.0
+            });
+            if contains_other {
+                continue;
+            }
+
+            triangles.push([ia, ib, ic]);
+            remaining.remove(i);
+            clipped = true;
+            break;
+        }
+
+        // Degenerate or self-intersecting polygons fall back to a fan.
+        if !clipped {
+            for i in 1..(remaining.len() - 1) {
+                triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+            }
+            return triangles;
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+    triangles
+}
+
+struct ChunkState {
+    meshes: Vec<ObjMesh>,
+    corners: HashMap<(Corner, usize), GLushort>,
+}
+
+impl ChunkState {
+    fn start(&mut self, diffuse_map: Option<PathBuf>) {
+        self.meshes.push(ObjMesh { builder: MeshBuilder::new(), diffuse_map: diffuse_map });
+        self.corners.clear();
+    }
+}
+
+// Loads a Wavefront OBJ file and the materials it references. OBJ models are
+// right-handed, so z is mirrored to bring them into the scene's frame, which
+// also keeps their counter-clockwise faces pointing outward.
+pub fn load_obj(path: &Path) -> io::Result<Vec<ObjMesh>> {
+    let source = try!(read_to_string(path));
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut positions: Vec<[GLfloat; 3]> = vec![];
+    let mut texcoords: Vec<[GLfloat; 2]> = vec![];
+    let mut normals: Vec<[GLfloat; 3]> = vec![];
+    let mut materials: Vec<ObjMaterial> = vec![ObjMaterial::default()];
+    let mut material_names: HashMap<String, usize> = HashMap::new();
+    let mut material = 0;
+
+    let mut state = ChunkState { meshes: vec![], corners: HashMap::new() };
+    state.start(None);
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue;
+        }
+
+        match fields[0] {
+            "v" => {
+                let p = try!(parse_floats(path, line_number, &fields[1..], 3));
+                positions.push([p[0], p[1], -p[2]]);
+            }
+            "vt" => {
+                let t = try!(parse_floats(path, line_number, &fields[1..], 2));
+                texcoords.push([t[0], t[1]]);
+            }
+            "vn" => {
+                let n = try!(parse_floats(path, line_number, &fields[1..], 3));
+                normals.push([n[0], n[1], -n[2]]);
+            }
+            "mtllib" => {
+                for library_name in &fields[1..] {
+                    match load_mtl(&directory.join(library_name)) {
+                        Ok(library) => {
+                            for (name, m) in library {
+                                material_names.insert(name, materials.len());
+                                materials.push(m);
+                            }
+                        }
+                        Err(e) => eprintln!("Failed to load {}: {}", library_name, e),
+                    }
+                }
+            }
+            "usemtl" => {
+                let name = fields[1..].join(" ");
+                material = *material_names.get(&name).unwrap_or(&0);
+                let diffuse_map = materials[material].diffuse_map.clone();
+                if state.meshes.last().unwrap().diffuse_map != diffuse_map {
+                    if state.meshes.last().unwrap().builder.elements.is_empty() {
+                        state.meshes.last_mut().unwrap().diffuse_map = diffuse_map;
+                    } else {
+                        state.start(diffuse_map);
+                    }
+                }
+            }
+            "f" => {
+                if fields.len() < 4 {
+                    return Err(invalid_data(path, line_number, "face with fewer than three corners"));
+                }
+
+                let counts = (positions.len(), texcoords.len(), normals.len());
+                let mut corners = Vec::with_capacity(fields.len() - 1);
+                for field in &fields[1..] {
+                    corners.push(try!(parse_corner(path, line_number, field, counts)));
+                }
+
+                // Element buffers hold GLushort indices, so larger models are
+                // split into several meshes.
+                if !state.meshes.last().unwrap().builder.has_room(corners.len()) {
+                    let diffuse_map = materials[material].diffuse_map.clone();
+                    state.start(diffuse_map);
+                }
+
+                let mut indices = Vec::with_capacity(corners.len());
+                {
+                    let mesh = state.meshes.last_mut().unwrap();
+                    mesh.builder.material(materials[material].shininess, materials[material].specular);
+                    for &corner in corners.iter() {
+                        let (v, vt, vn) = corner;
+                        let builder = &mut mesh.builder;
+                        let index = *state.corners.entry((corner, material)).or_insert_with(|| {
+                            let texcoord = vt.map(|k| texcoords[k]).unwrap_or([0.0, 0.0]);
+                            let normal = vn.map(|k| normals[k]).unwrap_or([0.0, 0.0, 0.0]);
+                            builder.vertex(positions[v], normal, texcoord)
+                        });
+                        indices.push(index);
+                    }
+                }
+
+                let polygon: Vec<[GLfloat; 3]> = corners.iter().map(|c| positions[c.0]).collect();
+                let builder = &mut state.meshes.last_mut().unwrap().builder;
+                for triangle in triangulate(&polygon) {
+                    builder.triangle(indices[triangle[0]], indices[triangle[1]], indices[triangle[2]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut meshes = state.meshes;
+    meshes.retain(|mesh| !mesh.builder.elements.is_empty());
+    // Corners without a normal were given a zero one. Only those are filled
+    // in, so the normals the file does give are kept.
+    for mesh in meshes.iter_mut() {
+        mesh.builder.compute_missing_normals();
+    }
+
+    Ok(meshes)
+}
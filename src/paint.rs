@@ -0,0 +1,161 @@
+use capture;
+use file_util;
+use gl;
+use gl::types::*;
+use png;
+use std::fs;
+use std::io;
+use std::os::raw;
+
+
+// Brush colours in RGB, picked with the number keys.
+const PALETTE: [[u8; 3]; 8] = [
+    [200, 16, 46],
+    [255, 255, 255],
+    [1, 33, 105],
+    [0, 0, 0],
+    [255, 205, 0],
+    [0, 122, 61],
+    [255, 103, 31],
+    [100, 40, 140],
+];
+
+const MIN_BRUSH_RADIUS: i32 = 1;
+const MAX_BRUSH_RADIUS: i32 = 64;
+
+// Paints onto a CPU-side copy of the flag texture and uploads the rows each
+// stamp touches.
+pub struct Paint {
+    pub enabled: bool,
+    pixels: Vec<[u8; 3]>,
+    width: i32,
+    height: i32,
+    texture: GLuint,
+    color: usize,
+    radius: i32,
+    last: Option<(i32, i32)>,
+}
+
+impl Paint {
+    pub fn new() -> Paint {
+        Paint {
+            enabled: false,
+            pixels: vec![],
+            width: 0,
+            height: 0,
+            texture: 0,
+            color: 0,
+            radius: 4,
+            last: None,
+        }
+    }
+
+    // Takes a copy of the image `texture` was made from.
+    pub fn load(&mut self, filename: &str, texture: GLuint) -> io::Result<()> {
+        let (pixels, height, width) = try!(file_util::read_tga(filename));
+        self.pixels = pixels;
+        self.width = width;
+        self.height = height;
+        self.texture = texture;
+
+        Ok(())
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled && !self.pixels.is_empty();
+        println!("Paint mode {}", if self.enabled { "on" } else { "off" });
+    }
+
+    pub fn select_color(&mut self, color: usize) {
+        if color < PALETTE.len() {
+            self.color = color;
+            println!("Brush colour {:?}", PALETTE[color]);
+        }
+    }
+
+    pub fn resize_brush(&mut self, grow: bool) {
+        self.radius = if grow { self.radius * 2 } else { self.radius / 2 };
+        self.radius = i32::max(MIN_BRUSH_RADIUS, i32::min(self.radius, MAX_BRUSH_RADIUS));
+        println!("Brush radius {}", self.radius);
+    }
+
+    // Texture rows run from t = 0 upward, as the TGA pixels do.
+    fn texel(&self, texcoord: [GLfloat; 2]) -> (i32, i32) {
+        (
+            (texcoord[0] * self.width as GLfloat) as i32,
+            (texcoord[1] * self.height as GLfloat) as i32,
+        )
+    }
+
+    // Paints from the end of the stroke so far to `texcoord`, stamping often
+    // enough that fast cursor moves still leave a solid line.
+    pub fn stroke(&mut self, texcoord: [GLfloat; 2]) {
+        let (x, y) = self.texel(texcoord);
+        let (x0, y0) = self.last.unwrap_or((x, y));
+        let length = f32::max((x - x0).abs() as f32, (y - y0).abs() as f32);
+        let spacing = f32::max(self.radius as f32 * 0.5, 1.0);
+        let stamps = (length / spacing).ceil() as i32;
+
+        let (mut lo, mut hi) = (y, y);
+        for k in 0..(stamps + 1) {
+            let u = if stamps > 0 { k as f32 / stamps as f32 } else { 1.0 };
+            let sx = x0 + ((x - x0) as f32 * u).round() as i32;
+            let sy = y0 + ((y - y0) as f32 * u).round() as i32;
+            self.stamp(sx, sy);
+            lo = i32::min(lo, sy);
+            hi = i32::max(hi, sy);
+        }
+        self.last = Some((x, y));
+
+        self.upload(lo - self.radius, hi + self.radius);
+    }
+
+    // Lifts the brush, so the next stroke starts afresh.
+    pub fn lift(&mut self) {
+        self.last = None;
+    }
+
+    fn stamp(&mut self, cx: i32, cy: i32) {
+        let color = PALETTE[self.color];
+        let r2 = self.radius * self.radius;
+        for y in i32::max(cy - self.radius, 0)..i32::min(cy + self.radius + 1, self.height) {
+            for x in i32::max(cx - self.radius, 0)..i32::min(cx + self.radius + 1, self.width) {
+                if (x - cx) * (x - cx) + (y - cy) * (y - cy) <= r2 {
+                    self.pixels[(y * self.width + x) as usize] = [color[2], color[1], color[0]];
+                }
+            }
+        }
+    }
+
+    fn upload(&self, lo: i32, hi: i32) {
+        let lo = i32::max(lo, 0);
+        let hi = i32::min(hi, self.height - 1);
+        if lo > hi {
+            return;
+        }
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D, 0,
+                0, lo, self.width, hi - lo + 1,
+                gl::BGR, gl::UNSIGNED_BYTE,
+                self.pixels[(lo * self.width) as usize..].as_ptr() as *const raw::c_void
+            );
+        }
+    }
+
+    // Saves the painted texture as both TGA and PNG, returning the TGA's name.
+    pub fn save(&self) -> io::Result<String> {
+        try!(fs::create_dir_all(capture::CAPTURE_DIRECTORY));
+        let stem = format!("{}/flag_{}", capture::CAPTURE_DIRECTORY, capture::timestamp());
+        let filename = format!("{}.tga", stem);
+        try!(file_util::write_tga(&filename, &self.pixels, self.width, self.height));
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        let rgb = png::rgb_from_tga(&self.pixels, width, height);
+        try!(png::write_png(&format!("{}.png", stem), width, height, &rgb));
+
+        Ok(filename)
+    }
+}
@@ -0,0 +1,139 @@
+use gl::types::*;
+use meshes::FlagVertex;
+use vec_util;
+
+
+pub struct Ray {
+    pub origin: [GLfloat; 3],
+    pub direction: [GLfloat; 3],
+}
+
+impl Ray {
+    pub fn at(&self, distance: GLfloat) -> [GLfloat; 3] {
+        [
+            self.origin[0] + self.direction[0] * distance,
+            self.origin[1] + self.direction[1] * distance,
+            self.origin[2] + self.direction[2] * distance,
+        ]
+    }
+}
+
+pub struct Hit {
+    pub distance: GLfloat,
+    pub indices: [GLushort; 3],
+    pub barycentric: [GLfloat; 3],
+}
+
+impl Hit {
+    pub fn nearest_vertex(&self) -> usize {
+        let mut nearest = 0;
+        for k in 1..3 {
+            if self.barycentric[k] > self.barycentric[nearest] {
+                nearest = k;
+            }
+        }
+
+        self.indices[nearest] as usize
+    }
+
+    pub fn texcoord(&self, vertices: &[FlagVertex]) -> [GLfloat; 2] {
+        let mut texcoord = [0.0; 2];
+        for k in 0..3 {
+            let v = &vertices[self.indices[k] as usize];
+            texcoord[0] += v.texcoord[0] * self.barycentric[k];
+            texcoord[1] += v.texcoord[1] * self.barycentric[k];
+        }
+
+        texcoord
+    }
+}
+
+// Casts a ray from the eye through the cursor, given in window coordinates
+// with y running down, by taking the near and far points under it back
+// through the inverse projection and modelview matrices.
+pub fn cursor_ray(
+    p_matrix: &[GLfloat], mv_matrix: &[GLfloat], x: GLfloat, y: GLfloat, window_size: [GLfloat; 2]
+) -> Option<Ray> {
+    let mut inverse_p = [0.0; 16];
+    let mut inverse_mv = [0.0; 16];
+    if !vec_util::mat_inverse(&mut inverse_p, p_matrix) || !vec_util::mat_inverse(&mut inverse_mv, mv_matrix) {
+        return None;
+    }
+
+    let ndc_x = 2.0 * x / window_size[0] - 1.0;
+    let ndc_y = 1.0 - 2.0 * y / window_size[1];
+    let mut points = [[0.0 as GLfloat; 3]; 2];
+    for (point, &ndc_z) in points.iter_mut().zip([-1.0 as GLfloat, 1.0].iter()) {
+        let mut eye = [0.0; 4];
+        let mut world = [0.0; 4];
+        vec_util::mat_vec_mul(&mut eye, &inverse_p, &[ndc_x, ndc_y, ndc_z, 1.0]);
+        vec_util::mat_vec_mul(&mut world, &inverse_mv, &eye);
+        if world[3] == 0.0 {
+            return None;
+        }
+        *point = [world[0] / world[3], world[1] / world[3], world[2] / world[3]];
+    }
+
+    let mut direction = [
+        points[1][0] - points[0][0],
+        points[1][1] - points[0][1],
+        points[1][2] - points[0][2],
+    ];
+    if vec_util::vec_length(&direction) == 0.0 {
+        return None;
+    }
+    vec_util::vec_normalize(&mut direction);
+
+    Some(Ray { origin: points[0], direction: direction })
+}
+
+// Finds the nearest triangle the ray passes through from either side, by the
+// Moller-Trumbore test.
+pub fn intersect_mesh(ray: &Ray, vertices: &[FlagVertex], elements: &[GLushort]) -> Option<Hit> {
+    const EPSILON: GLfloat = 1.0e-7;
+
+    let mut nearest: Option<Hit> = None;
+    for triangle in elements.chunks(3) {
+        if triangle.len() < 3 {
+            break;
+        }
+        let a = &vertices[triangle[0] as usize].position;
+        let b = &vertices[triangle[1] as usize].position;
+        let c = &vertices[triangle[2] as usize].position;
+
+        let edge1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let edge2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let mut p = [0.0; 3];
+        vec_util::vec_cross(&mut p, &ray.direction, &edge2);
+        let det = vec_util::vec_dot(&edge1, &p);
+        if det.abs() < EPSILON {
+            continue;
+        }
+
+        let to_origin = [ray.origin[0] - a[0], ray.origin[1] - a[1], ray.origin[2] - a[2]];
+        let u = vec_util::vec_dot(&to_origin, &p) / det;
+        if u < 0.0 || u > 1.0 {
+            continue;
+        }
+
+        let mut q = [0.0; 3];
+        vec_util::vec_cross(&mut q, &to_origin, &edge1);
+        let v = vec_util::vec_dot(&ray.direction, &q) / det;
+        if v < 0.0 || u + v > 1.0 {
+            continue;
+        }
+
+        let distance = vec_util::vec_dot(&edge2, &q) / det;
+        if distance <= 0.0 || nearest.as_ref().map_or(false, |hit| hit.distance <= distance) {
+            continue;
+        }
+
+        nearest = Some(Hit {
+            distance: distance,
+            indices: [triangle[0], triangle[1], triangle[2]],
+            barycentric: [1.0 - u - v, u, v],
+        });
+    }
+
+    nearest
+}
@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::Write;
+use std::io;
+
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const DEFLATE_MAX_STORED: usize = 65535;
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b): (u32, u32) = (1, 0);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn push_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    push_u32(out, data.len() as u32);
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    push_u32(out, crc);
+}
+
+// Wraps the data in a zlib stream of uncompressed deflate blocks, which are
+// quick to write and need no compressor, at the cost of larger files.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let block_count = data.len() / DEFLATE_MAX_STORED + 1;
+    let mut out = Vec::with_capacity(data.len() + 5 * block_count + 6);
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(DEFLATE_MAX_STORED).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = if blocks.peek().is_none() { 1 } else { 0 };
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+
+    push_u32(&mut out, adler32(data));
+    out
+}
+
+// Encodes 8-bit RGB pixels, given top row first.
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let stride = 3 * width;
+    let mut scanlines = Vec::with_capacity((stride + 1) * height);
+    for row in rgb.chunks(stride).take(height) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    push_u32(&mut header, width as u32);
+    push_u32(&mut header, height as u32);
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&PNG_SIGNATURE);
+    push_chunk(&mut out, b"IHDR", &header);
+    push_chunk(&mut out, b"IDAT", &zlib_stored(&scanlines));
+    push_chunk(&mut out, b"IEND", &[]);
+
+    out
+}
+
+pub fn write_png(filename: &str, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    let mut file = try!(File::create(filename));
+    try!(file.write_all(&encode_png(width, height, rgb)));
+
+    Ok(())
+}
+
+// Converts the BGR, bottom-row-first pixels of a TGA texture into top-row-first
+// RGB for encoding.
+pub fn rgb_from_tga(pixels: &[[u8; 3]], width: usize, height: usize) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(3 * width * height);
+    for row in (0..height).rev() {
+        for pixel in &pixels[row * width..(row + 1) * width] {
+            rgb.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+    }
+
+    rgb
+}
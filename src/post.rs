@@ -0,0 +1,667 @@
+use atlas::Image;
+use file_util;
+use file_util::Setting;
+use gl;
+use gl::types::*;
+use gl_util;
+use std::ffi::CString;
+use std::mem;
+use std::os::raw;
+use std::ptr;
+
+
+pub const CONFIG_FILE: &str = "post.cfg";
+
+// Halvings of the bloom chain below the scene's size.
+const BLOOM_LEVELS: usize = 5;
+const MSAA_SAMPLE_COUNTS: [GLint; 4] = [0, 2, 4, 8];
+// Colour grading LUTs are strips of this many slices of blue, each one this
+// many texels square.
+const GRADING_LUT_SIZE: i32 = 16;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ToneMapping {
+    // Clamps, as drawing straight to the window does.
+    Clamp = 0,
+    Reinhard = 1,
+    // Narkowicz's fit of the ACES filmic curve.
+    Aces = 2,
+}
+
+impl ToneMapping {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ToneMapping::Clamp => "clamp",
+            ToneMapping::Reinhard => "reinhard",
+            ToneMapping::Aces => "aces",
+        }
+    }
+
+    fn next(&self) -> ToneMapping {
+        match *self {
+            ToneMapping::Clamp => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::Clamp,
+        }
+    }
+}
+
+pub struct PostOptions {
+    pub enabled: bool,
+    // Zero for none.
+    pub msaa_samples: GLint,
+    pub tone_mapping: ToneMapping,
+    pub exposure: GLfloat,
+    pub bloom: bool,
+    // Linear brightness above which things start to glow.
+    pub bloom_threshold: GLfloat,
+    pub bloom_strength: GLfloat,
+    pub fxaa: bool,
+    pub vignette: bool,
+    pub vignette_strength: GLfloat,
+    pub grading: bool,
+    pub grading_lut: String,
+}
+
+impl PostOptions {
+    pub fn default() -> PostOptions {
+        PostOptions {
+            enabled: true,
+            msaa_samples: 4,
+            tone_mapping: ToneMapping::Aces,
+            exposure: 1.0,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_strength: 0.05,
+            fxaa: false,
+            vignette: false,
+            vignette_strength: 0.4,
+            grading: false,
+            grading_lut: String::from("assets/grading.tga"),
+        }
+    }
+
+    // Settings left out keep their defaults.
+    pub fn parse(settings: &[Setting]) -> Result<PostOptions, String> {
+        let mut options = PostOptions::default();
+
+        for setting in settings {
+            match setting.key.as_str() {
+                "enabled" => options.enabled = try!(setting.switch()),
+                "msaa" => options.msaa_samples = try!(setting.number()),
+                "tone_mapping" => options.tone_mapping = match setting.value.as_str() {
+                    "clamp" => ToneMapping::Clamp,
+                    "reinhard" => ToneMapping::Reinhard,
+                    "aces" => ToneMapping::Aces,
+                    _ => return Err(setting.invalid()),
+                },
+                "exposure" => options.exposure = try!(setting.number()),
+                "bloom" => options.bloom = try!(setting.switch()),
+                "bloom_threshold" => options.bloom_threshold = try!(setting.number()),
+                "bloom_strength" => options.bloom_strength = try!(setting.number()),
+                "fxaa" => options.fxaa = try!(setting.switch()),
+                "vignette" => options.vignette = try!(setting.switch()),
+                "vignette_strength" => options.vignette_strength = try!(setting.number()),
+                "grading" => options.grading = try!(setting.switch()),
+                "grading_lut" => options.grading_lut = setting.value.clone(),
+                _ => return Err(setting.unknown()),
+            }
+        }
+
+        if !MSAA_SAMPLE_COUNTS.contains(&options.msaa_samples) {
+            return Err(String::from("msaa must be 0, 2, 4 or 8"));
+        }
+        if !(options.exposure > 0.0) {
+            return Err(String::from("the exposure must be positive"));
+        }
+
+        Ok(options)
+    }
+
+    pub fn load(filename: &str) -> Result<PostOptions, String> {
+        let settings = try!(file_util::read_settings(filename));
+        PostOptions::parse(&settings)
+    }
+}
+
+// A texture with a framebuffer drawing into it.
+struct Target {
+    framebuffer: GLuint,
+    texture: GLuint,
+    // Only the scene's own target has one.
+    depth_buffer: GLuint,
+    width: GLsizei,
+    height: GLsizei,
+}
+
+impl Target {
+    fn new(internal_format: GLenum, width: GLsizei, height: GLsizei, with_depth: bool) -> Target {
+        let mut target = Target { framebuffer: 0, texture: 0, depth_buffer: 0, width: width, height: height };
+        unsafe {
+            gl::GenTextures(1, &mut target.texture);
+            gl::BindTexture(gl::TEXTURE_2D, target.texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0, internal_format as GLint, width, height, 0, gl::RGBA, gl::FLOAT, ptr::null()
+            );
+
+            gl::GenFramebuffers(1, &mut target.framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, target.texture, 0
+            );
+            if with_depth {
+                gl::GenRenderbuffers(1, &mut target.depth_buffer);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, target.depth_buffer);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, width, height);
+                gl::FramebufferRenderbuffer(
+                    gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, target.depth_buffer
+                );
+            }
+        }
+
+        target
+    }
+
+    fn delete(&self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+            if self.depth_buffer != 0 {
+                gl::DeleteRenderbuffers(1, &self.depth_buffer);
+            }
+        }
+    }
+}
+
+// Multisampled colour and depth the scene is drawn into, resolved into the
+// scene target before the post passes read it.
+struct MultisampleTarget {
+    framebuffer: GLuint,
+    color_buffer: GLuint,
+    depth_buffer: GLuint,
+}
+
+impl MultisampleTarget {
+    fn new(samples: GLint, width: GLsizei, height: GLsizei) -> MultisampleTarget {
+        let mut target = MultisampleTarget { framebuffer: 0, color_buffer: 0, depth_buffer: 0 };
+        unsafe {
+            gl::GenFramebuffers(1, &mut target.framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer);
+
+            gl::GenRenderbuffers(1, &mut target.color_buffer);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, target.color_buffer);
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples, gl::RGBA16F, width, height);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, target.color_buffer
+            );
+
+            gl::GenRenderbuffers(1, &mut target.depth_buffer);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, target.depth_buffer);
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples, gl::DEPTH_COMPONENT24, width, height);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, target.depth_buffer
+            );
+        }
+
+        target
+    }
+
+    fn delete(&self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteRenderbuffers(1, &self.color_buffer);
+            gl::DeleteRenderbuffers(1, &self.depth_buffer);
+        }
+    }
+}
+
+// The render targets, remade whenever the size or sample count changes.
+struct Targets {
+    width: GLsizei,
+    height: GLsizei,
+    samples: GLint,
+    multisample: Option<MultisampleTarget>,
+    scene: Target,
+    bloom: Vec<Target>,
+    // Tone mapped, waiting on FXAA.
+    display: Target,
+}
+
+impl Targets {
+    fn new(width: GLsizei, height: GLsizei, samples: GLint) -> Option<Targets> {
+        let targets = Targets {
+            width: width,
+            height: height,
+            samples: samples,
+            multisample: if samples > 0 { Some(MultisampleTarget::new(samples, width, height)) } else { None },
+            scene: Target::new(gl::RGBA16F, width, height, true),
+            bloom: (0..BLOOM_LEVELS).map(|level| {
+                Target::new(gl::RGBA16F, (width >> (level + 1)).max(1), (height >> (level + 1)).max(1), false)
+            }).collect(),
+            display: Target::new(gl::RGBA8, width, height, false),
+        };
+
+        let framebuffers = targets.multisample.iter().map(|target| target.framebuffer)
+            .chain(Some(targets.scene.framebuffer))
+            .chain(targets.bloom.iter().map(|target| target.framebuffer))
+            .chain(Some(targets.display.framebuffer));
+        for framebuffer in framebuffers {
+            let status = unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+                gl::CheckFramebufferStatus(gl::FRAMEBUFFER)
+            };
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                eprintln!("Post-processing framebuffer incomplete: 0x{:X}", status);
+                targets.delete();
+                return None;
+            }
+        }
+
+        Some(targets)
+    }
+
+    fn delete(&self) {
+        if let Some(ref multisample) = self.multisample {
+            multisample.delete();
+        }
+        self.scene.delete();
+        for target in self.bloom.iter() {
+            target.delete();
+        }
+        self.display.delete();
+    }
+}
+
+// A full-screen pass. Every pass reads one texture and knows its texel size.
+struct Pass {
+    program: GLuint,
+    corner: GLint,
+    source: GLint,
+    texel_size: GLint,
+}
+
+impl Pass {
+    fn new() -> Pass {
+        Pass { program: 0, corner: 0, source: 0, texel_size: 0 }
+    }
+
+    fn uniform(&self, name: &str) -> GLint {
+        let name = CString::new(name).unwrap();
+        unsafe { gl::GetUniformLocation(self.program, name.as_ptr()) }
+    }
+
+    // Returns false if the shaders don't compile or link.
+    fn init(&mut self, fragment_shader_path: &str) -> bool {
+        let vertex_shader = gl_util::make_shader(gl::VERTEX_SHADER, "shaders/post.v.glsl");
+        let fragment_shader = gl_util::make_shader(gl::FRAGMENT_SHADER, fragment_shader_path);
+        if vertex_shader == 0 || fragment_shader == 0 {
+            return false;
+        }
+        self.program = gl_util::make_program(vertex_shader, fragment_shader);
+        if self.program == 0 {
+            return false;
+        }
+
+        self.source = self.uniform("source");
+        self.texel_size = self.uniform("texel_size");
+        let corner_cstr = CString::new("corner").unwrap();
+        self.corner = unsafe { gl::GetAttribLocation(self.program, corner_cstr.as_ptr()) };
+
+        true
+    }
+
+    // Draws into `framebuffer` from `source`, whose size is given. Extra
+    // uniforms are set in between by `setup`, with the program in use.
+    fn draw<F: FnOnce()>(
+        &self, vertex_buffer: GLuint, framebuffer: GLuint, (width, height): (GLsizei, GLsizei),
+        source: &Target, setup: F
+    ) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::Viewport(0, 0, width, height);
+            gl::UseProgram(self.program);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, source.texture);
+            gl::Uniform1i(self.source, 0);
+            gl::Uniform2f(self.texel_size, 1.0 / source.width as GLfloat, 1.0 / source.height as GLfloat);
+            setup();
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer);
+            gl::EnableVertexAttribArray(self.corner as GLuint);
+            gl::VertexAttribPointer(
+                self.corner as GLuint, 2, gl::FLOAT, gl::FALSE,
+                mem::size_of::<[GLfloat; 2]>() as GLint, ptr::null()
+            );
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::DisableVertexAttribArray(self.corner as GLuint);
+        }
+    }
+}
+
+struct CompositeUniforms {
+    bloom: GLint,
+    bloom_strength: GLint,
+    exposure: GLint,
+    tone_mapping: GLint,
+    grading_lut: GLint,
+    grading: GLint,
+    vignette_strength: GLint,
+}
+
+// Loads a strip of GRADING_LUT_SIZE slices side by side, blue increasing
+// along it, red across each slice and green down it from the top, as the
+// usual neutral strips are laid out. Without one, grading does nothing.
+fn make_grading_lut(filename: &str) -> GLuint {
+    let size = GRADING_LUT_SIZE;
+    let texels: Vec<[u8; 3]> = match Image::load(filename) {
+        Ok(ref image) if image.width == size * size && image.height == size => {
+            let mut texels = Vec::with_capacity((size * size * size) as usize);
+            for b in 0..size {
+                for g in 0..size {
+                    let row = image.height - 1 - g;
+                    for r in 0..size {
+                        texels.push(image.pixels[(row * image.width + b * size + r) as usize]);
+                    }
+                }
+            }
+            texels
+        }
+        result => {
+            if let Ok(image) = result {
+                eprintln!(
+                    "{} is {} x {}, not a {} x {} grading strip",
+                    filename, image.width, image.height, size * size, size
+                );
+            }
+            let level = |c: i32| (255 * c / (size - 1)) as u8;
+            let mut texels = Vec::with_capacity((size * size * size) as usize);
+            for b in 0..size {
+                for g in 0..size {
+                    for r in 0..size {
+                        texels.push([level(b), level(g), level(r)]);
+                    }
+                }
+            }
+            texels
+        }
+    };
+
+    let mut texture = 0;
+    unsafe {
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_3D, texture);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexImage3D(
+            gl::TEXTURE_3D, 0, gl::RGB8 as GLint, size, size, size, 0,
+            gl::BGR, gl::UNSIGNED_BYTE, texels.as_ptr() as *const raw::c_void
+        );
+    }
+
+    texture
+}
+
+// Draws the scene into a floating point target, then on to the output
+// through bloom, tone mapping, colour grading, a vignette and FXAA. The
+// scene shaders gamma encode for the screen but nothing clamps here, so
+// decoding gets back linear light with highlights above 1 intact.
+pub struct PostProcess {
+    pub options: PostOptions,
+    max_samples: GLint,
+    targets: Option<Targets>,
+    vertex_buffer: GLuint,
+    bloom_downsample: Pass,
+    bloom_threshold: GLint,
+    bloom_first: GLint,
+    bloom_upsample: Pass,
+    composite: Pass,
+    composite_uniforms: CompositeUniforms,
+    fxaa: Pass,
+    grading_lut: GLuint,
+    // Whether the frame under way is going to the scene target.
+    active: bool,
+}
+
+impl PostProcess {
+    pub fn new(options: PostOptions) -> PostProcess {
+        PostProcess {
+            options: options,
+            max_samples: 0,
+            targets: None,
+            vertex_buffer: 0,
+            bloom_downsample: Pass::new(),
+            bloom_threshold: 0,
+            bloom_first: 0,
+            bloom_upsample: Pass::new(),
+            composite: Pass::new(),
+            composite_uniforms: CompositeUniforms {
+                bloom: 0,
+                bloom_strength: 0,
+                exposure: 0,
+                tone_mapping: 0,
+                grading_lut: 0,
+                grading: 0,
+                vignette_strength: 0,
+            },
+            fxaa: Pass::new(),
+            grading_lut: 0,
+            active: false,
+        }
+    }
+
+    // Returns false, leaving drawing straight to the output, if the passes'
+    // shaders can't be loaded.
+    pub fn init(&mut self) -> bool {
+        if !(self.bloom_downsample.init("shaders/bloom_downsample.f.glsl")
+            && self.bloom_upsample.init("shaders/bloom_upsample.f.glsl")
+            && self.composite.init("shaders/composite.f.glsl")
+            && self.fxaa.init("shaders/fxaa.f.glsl")) {
+            self.options.enabled = false;
+            return false;
+        }
+
+        self.bloom_threshold = self.bloom_downsample.uniform("threshold");
+        self.bloom_first = self.bloom_downsample.uniform("first");
+        self.composite_uniforms = CompositeUniforms {
+            bloom: self.composite.uniform("bloom"),
+            bloom_strength: self.composite.uniform("bloom_strength"),
+            exposure: self.composite.uniform("exposure"),
+            tone_mapping: self.composite.uniform("tone_mapping"),
+            grading_lut: self.composite.uniform("grading_lut"),
+            grading: self.composite.uniform("grading"),
+            vignette_strength: self.composite.uniform("vignette_strength"),
+        };
+        self.grading_lut = make_grading_lut(&self.options.grading_lut);
+
+        // One triangle over the whole screen.
+        let corners: [GLfloat; 6] = [-1.0, -1.0, 3.0, -1.0, -1.0, 3.0];
+        unsafe {
+            gl::GetIntegerv(gl::MAX_SAMPLES, &mut self.max_samples);
+            gl::GenBuffers(1, &mut self.vertex_buffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(&corners) as GLsizeiptr,
+                corners.as_ptr() as *const raw::c_void,
+                gl::STATIC_DRAW
+            );
+        }
+        self.options.msaa_samples = self.options.msaa_samples.min(self.max_samples);
+
+        true
+    }
+
+    pub fn toggle(&mut self) {
+        self.options.enabled = !self.options.enabled && self.vertex_buffer != 0;
+        println!("Post-processing {}", if self.options.enabled { "on" } else { "off" });
+    }
+
+    pub fn cycle_msaa(&mut self) {
+        let k = MSAA_SAMPLE_COUNTS.iter().position(|&n| n == self.options.msaa_samples).unwrap_or(0);
+        let next = MSAA_SAMPLE_COUNTS[(k + 1) % MSAA_SAMPLE_COUNTS.len()];
+        self.options.msaa_samples = if next > self.max_samples { 0 } else { next };
+        println!("MSAA {}", match self.options.msaa_samples {
+            0 => String::from("off"),
+            samples => format!("{}x", samples),
+        });
+    }
+
+    pub fn cycle_tone_mapping(&mut self) {
+        self.options.tone_mapping = self.options.tone_mapping.next();
+        println!("Tone mapping by {}", self.options.tone_mapping.name());
+    }
+
+    pub fn toggle_bloom(&mut self) {
+        self.options.bloom = !self.options.bloom;
+        println!("Bloom {}", if self.options.bloom { "on" } else { "off" });
+    }
+
+    pub fn toggle_fxaa(&mut self) {
+        self.options.fxaa = !self.options.fxaa;
+        println!("FXAA {}", if self.options.fxaa { "on" } else { "off" });
+    }
+
+    pub fn toggle_vignette(&mut self) {
+        self.options.vignette = !self.options.vignette;
+        println!("Vignette {}", if self.options.vignette { "on" } else { "off" });
+    }
+
+    pub fn toggle_grading(&mut self) {
+        self.options.grading = !self.options.grading;
+        println!("Colour grading {}", if self.options.grading { "on" } else { "off" });
+    }
+
+    // Call before drawing the scene. Points drawing at the scene target when
+    // post-processing is on, (re)making the targets to fit first.
+    pub fn begin(&mut self, width: GLsizei, height: GLsizei) {
+        self.active = false;
+        if !self.options.enabled || width <= 0 || height <= 0 {
+            return;
+        }
+
+        let stale = match self.targets {
+            Some(ref targets) => {
+                targets.width != width || targets.height != height || targets.samples != self.options.msaa_samples
+            }
+            None => true,
+        };
+        if stale {
+            if let Some(ref targets) = self.targets {
+                targets.delete();
+            }
+            self.targets = Targets::new(width, height, self.options.msaa_samples);
+            if self.targets.is_none() {
+                eprintln!("Post-processing unavailable at {} x {}", width, height);
+                self.options.enabled = false;
+                return;
+            }
+        }
+
+        let targets = self.targets.as_ref().unwrap();
+        let framebuffer = match targets.multisample {
+            Some(ref multisample) => multisample.framebuffer,
+            None => targets.scene.framebuffer,
+        };
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::Viewport(0, 0, width, height);
+        }
+        self.active = true;
+    }
+
+    // Call after drawing the scene. Runs the passes into `output` and leaves
+    // it bound.
+    pub fn end(&self, output: GLuint) {
+        if !self.active {
+            return;
+        }
+
+        let targets = self.targets.as_ref().unwrap();
+        let size = (targets.width, targets.height);
+        unsafe {
+            if let Some(ref multisample) = targets.multisample {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, multisample.framebuffer);
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, targets.scene.framebuffer);
+                gl::BlitFramebuffer(
+                    0, 0, size.0, size.1, 0, 0, size.0, size.1, gl::COLOR_BUFFER_BIT, gl::NEAREST
+                );
+            }
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+        }
+
+        if self.options.bloom {
+            self.render_bloom(targets);
+        }
+
+        let options = &self.options;
+        let composite_output = if options.fxaa { targets.display.framebuffer } else { output };
+        let uniforms = &self.composite_uniforms;
+        let grading_lut = self.grading_lut;
+        let bloom_texture = targets.bloom[0].texture;
+        self.composite.draw(self.vertex_buffer, composite_output, size, &targets.scene, || unsafe {
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, bloom_texture);
+            gl::Uniform1i(uniforms.bloom, 1);
+            gl::ActiveTexture(gl::TEXTURE2);
+            gl::BindTexture(gl::TEXTURE_3D, grading_lut);
+            gl::Uniform1i(uniforms.grading_lut, 2);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::Uniform1f(uniforms.bloom_strength, if options.bloom { options.bloom_strength } else { 0.0 });
+            gl::Uniform1f(uniforms.exposure, options.exposure);
+            gl::Uniform1i(uniforms.tone_mapping, options.tone_mapping as GLint);
+            gl::Uniform1i(uniforms.grading, options.grading as GLint);
+            gl::Uniform1f(uniforms.vignette_strength, if options.vignette { options.vignette_strength } else { 0.0 });
+        });
+
+        if options.fxaa {
+            self.fxaa.draw(self.vertex_buffer, output, size, &targets.display, || {});
+        }
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, output);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CULL_FACE);
+        }
+    }
+
+    // Thresholds the scene down the chain of halvings, then adds each level
+    // back into the one above it on the way up, leaving the glow in the
+    // first level.
+    fn render_bloom(&self, targets: &Targets) {
+        let threshold = self.bloom_threshold;
+        let first = self.bloom_first;
+        let bloom_threshold = self.options.bloom_threshold;
+        for level in 0..targets.bloom.len() {
+            let source = if level == 0 { &targets.scene } else { &targets.bloom[level - 1] };
+            let target = &targets.bloom[level];
+            self.bloom_downsample.draw(
+                self.vertex_buffer, target.framebuffer, (target.width, target.height), source, || unsafe {
+                    gl::Uniform1f(threshold, bloom_threshold);
+                    gl::Uniform1i(first, (level == 0) as GLint);
+                }
+            );
+        }
+
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE);
+        }
+        for level in (1..targets.bloom.len()).rev() {
+            let target = &targets.bloom[level - 1];
+            self.bloom_upsample.draw(
+                self.vertex_buffer, target.framebuffer, (target.width, target.height), &targets.bloom[level], || {}
+            );
+        }
+        unsafe {
+            gl::Disable(gl::BLEND);
+        }
+    }
+}
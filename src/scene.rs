@@ -0,0 +1,160 @@
+use file_util;
+use file_util::Setting;
+use gl;
+use gl::types::*;
+use std::ffi::CString;
+
+
+pub const SCENE_FILE: &str = "scene.cfg";
+// The fog code the scene's fragment shaders are compiled with.
+pub const FOG_SHADER: &str = "shaders/fog.glsl";
+
+// How the air between the eye and the scene looks, read from the scene file
+// and handed to the scene shaders as uniforms. Distances are in scene units
+// and colours are linear.
+pub struct SceneOptions {
+    pub fog: bool,
+    // Extinction per unit of distance everywhere.
+    pub fog_density: GLfloat,
+    // Extra extinction at fog_base_height, thinning out by a factor of e
+    // every 1 / fog_height_falloff above it.
+    pub fog_height_density: GLfloat,
+    pub fog_height_falloff: GLfloat,
+    pub fog_base_height: GLfloat,
+    pub fog_color: [GLfloat; 3],
+    // How much of the fog's colour comes from the sky behind it rather than
+    // fog_color, so distant surfaces fade into the horizon.
+    pub fog_sky: GLfloat,
+    // Blue light scattering out sooner than red, and light from the sun
+    // scattering toward the eye when looking its way.
+    pub scattering: bool,
+    pub sun_color: [GLfloat; 3],
+    // From -1 for scattering back toward the sun, through 0 for all ways
+    // alike, to 1 for straight on.
+    pub scattering_anisotropy: GLfloat,
+}
+
+impl SceneOptions {
+    pub fn default() -> SceneOptions {
+        SceneOptions {
+            fog: true,
+            fog_density: 0.04,
+            fog_height_density: 0.25,
+            fog_height_falloff: 1.5,
+            // The ground.
+            fog_base_height: -1.0,
+            fog_color: [0.5, 0.6, 0.7],
+            fog_sky: 1.0,
+            scattering: false,
+            sun_color: [1.0, 0.9, 0.75],
+            scattering_anisotropy: 0.7,
+        }
+    }
+
+    // Settings left out keep their defaults.
+    pub fn parse(settings: &[Setting]) -> Result<SceneOptions, String> {
+        let mut options = SceneOptions::default();
+
+        for setting in settings {
+            match setting.key.as_str() {
+                "fog" => options.fog = try!(setting.switch()),
+                "fog_density" => options.fog_density = try!(setting.number()),
+                "fog_height_density" => options.fog_height_density = try!(setting.number()),
+                "fog_height_falloff" => options.fog_height_falloff = try!(setting.number()),
+                "fog_base_height" => options.fog_base_height = try!(setting.number()),
+                "fog_color" => options.fog_color = try!(setting.color()),
+                "fog_sky" => options.fog_sky = try!(setting.number()),
+                "scattering" => options.scattering = try!(setting.switch()),
+                "sun_color" => options.sun_color = try!(setting.color()),
+                "scattering_anisotropy" => options.scattering_anisotropy = try!(setting.number()),
+                _ => return Err(setting.unknown()),
+            }
+        }
+
+        if options.fog_density < 0.0 || options.fog_height_density < 0.0 || options.fog_height_falloff < 0.0 {
+            return Err(String::from("fog densities and falloff can't be negative"));
+        }
+        if !(options.fog_sky >= 0.0 && options.fog_sky <= 1.0) {
+            return Err(String::from("fog_sky must be between 0 and 1"));
+        }
+        if !(options.scattering_anisotropy > -1.0 && options.scattering_anisotropy < 1.0) {
+            return Err(String::from("scattering_anisotropy must be between -1 and 1"));
+        }
+
+        Ok(options)
+    }
+
+    pub fn load(filename: &str) -> Result<SceneOptions, String> {
+        let settings = try!(file_util::read_settings(filename));
+        SceneOptions::parse(&settings)
+    }
+}
+
+// Where a program has the fog uniforms that shaders/fog.glsl declares, for
+// every shader compiled with it.
+#[derive(Copy, Clone)]
+pub struct FogUniforms {
+    fog: GLint,
+    scattering: GLint,
+    fog_density: GLint,
+    fog_height_density: GLint,
+    fog_height_falloff: GLint,
+    fog_base_height: GLint,
+    fog_color: GLint,
+    fog_sky: GLint,
+    sun_color: GLint,
+    scattering_anisotropy: GLint,
+}
+
+impl FogUniforms {
+    pub fn new() -> FogUniforms {
+        FogUniforms {
+            fog: 0,
+            scattering: 0,
+            fog_density: 0,
+            fog_height_density: 0,
+            fog_height_falloff: 0,
+            fog_base_height: 0,
+            fog_color: 0,
+            fog_sky: 0,
+            sun_color: 0,
+            scattering_anisotropy: 0,
+        }
+    }
+
+    pub fn locate(program: GLuint) -> FogUniforms {
+        let uniform = |name: &str| {
+            let name = CString::new(name).unwrap();
+            unsafe { gl::GetUniformLocation(program, name.as_ptr()) }
+        };
+
+        FogUniforms {
+            fog: uniform("fog"),
+            scattering: uniform("scattering"),
+            fog_density: uniform("fog_density"),
+            fog_height_density: uniform("fog_height_density"),
+            fog_height_falloff: uniform("fog_height_falloff"),
+            fog_base_height: uniform("fog_base_height"),
+            fog_color: uniform("fog_color"),
+            fog_sky: uniform("fog_sky"),
+            sun_color: uniform("sun_color"),
+            scattering_anisotropy: uniform("scattering_anisotropy"),
+        }
+    }
+
+    // Sets them from `options` on the program in use.
+    pub fn set(&self, options: &SceneOptions) {
+        unsafe {
+            gl_check!(gl::Uniform1i(self.fog, options.fog as GLint));
+            gl_check!(gl::Uniform1f(self.fog_density, options.fog_density));
+            gl_check!(gl::Uniform1f(self.fog_height_density, options.fog_height_density));
+            gl_check!(gl::Uniform1f(self.fog_height_falloff, options.fog_height_falloff));
+            gl_check!(gl::Uniform1f(self.fog_base_height, options.fog_base_height));
+            gl_check!(gl::Uniform3f(self.fog_color, options.fog_color[0], options.fog_color[1], options.fog_color[2]));
+            gl_check!(gl::Uniform1f(self.fog_sky, options.fog_sky));
+            gl_check!(gl::Uniform1i(self.scattering, options.scattering as GLint));
+            gl_check!(gl::Uniform3f(self.sun_color, options.sun_color[0], options.sun_color[1], options.sun_color[2]));
+            gl_check!(gl::Uniform1f(self.scattering_anisotropy, options.scattering_anisotropy));
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::f32;
+use std::ops::{Add, Div, Mul, Sub};
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+
+// Four floats worked on at once. SSE2 is always there on x86_64, and other
+// targets get plain arrays the compiler can vectorize as it sees fit.
+#[cfg(target_arch = "x86_64")]
+#[derive(Copy, Clone, Debug)]
+pub struct F32x4(__m128);
+
+#[cfg(not(target_arch = "x86_64"))]
+#[derive(Copy, Clone, Debug)]
+pub struct F32x4([f32; 4]);
+
+#[cfg(target_arch = "x86_64")]
+impl F32x4 {
+    pub fn splat(x: f32) -> F32x4 {
+        unsafe { F32x4(_mm_set1_ps(x)) }
+    }
+
+    pub fn new(lanes: [f32; 4]) -> F32x4 {
+        unsafe { F32x4(_mm_loadu_ps(lanes.as_ptr())) }
+    }
+
+    pub fn lanes(self) -> [f32; 4] {
+        let mut out = [0.0; 4];
+        unsafe { _mm_storeu_ps(out.as_mut_ptr(), self.0) };
+        out
+    }
+
+    pub fn min(self, other: F32x4) -> F32x4 {
+        unsafe { F32x4(_mm_min_ps(self.0, other.0)) }
+    }
+
+    pub fn max(self, other: F32x4) -> F32x4 {
+        unsafe { F32x4(_mm_max_ps(self.0, other.0)) }
+    }
+
+    pub fn sqrt(self) -> F32x4 {
+        unsafe { F32x4(_mm_sqrt_ps(self.0)) }
+    }
+
+    pub fn round(self) -> F32x4 {
+        unsafe { F32x4(_mm_cvtepi32_ps(_mm_cvtps_epi32(self.0))) }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+impl F32x4 {
+    pub fn splat(x: f32) -> F32x4 {
+        F32x4([x; 4])
+    }
+
+    pub fn new(lanes: [f32; 4]) -> F32x4 {
+        F32x4(lanes)
+    }
+
+    pub fn lanes(self) -> [f32; 4] {
+        self.0
+    }
+
+    fn map(self, f: fn(f32) -> f32) -> F32x4 {
+        F32x4([f(self.0[0]), f(self.0[1]), f(self.0[2]), f(self.0[3])])
+    }
+
+    fn zip(self, other: F32x4, f: fn(f32, f32) -> f32) -> F32x4 {
+        let (a, b) = (self.0, other.0);
+        F32x4([f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2]), f(a[3], b[3])])
+    }
+
+    pub fn min(self, other: F32x4) -> F32x4 {
+        self.zip(other, f32::min)
+    }
+
+    pub fn max(self, other: F32x4) -> F32x4 {
+        self.zip(other, f32::max)
+    }
+
+    pub fn sqrt(self) -> F32x4 {
+        self.map(f32::sqrt)
+    }
+
+    pub fn round(self) -> F32x4 {
+        self.map(f32::round)
+    }
+}
+
+macro_rules! binary_op {
+    ($trait_name:ident, $method:ident, $intrinsic:ident, $op:tt) => {
+        impl $trait_name for F32x4 {
+            type Output = F32x4;
+
+            #[cfg(target_arch = "x86_64")]
+            fn $method(self, other: F32x4) -> F32x4 {
+                unsafe { F32x4($intrinsic(self.0, other.0)) }
+            }
+
+            #[cfg(not(target_arch = "x86_64"))]
+            fn $method(self, other: F32x4) -> F32x4 {
+                let (a, b) = (self.0, other.0);
+                F32x4([a[0] $op b[0], a[1] $op b[1], a[2] $op b[2], a[3] $op b[3]])
+            }
+        }
+    }
+}
+
+binary_op!(Add, add, _mm_add_ps, +);
+binary_op!(Sub, sub, _mm_sub_ps, -);
+binary_op!(Mul, mul, _mm_mul_ps, *);
+binary_op!(Div, div, _mm_div_ps, /);
+
+const FRAC_1_2PI: f32 = 0.5 / f32::consts::PI;
+
+// Sine by reducing to [-pi, pi], folding onto [-pi/2, pi/2] and summing the
+// Taylor series to the x^11 term, which is good to about 6e-8 there.
+pub fn sin(x: F32x4) -> F32x4 {
+    let pi = F32x4::splat(f32::consts::PI);
+    let x = x - F32x4::splat(2.0 * f32::consts::PI) * (x * F32x4::splat(FRAC_1_2PI)).round();
+    let x = x.min(pi - x).max(F32x4::splat(0.0) - pi - x);
+
+    let x2 = x * x;
+    let mut sum = F32x4::splat(-1.0 / 39916800.0);
+    sum = sum * x2 + F32x4::splat(1.0 / 362880.0);
+    sum = sum * x2 + F32x4::splat(-1.0 / 5040.0);
+    sum = sum * x2 + F32x4::splat(1.0 / 120.0);
+    sum = sum * x2 + F32x4::splat(-1.0 / 6.0);
+    sum = sum * x2 + F32x4::splat(1.0);
+
+    sum * x
+}
+
+pub fn cos(x: F32x4) -> F32x4 {
+    sin(x + F32x4::splat(f32::consts::FRAC_PI_2))
+}
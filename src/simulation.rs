@@ -0,0 +1,335 @@
+use cloth::Cloth;
+use flag_update;
+use flag_update::FlagUpdater;
+use flagpole::FlagpoleProfile;
+use gl::types::{GLfloat, GLushort};
+use halyard::Halyard;
+use meshes;
+use meshes::FlagVertex;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+pub const DEFAULT_STEP_RATE: f64 = 60.0;
+pub const DEFAULT_MAX_STEPS: u32 = 8;
+
+// States older than the previous one kept for stepping backward. Each holds a
+// copy of the flag mesh, 7500 vertices of 64 bytes or 480KB, and with the
+// cloth on another 180KB of particle positions, so this is about 58MB, or
+// 80MB with the cloth on.
+const HISTORY_LENGTH: usize = 120;
+
+pub const USAGE: &str = "usage: flag [--step-rate HZ] [--max-steps COUNT] [--threads COUNT]";
+
+pub struct SimulationOptions {
+    pub step_rate: f64,
+    pub max_steps: u32,
+    pub threads: usize,
+}
+
+impl SimulationOptions {
+    pub fn default() -> SimulationOptions {
+        SimulationOptions {
+            step_rate: DEFAULT_STEP_RATE,
+            max_steps: DEFAULT_MAX_STEPS,
+            threads: default_threads(),
+        }
+    }
+
+    pub fn parse(args: &[String]) -> Result<SimulationOptions, String> {
+        let mut options = SimulationOptions::default();
+
+        let mut rest = args.iter();
+        while let Some(flag) = rest.next() {
+            let value = match rest.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {}", flag)),
+            };
+            let invalid = || format!("invalid value '{}' for {}", value, flag);
+            match flag.as_str() {
+                "--step-rate" => options.step_rate = try!(value.parse().map_err(|_| invalid())),
+                "--max-steps" => options.max_steps = try!(value.parse().map_err(|_| invalid())),
+                "--threads" => options.threads = try!(value.parse().map_err(|_| invalid())),
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+
+        if !(options.step_rate > 0.0) || options.max_steps == 0 || options.threads == 0 {
+            return Err(String::from("the step rate, step limit and thread count must be positive"));
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// Accumulates frame time and hands it out as whole fixed steps. When a frame
+// takes so long that more than `max_steps` are owed, the excess is dropped
+// rather than letting each frame fall further behind.
+pub struct FixedTimestep {
+    step: f64,
+    max_steps: u32,
+    accumulator: f64,
+    last_time: Option<f64>,
+}
+
+impl FixedTimestep {
+    pub fn new(step_rate: f64, max_steps: u32) -> FixedTimestep {
+        FixedTimestep {
+            step: 1.0 / step_rate,
+            max_steps: max_steps,
+            accumulator: 0.0,
+            last_time: None,
+        }
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    // Returns the number of steps to run for a frame starting at `now`.
+    pub fn advance(&mut self, now: f64) -> u32 {
+        let elapsed = self.last_time.map_or(0.0, |last| f64::max(now - last, 0.0));
+        self.last_time = Some(now);
+        self.accumulator += elapsed;
+
+        let owed = (self.accumulator / self.step).floor();
+        let steps = f64::min(owed, self.max_steps as f64);
+        self.accumulator -= steps * self.step;
+        if owed > steps {
+            self.accumulator %= self.step;
+        }
+
+        steps as u32
+    }
+
+    // How far the current frame lies between the last two steps.
+    pub fn alpha(&self) -> f64 {
+        self.accumulator / self.step
+    }
+
+    // Shows the latest step as is, for when steps are taken by hand.
+    pub fn settle(&mut self) {
+        self.accumulator = self.step;
+    }
+}
+
+#[derive(Clone)]
+pub struct SimulationState {
+    pub time: f64,
+    pub halyard: Halyard,
+    pub flag_vertices: Vec<FlagVertex>,
+    // Only replaced when the cloth tears, so it is shared between states.
+    pub flag_elements: Rc<Vec<GLushort>>,
+    pub rope_vertices: Vec<FlagVertex>,
+    pub cloth: Option<Cloth>,
+}
+
+impl SimulationState {
+    pub fn new(
+        flag_vertices: Vec<FlagVertex>, flag_elements: Vec<GLushort>, rope_vertices: Vec<FlagVertex>
+    ) -> SimulationState {
+        SimulationState {
+            time: 0.0,
+            halyard: Halyard::new(),
+            flag_vertices: flag_vertices,
+            flag_elements: Rc::new(flag_elements),
+            rope_vertices: rope_vertices,
+            cloth: None,
+        }
+    }
+
+    pub fn step(&mut self, dt: f64, updater: &mut FlagUpdater, flagpole: &FlagpoleProfile) {
+        self.time += dt;
+        let time = self.time as GLfloat;
+        self.halyard.update(time);
+
+        let hoist_offset = meshes::halyard_offset(self.halyard.position());
+        match self.cloth {
+            Some(ref mut cloth) => {
+                cloth.step(dt as GLfloat, time, hoist_offset, &mut self.flag_vertices, &mut self.flag_elements);
+            }
+            None => {
+                let (columns, rows) = (meshes::FLAG_X_RES as usize, meshes::FLAG_Y_RES as usize);
+                updater.update(&mut self.flag_vertices, columns, rows, time, hoist_offset);
+            }
+        }
+        meshes::calculate_rope(&mut self.rope_vertices, hoist_offset, flagpole);
+    }
+}
+
+// Runs the scene at a fixed rate independent of the frame rate, keeping the
+// two most recent states so frames can be drawn between them, and a ring
+// buffer of older ones to step back through.
+pub struct Simulation {
+    pub timestep: FixedTimestep,
+    pub previous: SimulationState,
+    pub current: SimulationState,
+    history: VecDeque<SimulationState>,
+    updater: FlagUpdater,
+    // What the rope is strung from and the cloth collides with.
+    flagpole: FlagpoleProfile,
+    // The untorn flag's elements, restored when the cloth is switched off.
+    flag_elements: Rc<Vec<GLushort>>,
+    step_time: Duration,
+}
+
+impl Simulation {
+    pub fn new(state: SimulationState, flagpole: FlagpoleProfile, options: &SimulationOptions) -> Simulation {
+        Simulation {
+            timestep: FixedTimestep::new(options.step_rate, options.max_steps),
+            flagpole: flagpole,
+            flag_elements: state.flag_elements.clone(),
+            updater: FlagUpdater::new(options.threads),
+            previous: state.clone(),
+            current: state,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            step_time: Duration::new(0, 0),
+        }
+    }
+
+    fn step(&mut self) {
+        // Reuse the oldest state's buffers once the history is full.
+        if self.history.len() == HISTORY_LENGTH {
+            let mut oldest = self.history.pop_front().unwrap();
+            oldest.clone_from(&self.previous);
+            self.history.push_back(oldest);
+        } else {
+            self.history.push_back(self.previous.clone());
+        }
+
+        self.previous.clone_from(&self.current);
+        let start = Instant::now();
+        self.current.step(self.timestep.step(), &mut self.updater, &self.flagpole);
+        self.step_time = start.elapsed();
+    }
+
+    // Runs the steps owed for a frame starting at `clock` on the simulation
+    // time line.
+    pub fn advance(&mut self, clock: f64) {
+        for _ in 0..self.timestep.advance(clock) {
+            self.step();
+        }
+    }
+
+    pub fn step_forward(&mut self) {
+        self.step();
+        self.timestep.settle();
+    }
+
+    // Returns false once the history runs out.
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(older) => {
+                let previous = mem::replace(&mut self.previous, older);
+                self.current = previous;
+                self.timestep.settle();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Switches between the scripted wave and the cloth solver, which starts
+    // from the flag's current shape. Switching off mends any tears. Returns
+    // whether the cloth is now on.
+    pub fn toggle_cloth(&mut self) -> bool {
+        let columns = meshes::FLAG_X_RES as usize;
+        let rows = meshes::FLAG_Y_RES as usize;
+        if self.current.cloth.take().is_some() {
+            self.current.flag_vertices.truncate(columns * rows);
+            self.current.flag_elements = self.flag_elements.clone();
+            return false;
+        }
+
+        let rest = meshes::flag_rest_positions();
+        let colliders = meshes::scene_colliders(&self.flagpole);
+        let hoist_offset = meshes::halyard_offset(self.current.halyard.position());
+        self.current.cloth = Some(Cloth::new(
+            columns, rows, &rest, &self.current.flag_vertices, hoist_offset, colliders
+        ));
+        true
+    }
+
+    pub fn cloth_enabled(&self) -> bool {
+        self.current.cloth.is_some()
+    }
+
+    // Storms blow hard enough to tear the flag, so they need the cloth on.
+    // Returns whether the storm is now blowing.
+    pub fn toggle_storm(&mut self) -> bool {
+        if !self.cloth_enabled() {
+            self.toggle_cloth();
+        }
+
+        match self.current.cloth {
+            Some(ref mut cloth) => cloth.toggle_storm(),
+            None => false,
+        }
+    }
+
+    // Pins a flag vertex to `target` until released.
+    pub fn grab(&mut self, vertex: usize, target: [GLfloat; 3]) {
+        if let Some(ref mut cloth) = self.current.cloth {
+            cloth.grab(vertex, target);
+        }
+    }
+
+    pub fn move_grab(&mut self, target: [GLfloat; 3]) {
+        if let Some(ref mut cloth) = self.current.cloth {
+            cloth.move_grab(target);
+        }
+    }
+
+    pub fn release(&mut self) {
+        if let Some(ref mut cloth) = self.current.cloth {
+            cloth.release();
+        }
+    }
+
+    // How long the latest step took, not counting keeping the history.
+    pub fn step_time(&self) -> Duration {
+        self.step_time
+    }
+
+    // The cloth's wind at the middle of the flag, or None while the flag
+    // follows the scripted wave instead.
+    pub fn wind(&self) -> Option<[GLfloat; 3]> {
+        let (columns, rows) = (meshes::FLAG_X_RES as usize, meshes::FLAG_Y_RES as usize);
+        let middle = (rows / 2) * columns + columns / 2;
+        let position = self.current.flag_vertices.get(middle).map_or([0.0; 3], |v| {
+            [v.position[0], v.position[1], v.position[2]]
+        });
+        self.current.cloth.as_ref().map(|cloth| cloth.wind(self.current.time as GLfloat, &position))
+    }
+
+    pub fn wave_amplitude(&self) -> GLfloat {
+        flag_update::amplitude(self.current.time as GLfloat)
+    }
+
+    // Scene time between the last two steps, matching what interpolate
+    // blends to.
+    pub fn time(&self) -> f64 {
+        let alpha = self.timestep.alpha();
+        self.previous.time + (self.current.time - self.previous.time) * alpha
+    }
+
+    pub fn interpolate(&self, flag_out: &mut Vec<FlagVertex>, rope_out: &mut [FlagVertex]) {
+        let alpha = self.timestep.alpha() as GLfloat;
+        let (previous, current) = (&self.previous.flag_vertices, &self.current.flag_vertices);
+        flag_out.resize(current.len(), FlagVertex::zero());
+        meshes::interpolate_vertices(flag_out, previous, current, alpha);
+        // Vertices split off by a tear in the last step have nothing to blend
+        // from.
+        if previous.len() < current.len() {
+            flag_out[previous.len()..].copy_from_slice(&current[previous.len()..]);
+        }
+        meshes::interpolate_vertices(rope_out, &self.previous.rope_vertices, &self.current.rope_vertices, alpha);
+    }
+}
@@ -0,0 +1,70 @@
+// Speeds the time scale steps through, from slow motion to fast forward.
+const TIME_SCALES: [f64; 7] = [0.1, 0.25, 0.5, 1.0, 2.0, 3.0, 4.0];
+const NORMAL_SPEED: usize = 3;
+
+// Owns the simulation clock, which runs from frame times scaled by the
+// current speed and stands still while paused.
+pub struct TimeControl {
+    paused: bool,
+    scale: usize,
+    clock: f64,
+    last_frame: Option<f64>,
+}
+
+impl TimeControl {
+    pub fn new() -> TimeControl {
+        TimeControl {
+            paused: false,
+            scale: NORMAL_SPEED,
+            clock: 0.0,
+            last_frame: None,
+        }
+    }
+
+    pub fn scale(&self) -> f64 {
+        TIME_SCALES[self.scale]
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        println!("{}", if self.paused { "Paused" } else { "Running" });
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn slower(&mut self) {
+        if self.scale > 0 {
+            self.scale -= 1;
+        }
+        println!("Time scale {}x", self.scale());
+    }
+
+    pub fn faster(&mut self) {
+        if self.scale + 1 < TIME_SCALES.len() {
+            self.scale += 1;
+        }
+        println!("Time scale {}x", self.scale());
+    }
+
+    pub fn reset_scale(&mut self) {
+        self.scale = NORMAL_SPEED;
+        println!("Time scale {}x", self.scale());
+    }
+
+    // Returns the simulation clock for a frame starting at `frame_time`.
+    pub fn advance(&mut self, frame_time: f64) -> f64 {
+        let elapsed = self.last_frame.map_or(0.0, |last| f64::max(frame_time - last, 0.0));
+        self.last_frame = Some(frame_time);
+        if !self.paused {
+            self.clock += elapsed * self.scale();
+        }
+
+        self.clock
+    }
+}
@@ -0,0 +1,346 @@
+use gl;
+use gl::types::*;
+use gl_debug;
+use gl_util;
+use meshes::FlagVertex;
+use std::mem;
+use std::os::raw;
+use std::ptr;
+use std::slice;
+use std::time::{Duration, Instant};
+
+
+// Regions in the ring the mapped strategies write to in turn, so the CPU
+// fills one while the GPU may still be drawing from the others.
+const REGIONS: usize = 3;
+
+// How long to wait on the GPU for a region before giving up on it, in
+// nanoseconds.
+const FENCE_TIMEOUT: GLuint64 = 1_000_000_000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UploadStrategy {
+    // Respecify the whole buffer each frame, letting the driver hand back
+    // fresh storage while the GPU finishes with the old.
+    Orphan,
+    // Overwrite the same storage in place.
+    SubData,
+    // Map each region of a ring unsynchronized, fencing it once drawn from.
+    MapUnsynchronized,
+    // As above, with the ring mapped once for good. Needs GL 4.4 or
+    // ARB_buffer_storage.
+    Persistent,
+}
+
+impl UploadStrategy {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            UploadStrategy::Orphan => "orphan",
+            UploadStrategy::SubData => "sub-data",
+            UploadStrategy::MapUnsynchronized => "unsynchronized map",
+            UploadStrategy::Persistent => "persistent map",
+        }
+    }
+
+    pub fn next(&self) -> UploadStrategy {
+        match *self {
+            UploadStrategy::Orphan => UploadStrategy::SubData,
+            UploadStrategy::SubData => UploadStrategy::MapUnsynchronized,
+            UploadStrategy::MapUnsynchronized => UploadStrategy::Persistent,
+            UploadStrategy::Persistent => UploadStrategy::Orphan,
+        }
+    }
+
+    fn regions(&self) -> usize {
+        match *self {
+            UploadStrategy::Orphan | UploadStrategy::SubData => 1,
+            UploadStrategy::MapUnsynchronized | UploadStrategy::Persistent => REGIONS,
+        }
+    }
+}
+
+// The attributes of a FlagVertex that change from frame to frame.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct StreamVertex {
+    position: [GLfloat; 3],
+    normal: [GLfloat; 3],
+    tangent: [GLfloat; 4],
+}
+
+fn pack(out: &mut [StreamVertex], vertices: &[FlagVertex]) {
+    for (o, v) in out.iter_mut().zip(vertices.iter()) {
+        o.position = [v.position[0], v.position[1], v.position[2]];
+        o.normal = [v.normal[0], v.normal[1], v.normal[2]];
+        o.tangent = v.tangent;
+    }
+}
+
+// Upload figures gathered since the last readout.
+struct StreamStats {
+    uploads: u32,
+    bytes: usize,
+    upload_time: Duration,
+    stalls: u32,
+}
+
+impl StreamStats {
+    fn new() -> StreamStats {
+        StreamStats {
+            uploads: 0,
+            bytes: 0,
+            upload_time: Duration::new(0, 0),
+            stalls: 0,
+        }
+    }
+}
+
+// Streams positions and normals into a buffer of their own, leaving the rest
+// of each vertex in the mesh's static buffer. The buffer is made on the
+// first upload and remade whenever the strategy changes or the mesh grows.
+pub struct VertexStream {
+    strategy: UploadStrategy,
+    buffer: GLuint,
+    // Vertices each region holds.
+    capacity: usize,
+    region: usize,
+    // Where the last upload went, for the attribute pointers.
+    offset: usize,
+    mapping: *mut StreamVertex,
+    fences: [GLsync; REGIONS],
+    staging: Vec<StreamVertex>,
+    stats: StreamStats,
+}
+
+impl VertexStream {
+    pub fn new(strategy: UploadStrategy) -> VertexStream {
+        VertexStream {
+            strategy: strategy,
+            buffer: 0,
+            capacity: 0,
+            region: 0,
+            offset: 0,
+            mapping: ptr::null_mut(),
+            fences: [ptr::null(); REGIONS],
+            staging: vec![],
+            stats: StreamStats::new(),
+        }
+    }
+
+    pub fn strategy(&self) -> UploadStrategy {
+        self.strategy
+    }
+
+    // Moves on to the next strategy, skipping persistent mapping where the
+    // context can't do it.
+    pub fn cycle_strategy(&mut self) {
+        let mut strategy = self.strategy.next();
+        if strategy == UploadStrategy::Persistent && !persistent_mapping_supported() {
+            strategy = strategy.next();
+        }
+        self.destroy();
+        self.strategy = strategy;
+        self.stats = StreamStats::new();
+    }
+
+    fn region_bytes(&self) -> usize {
+        self.capacity * mem::size_of::<StreamVertex>()
+    }
+
+    fn create(&mut self, capacity: usize) {
+        if self.strategy == UploadStrategy::Persistent && !persistent_mapping_supported() {
+            println!("Persistent mapping is not supported, mapping unsynchronized instead");
+            self.strategy = UploadStrategy::MapUnsynchronized;
+        }
+        self.capacity = capacity;
+        self.region = 0;
+        let size = (self.region_bytes() * self.strategy.regions()) as GLsizeiptr;
+
+        unsafe {
+            gl::GenBuffers(1, &mut self.buffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+            if self.strategy == UploadStrategy::Persistent {
+                let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+                gl::BufferStorage(gl::ARRAY_BUFFER, size, ptr::null(), flags);
+                self.mapping = gl::MapBufferRange(gl::ARRAY_BUFFER, 0, size, flags) as *mut StreamVertex;
+            } else {
+                gl::BufferData(gl::ARRAY_BUFFER, size, ptr::null(), gl::STREAM_DRAW);
+            }
+        }
+        // Made again whenever the strategy changes, so labelled here rather
+        // than at startup.
+        gl_debug::label(gl::BUFFER, self.buffer, "vertex stream");
+    }
+
+    pub fn destroy(&mut self) {
+        if self.buffer == 0 {
+            return;
+        }
+
+        unsafe {
+            for fence in self.fences.iter_mut() {
+                if !fence.is_null() {
+                    gl::DeleteSync(*fence);
+                    *fence = ptr::null();
+                }
+            }
+            if !self.mapping.is_null() {
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+                gl::UnmapBuffer(gl::ARRAY_BUFFER);
+                self.mapping = ptr::null_mut();
+            }
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+        self.buffer = 0;
+        self.capacity = 0;
+    }
+
+    // Blocks until the GPU is done drawing from the current region.
+    fn wait_for_region(&mut self) {
+        let fence = self.fences[self.region];
+        if fence.is_null() {
+            return;
+        }
+
+        unsafe {
+            let mut status = gl::ClientWaitSync(fence, 0, 0);
+            if status == gl::TIMEOUT_EXPIRED {
+                self.stats.stalls += 1;
+                status = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, FENCE_TIMEOUT);
+            }
+            if status == gl::WAIT_FAILED || status == gl::TIMEOUT_EXPIRED {
+                eprintln!("Gave up waiting on the GPU for a stream region");
+            }
+            gl::DeleteSync(fence);
+        }
+        self.fences[self.region] = ptr::null();
+    }
+
+    pub fn upload(&mut self, vertices: &[FlagVertex]) {
+        if vertices.is_empty() {
+            return;
+        }
+        let start = Instant::now();
+        if vertices.len() > self.capacity {
+            self.destroy();
+        }
+        if self.buffer == 0 {
+            // Leave room for the cloth to tear a little before growing.
+            self.create(vertices.len() + vertices.len() / 4);
+        }
+
+        let bytes = vertices.len() * mem::size_of::<StreamVertex>();
+        let regions = self.strategy.regions();
+        self.region = (self.region + 1) % regions;
+        self.offset = self.region * self.region_bytes();
+
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+            match self.strategy {
+                UploadStrategy::Orphan | UploadStrategy::SubData => {
+                    let zero = StreamVertex { position: [0.0; 3], normal: [0.0; 3], tangent: [0.0; 4] };
+                    self.staging.resize(vertices.len(), zero);
+                    pack(&mut self.staging, vertices);
+                    if self.strategy == UploadStrategy::Orphan {
+                        let size = self.region_bytes() as GLsizeiptr;
+                        gl::BufferData(gl::ARRAY_BUFFER, size, ptr::null(), gl::STREAM_DRAW);
+                    }
+                    gl::BufferSubData(
+                        gl::ARRAY_BUFFER, 0, bytes as GLsizeiptr, self.staging.as_ptr() as *const raw::c_void
+                    );
+                }
+                UploadStrategy::MapUnsynchronized => {
+                    self.wait_for_region();
+                    let access = gl::MAP_WRITE_BIT | gl::MAP_UNSYNCHRONIZED_BIT | gl::MAP_INVALIDATE_RANGE_BIT;
+                    let mapping = gl::MapBufferRange(
+                        gl::ARRAY_BUFFER, self.offset as GLintptr, bytes as GLsizeiptr, access
+                    ) as *mut StreamVertex;
+                    if !mapping.is_null() {
+                        pack(slice::from_raw_parts_mut(mapping, vertices.len()), vertices);
+                        gl::UnmapBuffer(gl::ARRAY_BUFFER);
+                    }
+                }
+                UploadStrategy::Persistent => {
+                    self.wait_for_region();
+                    if !self.mapping.is_null() {
+                        let region = self.mapping.offset((self.region * self.capacity) as isize);
+                        pack(slice::from_raw_parts_mut(region, vertices.len()), vertices);
+                    }
+                }
+            }
+        }
+
+        self.stats.uploads += 1;
+        self.stats.bytes += bytes;
+        self.stats.upload_time += start.elapsed();
+    }
+
+    // Points the position attribute alone at the last upload, for programs
+    // that need nothing else.
+    pub fn bind_position(&self, position: GLint) {
+        let stride = mem::size_of::<StreamVertex>() as GLint;
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+            if let Some(position) = gl_util::attrib(position) {
+                gl::VertexAttribPointer(
+                    position, 3, gl::FLOAT, gl::FALSE, stride, self.offset as *const raw::c_void
+                );
+            }
+        }
+    }
+
+    // Points the position, normal and tangent attributes at the last upload,
+    // skipping any the program doesn't have.
+    pub fn bind(&self, position: GLint, normal: GLint, tangent: GLint) {
+        let stride = mem::size_of::<StreamVertex>() as GLint;
+        self.bind_position(position);
+        unsafe {
+            if let Some(normal) = gl_util::attrib(normal) {
+                gl::VertexAttribPointer(
+                    normal, 3, gl::FLOAT, gl::FALSE, stride,
+                    (self.offset + mem::size_of::<[GLfloat; 3]>()) as *const raw::c_void
+                );
+            }
+            if let Some(tangent) = gl_util::attrib(tangent) {
+                gl::VertexAttribPointer(
+                    tangent, 4, gl::FLOAT, gl::FALSE, stride,
+                    (self.offset + mem::size_of::<[GLfloat; 6]>()) as *const raw::c_void
+                );
+            }
+        }
+    }
+
+    // Marks the last upload's region as in use until the draws issued so far
+    // are done.
+    pub fn fence(&mut self) {
+        if self.strategy.regions() == 1 || self.buffer == 0 {
+            return;
+        }
+
+        unsafe {
+            if !self.fences[self.region].is_null() {
+                gl::DeleteSync(self.fences[self.region]);
+            }
+            self.fences[self.region] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        }
+    }
+
+    // Sums up and resets the figures gathered since the last readout.
+    pub fn readout(&mut self) -> String {
+        let uploads = u32::max(self.stats.uploads, 1) as f64;
+        let time = self.stats.upload_time;
+        let milliseconds = (time.as_secs() as f64 * 1.0e3 + time.subsec_nanos() as f64 * 1.0e-6) / uploads;
+        let readout = format!(
+            "{}, {:.3} ms and {} KB per upload, {} stalls",
+            self.strategy.name(), milliseconds,
+            (self.stats.bytes as f64 / uploads / 1024.0).round(), self.stats.stalls
+        );
+        self.stats = StreamStats::new();
+
+        readout
+    }
+}
+
+fn persistent_mapping_supported() -> bool {
+    gl_util::has_feature(4, 4, "GL_ARB_buffer_storage")
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::io;
+use std::path::Path;
+
+
+fn size_mismatch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "frame size changed during recording")
+}
+
+// Streams frames into a YUV4MPEG2 file as full range 4:2:0, which players
+// and encoders such as ffmpeg and mpv read directly. The range has to be
+// given in the header, as they otherwise take the samples as limited range.
+pub struct Y4mWriter {
+    out: BufWriter<File>,
+    width: usize,
+    height: usize,
+}
+
+impl Y4mWriter {
+    pub fn create(path: &Path, width: usize, height: usize, fps: u32) -> io::Result<Y4mWriter> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+        try!(writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg XCOLORRANGE=FULL", width, height, fps));
+
+        Ok(Y4mWriter { out: out, width: width, height: height })
+    }
+
+    // Takes 8-bit RGB pixels, top row first.
+    pub fn write_frame(&mut self, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+        if width != self.width || height != self.height {
+            return Err(size_mismatch());
+        }
+
+        let (y, u, v) = rgb_to_yuv420(width, height, rgb);
+        try!(self.out.write_all(b"FRAME\n"));
+        try!(self.out.write_all(&y));
+        try!(self.out.write_all(&u));
+        try!(self.out.write_all(&v));
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// BT.601 full range conversion in 16.16 fixed point, with chroma averaged
+// over each 2x2 block.
+fn rgb_to_yuv420(width: usize, height: usize, rgb: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let clamp = |x: i32| if x < 0 { 0 } else if x > 255 { 255 } else { x as u8 };
+
+    let mut y = Vec::with_capacity(width * height);
+    for pixel in rgb.chunks(3).take(width * height) {
+        let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+        y.push(clamp((19595 * r + 38470 * g + 7471 * b + 32768) >> 16));
+    }
+
+    let chroma_width = (width + 1) / 2;
+    let chroma_height = (height + 1) / 2;
+    let mut u = Vec::with_capacity(chroma_width * chroma_height);
+    let mut v = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let (mut r, mut g, mut b, mut n) = (0, 0, 0, 0);
+            for py in (2 * cy)..usize::min(2 * cy + 2, height) {
+                for px in (2 * cx)..usize::min(2 * cx + 2, width) {
+                    let i = 3 * (py * width + px);
+                    r += rgb[i] as i32;
+                    g += rgb[i + 1] as i32;
+                    b += rgb[i + 2] as i32;
+                    n += 1;
+                }
+            }
+            let (r, g, b) = (r / n, g / n, b / n);
+            u.push(clamp(((-11059 * r - 21709 * g + 32768 * b + 32768) >> 16) + 128));
+            v.push(clamp(((32768 * r - 27439 * g - 5329 * b + 32768) >> 16) + 128));
+        }
+    }
+
+    (y, u, v)
+}
+
+const GIF_MAX_COLORS: usize = 256;
+const GIF_MAX_CODE: u16 = 4095;
+
+// A box of 15-bit colours for median cut quantization.
+struct ColorBox {
+    colors: Vec<u16>,
+    count: u32,
+}
+
+fn channel(color: u16, c: usize) -> u16 {
+    (color >> (10 - 5 * c)) & 0x1F
+}
+
+impl ColorBox {
+    fn longest_axis(&self) -> (usize, u16) {
+        let mut best = (0, 0);
+        for c in 0..3 {
+            let lo = self.colors.iter().map(|&k| channel(k, c)).min().unwrap_or(0);
+            let hi = self.colors.iter().map(|&k| channel(k, c)).max().unwrap_or(0);
+            if hi - lo > best.1 {
+                best = (c, hi - lo);
+            }
+        }
+
+        best
+    }
+}
+
+// Builds a palette of at most 256 colours for one frame by median cut over a
+// 5-bit-per-channel histogram. Returns the palette and a lookup from 15-bit
+// colour to palette index.
+fn quantize(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut histogram = vec![0 as u32; 1 << 15];
+    for pixel in rgb.chunks(3) {
+        histogram[rgb15(pixel) as usize] += 1;
+    }
+
+    let colors: Vec<u16> = (0..(1 << 15) as u16).filter(|&k| histogram[k as usize] > 0).collect();
+    let count = colors.iter().map(|&k| histogram[k as usize]).sum();
+    let mut boxes = vec![ColorBox { colors: colors, count: count }];
+
+    while boxes.len() < GIF_MAX_COLORS {
+        let candidate = boxes.iter().enumerate()
+            .filter(|&(_, b)| b.colors.len() > 1)
+            .max_by_key(|&(_, b)| b.count as u64 * b.longest_axis().1 as u64)
+            .map(|(i, _)| i);
+        let i = match candidate {
+            Some(i) => i,
+            None => break,
+        };
+
+        let mut splitting = boxes.swap_remove(i);
+        let (axis, _) = splitting.longest_axis();
+        splitting.colors.sort_by_key(|&k| channel(k, axis));
+
+        let mut seen = 0;
+        let mut median = 1;
+        for (j, &k) in splitting.colors.iter().enumerate() {
+            seen += histogram[k as usize];
+            if 2 * seen >= splitting.count {
+                median = usize::max(1, usize::min(j, splitting.colors.len() - 1));
+                break;
+            }
+        }
+
+        let upper = splitting.colors.split_off(median);
+        let upper_count = upper.iter().map(|&k| histogram[k as usize]).sum();
+        boxes.push(ColorBox { colors: upper, count: upper_count });
+        boxes.push(ColorBox { count: splitting.count - upper_count, colors: splitting.colors });
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len());
+    let mut lookup = vec![0 as u8; 1 << 15];
+    for (i, b) in boxes.iter().enumerate() {
+        let mut sum = [0 as u64; 3];
+        for &k in b.colors.iter() {
+            let weight = histogram[k as usize] as u64;
+            for c in 0..3 {
+                sum[c] += weight * ((channel(k, c) << 3 | channel(k, c) >> 2) as u64);
+            }
+            lookup[k as usize] = i as u8;
+        }
+        let total = u64::max(b.count as u64, 1);
+        palette.push([(sum[0] / total) as u8, (sum[1] / total) as u8, (sum[2] / total) as u8]);
+    }
+
+    (palette, lookup)
+}
+
+fn rgb15(pixel: &[u8]) -> u16 {
+    ((pixel[0] as u16 >> 3) << 10) | ((pixel[1] as u16 >> 3) << 5) | (pixel[2] as u16 >> 3)
+}
+
+// Packs variable-width codes least significant bit first, as GIF expects.
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.accumulator |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.accumulator as u8);
+            self.accumulator >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.accumulator as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    const MIN_CODE_SIZE: u32 = 8;
+    let clear: u16 = 1 << MIN_CODE_SIZE;
+    let end: u16 = clear + 1;
+
+    let mut out = BitWriter { bytes: vec![], accumulator: 0, bits: 0 };
+    let mut dictionary: HashMap<u32, u16> = HashMap::new();
+    let mut code_size = MIN_CODE_SIZE + 1;
+    let mut last_code = end;
+
+    out.write(clear, code_size);
+    let mut prefix = match indices.first() {
+        Some(&k) => k as u16,
+        None => {
+            out.write(end, code_size);
+            return out.finish();
+        }
+    };
+
+    for &k in &indices[1..] {
+        let key = (prefix as u32) << 8 | k as u32;
+        if let Some(&code) = dictionary.get(&key) {
+            prefix = code;
+            continue;
+        }
+
+        out.write(prefix, code_size);
+        last_code += 1;
+        dictionary.insert(key, last_code);
+        if last_code >= (1 << code_size) {
+            code_size += 1;
+        }
+        if last_code == GIF_MAX_CODE {
+            out.write(clear, code_size);
+            dictionary.clear();
+            code_size = MIN_CODE_SIZE + 1;
+            last_code = end;
+        }
+        prefix = k as u16;
+    }
+
+    out.write(prefix, code_size);
+    // The decoder adds an entry on reading the last code, as it would for any
+    // other, so the clear code after it can need the next width up.
+    if last_code + 1 >= (1 << code_size) {
+        code_size += 1;
+    }
+    out.write(clear, code_size);
+    out.write(end, MIN_CODE_SIZE + 1);
+
+    out.finish()
+}
+
+// Streams frames into an animated GIF, looping forever, with a palette
+// quantized separately for each frame.
+pub struct GifWriter {
+    out: BufWriter<File>,
+    width: usize,
+    height: usize,
+    fps: u32,
+    frame: u32,
+}
+
+impl GifWriter {
+    pub fn create(path: &Path, width: usize, height: usize, fps: u32) -> io::Result<GifWriter> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+        try!(out.write_all(b"GIF89a"));
+        try!(out.write_all(&[
+            width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8,
+            0x70, 0, 0
+        ]));
+        // NETSCAPE2.0 application extension, looping forever.
+        try!(out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00"));
+
+        Ok(GifWriter { out: out, width: width, height: height, fps: fps, frame: 0 })
+    }
+
+    // Delays are in hundredths of a second, so they are spread to keep the
+    // average rate right.
+    fn delay(&self) -> u16 {
+        let at = |frame: u32| (frame as u64 * 100 + self.fps as u64 / 2) / self.fps as u64;
+        (at(self.frame + 1) - at(self.frame)) as u16
+    }
+
+    // Takes 8-bit RGB pixels, top row first.
+    pub fn write_frame(&mut self, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+        if width != self.width || height != self.height {
+            return Err(size_mismatch());
+        }
+
+        let (palette, lookup) = quantize(rgb);
+        let indices: Vec<u8> = rgb.chunks(3).map(|pixel| lookup[rgb15(pixel) as usize]).collect();
+
+        let delay = self.delay();
+        try!(self.out.write_all(&[0x21, 0xF9, 0x04, 0x00, delay as u8, (delay >> 8) as u8, 0x00, 0x00]));
+
+        try!(self.out.write_all(&[
+            0x2C, 0, 0, 0, 0,
+            width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8,
+            0x87
+        ]));
+        for i in 0..GIF_MAX_COLORS {
+            let color = palette.get(i).cloned().unwrap_or([0, 0, 0]);
+            try!(self.out.write_all(&color));
+        }
+
+        try!(self.out.write_all(&[8]));
+        for block in lzw_encode(&indices).chunks(255) {
+            try!(self.out.write_all(&[block.len() as u8]));
+            try!(self.out.write_all(block));
+        }
+        try!(self.out.write_all(&[0]));
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        try!(self.out.write_all(&[0x3B]));
+        self.out.flush()
+    }
+}